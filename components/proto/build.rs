@@ -0,0 +1,5 @@
+use protobuf_build::Builder;
+
+fn main() {
+    Builder::new().search_dir_for_protos("proto").generate()
+}
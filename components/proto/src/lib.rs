@@ -0,0 +1,9 @@
+//! The RPCs of a fastjob server that are not part of the `FastJob` service of
+//! `fastjob-proto`, generated from the definitions under `proto/`.
+#![allow(clippy::all)]
+
+pub use protos::*;
+
+mod protos {
+    include!(concat!(env!("OUT_DIR"), "/protos/mod.rs"));
+}
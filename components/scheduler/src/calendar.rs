@@ -0,0 +1,235 @@
+//! Business-day calendars consulted by `CronTrigger`, see `CalendarPolicy` for how the runs
+//! on excluded days are adjusted.
+//!
+//! A day is allowed when it is one of the working days of the week and not a holiday.
+//! Holidays can be imported from iCalendar files, every all-day or timed `VEVENT` excludes
+//! the days it covers, recurring events are not expanded.
+use chrono::{Datelike, NaiveDate};
+use fastjob_components_storage::model::calendar_info::CalendarInfo;
+use std::collections::BTreeSet;
+
+use crate::error::{self, Result};
+
+/// How far an allowed day is looked for, a calendar excluding longer stretches is invalid.
+pub const MAX_EXCLUDED_DAYS: i64 = 366;
+
+const DEFAULT_WORKING_DAYS: &str = "1,2,3,4,5";
+
+#[derive(Clone, Debug)]
+pub struct Calendar {
+    /// Indexed by the days from Monday.
+    working_days: [bool; 7],
+    holidays: BTreeSet<NaiveDate>,
+}
+
+impl Calendar {
+    pub fn from_info(info: &CalendarInfo) -> Result<Self> {
+        let mut working_days = [false; 7];
+        for day in info
+            .working_days
+            .as_deref()
+            .unwrap_or(DEFAULT_WORKING_DAYS)
+            .split(',')
+            .map(str::trim)
+            .filter(|d| !d.is_empty())
+        {
+            match day.parse::<usize>() {
+                Ok(d) if (1..=7).contains(&d) => working_days[d - 1] = true,
+                _ => {
+                    return error::InvalidCalendar {
+                        reason: format!("working day {} isn't between 1 and 7", day),
+                    }
+                    .fail()
+                }
+            }
+        }
+        if !working_days.iter().any(|allowed| *allowed) {
+            return error::InvalidCalendar {
+                reason: "no working day",
+            }
+            .fail();
+        }
+        let holidays = parse_holidays(info.holidays.as_deref().unwrap_or_default())?;
+        Ok(Self {
+            working_days,
+            holidays,
+        })
+    }
+
+    pub fn is_allowed(&self, date: NaiveDate) -> bool {
+        self.working_days[date.weekday().num_days_from_monday() as usize]
+            && !self.holidays.contains(&date)
+    }
+
+    /// The first allowed day after `date`.
+    pub fn next_allowed(&self, date: NaiveDate) -> Option<NaiveDate> {
+        (1..=MAX_EXCLUDED_DAYS)
+            .filter_map(|days| date.checked_add_signed(chrono::Duration::days(days)))
+            .find(|d| self.is_allowed(*d))
+    }
+
+    /// The last allowed day of the month of `date`.
+    pub fn last_allowed_of_month(&self, date: NaiveDate) -> Option<NaiveDate> {
+        let mut day = last_of_month(date);
+        while day.month() == date.month() {
+            if self.is_allowed(day) {
+                return Some(day);
+            }
+            day = day.pred();
+        }
+        None
+    }
+}
+
+pub fn last_of_month(date: NaiveDate) -> NaiveDate {
+    let (year, month) = if date.month() == 12 {
+        (date.year() + 1, 1)
+    } else {
+        (date.year(), date.month() + 1)
+    };
+    NaiveDate::from_ymd(year, month, 1).pred()
+}
+
+/// The dates of comma-separated `yyyy-MM-dd` holidays.
+pub fn parse_holidays(holidays: &str) -> Result<BTreeSet<NaiveDate>> {
+    holidays
+        .split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(|d| {
+            NaiveDate::parse_from_str(d, "%Y-%m-%d").map_err(|_| {
+                error::InvalidCalendar {
+                    reason: format!("holiday {} isn't a yyyy-MM-dd date", d),
+                }
+                .build()
+            })
+        })
+        .collect()
+}
+
+/// The holidays as stored in `CalendarInfo`.
+pub fn format_holidays(holidays: &BTreeSet<NaiveDate>) -> String {
+    holidays
+        .iter()
+        .map(|d| d.format("%Y-%m-%d").to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The days covered by the events of an iCalendar file.
+pub fn parse_ics(ics: &str) -> Result<BTreeSet<NaiveDate>> {
+    // continuation lines start with a space or a tab.
+    let mut lines: Vec<String> = vec![];
+    for line in ics.lines() {
+        match (line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.trim_end().to_string()),
+        }
+    }
+
+    let mut days = BTreeSet::new();
+    let mut event: Option<(Option<NaiveDate>, Option<(NaiveDate, bool)>)> = None;
+    for line in lines {
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name.to_ascii_uppercase(), value.trim()),
+            None => continue,
+        };
+        // parameters such as `;VALUE=DATE` or `;TZID=...` follow the property name.
+        let property = name.split(';').next().unwrap_or_default();
+        match (property, event.as_mut()) {
+            ("BEGIN", _) if value.eq_ignore_ascii_case("VEVENT") => event = Some((None, None)),
+            ("DTSTART", Some(e)) => e.0 = Some(parse_ics_date(value)?),
+            ("DTEND", Some(e)) => e.1 = Some((parse_ics_date(value)?, value.len() == 8)),
+            ("END", Some(_)) if value.eq_ignore_ascii_case("VEVENT") => {
+                let (start, end) = event.take().unwrap_or_default();
+                let start = start.ok_or_else(|| invalid_ics("an event without DTSTART"))?;
+                days.insert(start);
+                if let Some((end, all_day)) = end {
+                    // the end of an all-day event is the day after it.
+                    let last = if all_day { end.pred() } else { end };
+                    let mut day = start.succ();
+                    while day <= last {
+                        days.insert(day);
+                        day = day.succ();
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(days)
+}
+
+/// The date of a `DATE` or `DATE-TIME` value, e.g. `20211001` or `20211001T090000Z`.
+fn parse_ics_date(value: &str) -> Result<NaiveDate> {
+    value
+        .get(..8)
+        .and_then(|d| NaiveDate::parse_from_str(d, "%Y%m%d").ok())
+        .ok_or_else(|| invalid_ics(&format!("invalid date {}", value)))
+}
+
+fn invalid_ics(reason: &str) -> error::SchedError {
+    error::InvalidCalendar {
+        reason: format!("iCalendar {}", reason),
+    }
+    .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd(y, m, d)
+    }
+
+    #[test]
+    fn t_calendar() {
+        let calendar = Calendar::from_info(&CalendarInfo {
+            holidays: Some("2021-12-31, 2021-12-24".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(calendar.is_allowed(date(2021, 12, 23)));
+        assert!(!calendar.is_allowed(date(2021, 12, 24)));
+        assert!(!calendar.is_allowed(date(2021, 12, 25)));
+        assert_eq!(calendar.next_allowed(date(2021, 12, 23)), Some(date(2021, 12, 27)));
+        assert_eq!(calendar.last_allowed_of_month(date(2021, 12, 1)), Some(date(2021, 12, 30)));
+
+        let invalid = |working_days: &str, holidays: &str| {
+            Calendar::from_info(&CalendarInfo {
+                working_days: Some(working_days.to_string()),
+                holidays: Some(holidays.to_string()),
+                ..Default::default()
+            })
+            .unwrap_err()
+            .to_string()
+        };
+        assert_eq!(invalid("1,8", ""), "Invalid calendar: working day 8 isn't between 1 and 7.");
+        assert_eq!(invalid("", ""), "Invalid calendar: no working day.");
+        assert_eq!(
+            invalid("1", "2021/12/24"),
+            "Invalid calendar: holiday 2021/12/24 isn't a yyyy-MM-dd date."
+        );
+    }
+
+    #[test]
+    fn t_parse_ics() {
+        let ics = "BEGIN:VCALENDAR\r\n\
+                   BEGIN:VEVENT\r\n\
+                   SUMMARY:National\r\n  Day\r\n\
+                   DTSTART;VALUE=DATE:20211001\r\n\
+                   DTEND;VALUE=DATE:20211004\r\n\
+                   END:VEVENT\r\n\
+                   BEGIN:VEVENT\r\n\
+                   DTSTART:20211224T090000Z\r\n\
+                   END:VEVENT\r\n\
+                   END:VCALENDAR\r\n";
+        let days = parse_ics(ics).unwrap();
+        assert_eq!(
+            format_holidays(&days),
+            "2021-10-01,2021-10-02,2021-10-03,2021-12-24"
+        );
+        assert!(parse_ics("BEGIN:VEVENT\nDTSTART:tomorrow\nEND:VEVENT").is_err());
+    }
+}
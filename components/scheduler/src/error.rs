@@ -1,3 +1,5 @@
+use fastjob_components_storage::BatisError;
+use fastjob_components_utils::id_generator::IdGeneratorError;
 use snafu::{ResultExt, Snafu};
 
 pub type Result<T, E = SchedError> = std::result::Result<T, E>;
@@ -22,6 +24,51 @@ pub enum SchedError {
     #[snafu(display("Scheduler {} is too busy.", sched_id))]
     SchedTooBusy { sched_id: u64 },
 
+    #[snafu(display("Scheduler storage encounter error: {}.", source))]
+    SchedStorageError { source: BatisError },
+
+    #[snafu(display("Instance {} has no task {}.", instance_id, task_id))]
+    TaskNotFound { instance_id: u64, task_id: String },
+
+    #[snafu(display("Invalid workflow DAG: {}.", reason))]
+    InvalidDag { reason: String },
+
+    #[snafu(display("Encode workflow DAG encounter error: {}.", source))]
+    DagEncodeFailed { source: serde_json::Error },
+
+    #[snafu(display("Workflow instance {} doesn't exist.", wf_instance_id))]
+    WorkflowInstanceNotFound { wf_instance_id: u64 },
+
+    #[snafu(display("Workflow instance {}: {}.", wf_instance_id, reason))]
+    InvalidWorkflowOperation { wf_instance_id: u64, reason: String },
+
+    #[snafu(display("Workflow instance {} keeps being updated concurrently.", wf_instance_id))]
+    WorkflowConflict { wf_instance_id: u64 },
+
+    #[snafu(display("Invalid calendar: {}.", reason))]
+    InvalidCalendar { reason: String },
+
+    #[snafu(display("Calendar {} doesn't exist.", calendar_id))]
+    CalendarNotFound { calendar_id: u64 },
+
+    #[snafu(display("Invalid time zone {}.", time_zone))]
+    InvalidTimeZone { time_zone: String },
+
+    #[snafu(display("Invalid cron expression {}: {}.", expression, reason))]
+    InvalidCronExpression { expression: String, reason: String },
+
+    #[snafu(display("Invalid fire time {}: {}.", expression, reason))]
+    InvalidFireTime { expression: String, reason: String },
+
+    #[snafu(display("Job {} isn't an API job, only API jobs run on demand.", job_id))]
+    NotApiJob { job_id: u64 },
+
+    #[snafu(display("Job {} isn't running.", job_id))]
+    JobNotRunning { job_id: u64 },
+
+    #[snafu(display("Generate id encounter error: {}.", source))]
+    IdGeneratorFailed { source: IdGeneratorError },
+
     #[snafu(display("Constructor task id: {} encounter error: {}", task_id, source))]
     ConstructorTaskFailed {
         source: delay_timer::error::TaskError,
@@ -0,0 +1,164 @@
+//! Plan the instances of FixRate and FixDelay jobs, their `time_expression` is the
+//! interval in milliseconds.
+//!
+//! FixRate fires every interval from the planned start kept in `next_trigger_time`, ticks
+//! missed while no server scheduled the job collapse into a single late one. FixDelay fires
+//! an interval after the previous instance finished and never while one is unfinished.
+use fastjob_components_utils::time::Clock;
+use std::sync::Arc;
+
+use crate::misfire::Fire;
+
+/// Shorter intervals would create too many instances per schedule round.
+pub const MIN_FREQUENT_INTERVAL_MS: i64 = 1000;
+
+/// The interval of a frequent job, `None` if it isn't a number of milliseconds of at least
+/// `MIN_FREQUENT_INTERVAL_MS`.
+pub fn parse_interval(time_expression: Option<&str>) -> Option<i64> {
+    time_expression
+        .and_then(|e| e.trim().parse::<i64>().ok())
+        .filter(|interval| *interval >= MIN_FREQUENT_INTERVAL_MS)
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FixRatePlan {
+    /// The trigger times to create instances for, in order.
+    pub ticks: Vec<i64>,
+    /// The first tick of the next round, stored as `next_trigger_time`.
+    pub next_trigger_time: i64,
+}
+
+#[derive(Clone)]
+pub struct FrequentPlanner {
+    clock: Arc<dyn Clock>,
+    /// How far ahead of now a round plans, one schedule interval.
+    lookahead: i64,
+}
+
+impl FrequentPlanner {
+    pub fn new(clock: Arc<dyn Clock>, lookahead: i64) -> Self {
+        Self { clock, lookahead }
+    }
+
+    pub fn now(&self) -> i64 {
+        self.clock.now_millis()
+    }
+
+    /// The ticks of a FixRate job before the next round, starting at `planned` or now for
+    /// a job that was never planned.
+    pub fn fix_rate(&self, planned: Option<i64>, interval: i64) -> FixRatePlan {
+        let now = self.now();
+        let mut tick = planned.unwrap_or(now);
+        if tick < now {
+            // only the latest missed tick fires.
+            tick += (now - tick) / interval * interval;
+        }
+        let mut ticks = vec![];
+        while tick < now + self.lookahead {
+            ticks.push(tick);
+            tick += interval;
+        }
+        FixRatePlan {
+            ticks,
+            next_trigger_time: tick,
+        }
+    }
+
+    /// The next instance of a FixDelay job if it falls before the next round, `None` while
+    /// an instance is unfinished. Its slot is an interval after the previous instance
+    /// finished, or `planned` for a job none finished yet.
+    pub fn fix_delay(
+        &self,
+        last_finished_time: Option<i64>,
+        planned: Option<i64>,
+        interval: i64,
+        running: bool,
+    ) -> Option<Fire> {
+        if running {
+            return None;
+        }
+        let now = self.now();
+        let slot = last_finished_time
+            .map(|t| t + interval)
+            .or(planned)
+            .unwrap_or(now);
+        let fire = Fire {
+            expected_trigger_time: slot,
+            trigger_time: slot.max(now),
+            action: None,
+        };
+        if fire.trigger_time < now + self.lookahead {
+            Some(fire)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fastjob_components_utils::time::{Duration, ManualClock};
+
+    fn planner(clock: &ManualClock) -> FrequentPlanner {
+        FrequentPlanner::new(Arc::new(clock.clone()), 10_000)
+    }
+
+    #[test]
+    fn t_parse_interval() {
+        assert_eq!(parse_interval(Some(" 5000 ")), Some(5000));
+        assert_eq!(parse_interval(Some("10")), None);
+        assert_eq!(parse_interval(Some("0 * * * * *")), None);
+        assert_eq!(parse_interval(None), None);
+    }
+
+    #[test]
+    fn t_fix_rate() {
+        let clock = ManualClock::new(100_000);
+        let planner = planner(&clock);
+        let plan = planner.fix_rate(None, 4000);
+        assert_eq!(plan.ticks, vec![100_000, 104_000, 108_000]);
+        assert_eq!(plan.next_trigger_time, 112_000);
+
+        // the next round continues from the planned start, not from now.
+        clock.advance(Duration::from_millis(10_500));
+        let plan = planner.fix_rate(Some(plan.next_trigger_time), 4000);
+        assert_eq!(plan.ticks, vec![112_000, 116_000, 120_000]);
+
+        // a minute without rounds fires the latest missed tick once.
+        clock.advance(Duration::from_secs(60));
+        let plan = planner.fix_rate(Some(plan.next_trigger_time), 4000);
+        assert_eq!(plan.ticks, vec![168_000, 172_000, 176_000, 180_000]);
+        assert_eq!(plan.next_trigger_time, 184_000);
+    }
+
+    #[test]
+    fn t_fix_delay() {
+        let clock = ManualClock::new(100_000);
+        let planner = planner(&clock);
+        let fire = |slot: i64, trigger_time: i64| Fire {
+            expected_trigger_time: slot,
+            trigger_time,
+            action: None,
+        };
+        assert_eq!(planner.fix_delay(None, None, 4000, false), Some(fire(100_000, 100_000)));
+        // a job none finished yet starts at its planned time.
+        assert_eq!(
+            planner.fix_delay(None, Some(90_000), 4000, false),
+            Some(fire(90_000, 100_000))
+        );
+        assert_eq!(planner.fix_delay(Some(99_000), None, 4000, true), None);
+        assert_eq!(
+            planner.fix_delay(Some(99_000), Some(90_000), 4000, false),
+            Some(fire(103_000, 103_000))
+        );
+        assert_eq!(planner.fix_delay(Some(99_000), None, 60_000, false), None);
+
+        // the slot stays the same however late the round is.
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(
+            planner.fix_delay(Some(99_000), None, 60_000, false),
+            Some(fire(159_000, 160_000))
+        );
+    }
+}
@@ -0,0 +1,48 @@
+//! Deterministic offsets spreading the cron jobs that share a fire time.
+//!
+//! A job with a `jitter` window fires every run at the same offset within it, picked by
+//! hashing the job id, a job without one uses the `cron_spread` window of its app. The
+//! offset stays short of the next fire time so a run never leaves its cron slot.
+
+/// The offset in milliseconds of the runs of `job_id` within `window`, shorter than `slot`,
+/// the time until the following run. 0 when the window is empty.
+pub fn offset(job_id: u64, window: u64, slot: Option<i64>) -> i64 {
+    let bound = match slot {
+        Some(slot) => window.min(slot.max(0) as u64),
+        None => window,
+    };
+    if bound == 0 {
+        return 0;
+    }
+    (mix(job_id) % bound) as i64
+}
+
+/// The splitmix64 finalizer, unlike the std hasher it is stable across builds, so every
+/// server picks the same offset.
+fn mix(id: u64) -> u64 {
+    let mut z = id.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn t_offset() {
+        assert_eq!(offset(42, 60_000, None), offset(42, 60_000, None));
+        assert_eq!(offset(42, 0, None), 0);
+        assert_eq!(offset(42, 60_000, Some(0)), 0);
+
+        // an hourly job sharing `0 0 * * * *` with 500 others.
+        let offsets: Vec<i64> = (1..=500).map(|id| offset(id, 300_000, Some(3_600_000))).collect();
+        assert!(offsets.iter().all(|o| (0..300_000).contains(o)));
+        assert!(offsets.iter().collect::<HashSet<_>>().len() > 490);
+
+        // a minutely job never slips into the next minute.
+        assert!((1..=500).all(|id| offset(id, 300_000, Some(60_000)) < 60_000));
+    }
+}
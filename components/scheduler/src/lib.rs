@@ -5,41 +5,60 @@
 #[macro_use]
 extern crate fastjob_components_log;
 
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
+use chrono::{Datelike, Local, TimeZone, Timelike};
 use cron::Schedule;
 use delay_timer::prelude::*;
-use snafu::ResultExt;
+use snafu::{OptionExt, ResultExt};
 
 use error::Result;
 use fastjob_components_storage::model::{
     app_info::AppInfo,
-    instance_info::InstanceInfo,
-    job_info::{JobInfo, JobStatus, JobTimeExpressionType, JobType},
+    instance_info::{InstanceInfo, InstanceStatus},
+    job_info::{
+        CalendarPolicy, JobInfo, JobStatus, JobTimeExpressionType, JobType, MisfirePolicy,
+    },
+    workflow_info::WorkflowInfo,
 };
 use fastjob_components_storage::Storage;
 use fastjob_components_utils::component::Component;
 
+use crate::calendar::Calendar;
 use crate::dispatch::Dispatch;
+use crate::frequent::{parse_interval, FrequentPlanner, MIN_FREQUENT_INTERVAL_MS};
+use crate::misfire::{Fire, DEFAULT_MAX_CATCH_UP, MISFIRE_THRESHOLD};
+use crate::trigger::CronTrigger;
+use crate::workflow::WorkflowEngine;
 use fastjob_components_utils::event::Event;
+use fastjob_components_utils::time::{Clock, SystemClock};
 use std::fmt::{Debug, Formatter};
 use tokio::sync::mpsc::Sender;
 
+pub mod calendar;
 mod container;
 pub mod error;
-mod mapreduce;
+pub mod frequent;
+pub mod jitter;
+pub mod mapreduce;
+pub mod misfire;
+pub mod one_shot;
 mod rt;
-mod workflow;
+pub mod trigger;
+pub mod workflow;
 
 pub const SCHEDULE_INTERVAL: Duration = Duration::from_millis(10000);
 
 pub struct Scheduler<S: Storage> {
     delay_timer: DelayTimer,
-    storage: S,
+    storage: Arc<S>,
+    workflow: WorkflowEngine<S>,
+    frequent: FrequentPlanner,
     task_sender: Sender<(JobInfo, u64)>,
 }
 
@@ -52,157 +71,515 @@ impl<S: Storage> Debug for Scheduler<S> {
 }
 
 impl<S: Storage> Scheduler<S> {
-    pub fn new(storage: S, task_sender: Sender<(JobInfo, u64)>) -> Self {
+    pub fn new(storage: Arc<S>, task_sender: Sender<(JobInfo, u64)>) -> Self {
         Self {
             delay_timer: DelayTimerBuilder::default().enable_status_report().build(),
+            workflow: WorkflowEngine::new(storage.clone()),
+            frequent: FrequentPlanner::new(
+                Arc::new(SystemClock),
+                SCHEDULE_INTERVAL.as_millis() as i64,
+            ),
             storage,
             task_sender,
         }
     }
 
-    /// Schedule tasks of type CRON expressions.
+    /// Plan the frequent jobs with `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.frequent = FrequentPlanner::new(clock, SCHEDULE_INTERVAL.as_millis() as i64);
+        self
+    }
+
+    /// Schedule tasks of type CRON expressions, see `misfire` for the trigger times missed
+    /// while no server was scheduling them.
     pub fn schedule_cron_job(&self, ids: &[u64]) -> Result<()> {
         if ids.is_empty() {
             return Ok(());
         }
-        let now = chrono::Local::now().timestamp_millis();
-        let mut chunks = ids.chunks(10);
-        for chunk in chunks {
-            rt::build().block_on(async move {
-                let job_infos = self
+        let now = self.frequent.now();
+        for chunk in ids.chunks(10) {
+            let job_infos = self
+                .storage
+                .find_cron_jobs(chunk, now + SCHEDULE_INTERVAL.as_millis() as i64 * 2)
+                .context(error::SchedStorageError)?;
+            if job_infos.is_empty() {
+                continue;
+            }
+            let mut spreads = HashMap::with_capacity(chunk.len());
+            for app_id in chunk {
+                let app_info = self
                     .storage
-                    .find_cron_jobs(chunk, now + SCHEDULE_INTERVAL.as_millis() * 2)
-                    .context("")?;
-                if job_infos.is_empty() {
-                    return;
-                }
-                info!(
-                    "[Cron Scheduler] all cron jobs ready to scheduled: {}",
-                    job_infos
-                );
-
-                // 1. write record to instance table.
-                let instances: Vec<(u64, u64)> = vec![];
-                let mut job_instance_map =
-                    std::collections::HashMap::with_capacity(job_infos.len());
-                for job in job_infos {
-                    let instance_info = InstanceInfo::create(
-                        job.get_id(),
-                        job.get_app_id(),
-                        job.get_job_params(),
-                        None,
-                        None,
-                        job.get_next_trigger_time(),
-                    );
-                    job_instance_map.insert(job.id.unwrap(), instance_info.id.unwrap());
+                    .find_app_info_by_id(*app_id)
+                    .context(error::SchedStorageError)?;
+                if let Some(spread) = app_info.and_then(|app| app.cron_spread) {
+                    spreads.insert(*app_id, spread);
                 }
-                self.storage.save_batch(instances.as_slice());
-
-                // 2. push to timing wheel, then waiting be triggered.
-                for job in job_infos {
-                    let instance_id = job_instance_map.get(&job.id.unwrap());
-                    let target_trigger_time = job.get_next_trigger_time().unwrap();
-
-                    // let delay = if target_trigger_time < now {
-                    //     warn!("[Job-{}] schedule delay, expect: {}, current: {}", job.id.unwrap(), target_trigger_time, chrono::Local::now().timestamp_millis());
-                    //     0
-                    // } else {
-                    //     target_trigger_time - now
-                    // };
-
-                    // push to timing wheel, consider use tokio library?.
-                    if instance_id.is_none() {
-                        return Ok(());
-                    }
-                    if let Some(task) = self.build_task(job.clone(), instance_id.unwrap().clone()) {
-                        self.delay_timer.add_task(task);
-                    }
-                    // 3. calculate job the next trigger time.(ignore repeat execute in 5s, i.e.
-                    // the minimum continuous execution interval in cron mode is SCHEDULE_INTERVAL ms).
-                    self.refresh_job(job.clone())?;
+            }
+            for job in job_infos {
+                let job_id = job.id.unwrap_or_default();
+                // the job's own jitter wins over the spread of its app.
+                let window = job
+                    .jitter
+                    .filter(|jitter| *jitter > 0)
+                    .or_else(|| spreads.get(&job.app_id.unwrap_or_default()).copied())
+                    .unwrap_or_default();
+                if let Err(e) = self.schedule_cron(job, window, now) {
+                    error!("[Cron Scheduler] schedule job {} failed: {}", job_id, e);
                 }
-                Ok(())
+            }
+        }
+        Ok(())
+    }
+
+    fn schedule_cron(&self, mut job: JobInfo, window: u64, now: i64) -> Result<()> {
+        let job_id = job.id.unwrap_or_default();
+        let trigger = match self.cron_trigger(&job) {
+            Ok(trigger) => trigger,
+            Err(e @ error::SchedError::SchedStorageError { .. }) => return Err(e),
+            Err(e) => {
+                warn!("[Cron Scheduler] disable job {}: {}", job_id, e);
+                job.status = Some(JobStatus::DISABLED.into());
+                return self
+                    .storage
+                    .update(&mut [job])
+                    .context(error::SchedStorageError);
+            }
+        };
+        let policy = job
+            .misfire_policy
+            .and_then(|p| MisfirePolicy::try_from(p).ok())
+            .unwrap_or_default();
+        let max_catch_up = job
+            .max_catch_up
+            .map_or(DEFAULT_MAX_CATCH_UP, |n| n as usize);
+        let plan = misfire::plan_cron(
+            &trigger,
+            job.next_trigger_time.unwrap_or(now),
+            now,
+            policy,
+            max_catch_up,
+        );
+        if plan.fires.iter().any(|fire| fire.action.is_some()) || plan.dropped > 0 {
+            warn!(
+                "[Cron Scheduler] job {} missed its trigger time {:?}, {:?} fires {} and drops {} runs.",
+                job_id,
+                job.next_trigger_time,
+                policy,
+                plan.fires.len(),
+                plan.dropped
+            );
+        }
+        let fires: Vec<Fire> = plan
+            .fires
+            .into_iter()
+            .map(|mut fire| {
+                let slot = trigger
+                    .next_after(fire.expected_trigger_time)
+                    .map(|next| next - fire.expected_trigger_time);
+                let offset = jitter::offset(job_id, window, slot);
+                fire.expected_trigger_time += offset;
+                fire.trigger_time += offset;
+                fire
             })
+            .collect();
+        let previous_trigger_time = job.next_trigger_time;
+        match plan.next_trigger_time {
+            Some(next_trigger_time) => job.next_trigger_time = Some(next_trigger_time),
+            None => job.status = Some(JobStatus::DISABLED.into()),
+        }
+        self.trigger_round(previous_trigger_time, &job, fires)
+    }
+
+    /// Validate a job before it is saved and resolve when it first fires, a cron job whose
+    /// expression is invalid in its time zone and calendar or never fires is rejected, as is
+    /// a one-shot job whose fire time is invalid or past. The delay of a one-shot job is
+    /// resolved to an absolute time here, see `one_shot`.
+    pub fn prepare_job(&self, job: &mut JobInfo) -> Result<()> {
+        let now = self.frequent.now();
+        match JobTimeExpressionType::try_from(job.time_expression_type.unwrap_or_default()) {
+            Ok(JobTimeExpressionType::CRON) => {
+                job.next_trigger_time = Some(self.cron_trigger(job)?.first_after(now)?);
+            }
+            Ok(JobTimeExpressionType::OneShot) => {
+                job.next_trigger_time = Some(one_shot::fire_time(
+                    job.time_expression.as_deref().unwrap_or_default(),
+                    job.time_zone.as_deref(),
+                    now,
+                )?);
+            }
+            _ => {}
         }
         Ok(())
     }
 
-    /// Schedule second-level task.
+    /// The trigger of a cron job in its time zone, adjusted by its calendar.
+    pub fn cron_trigger(&self, job: &JobInfo) -> Result<CronTrigger> {
+        let trigger = CronTrigger::parse(
+            job.time_expression.as_deref().unwrap_or_default(),
+            job.time_zone.as_deref(),
+        )?;
+        let calendar_id = match job.calendar_id {
+            Some(calendar_id) => calendar_id,
+            None => return Ok(trigger),
+        };
+        let calendar = self
+            .storage
+            .find_calendar(calendar_id)
+            .context(error::SchedStorageError)?
+            .context(error::CalendarNotFound { calendar_id })?;
+        let policy = job
+            .calendar_policy
+            .and_then(|p| CalendarPolicy::try_from(p).ok())
+            .unwrap_or_default();
+        Ok(trigger.with_calendar(Calendar::from_info(&calendar)?, policy))
+    }
+
+    /// Schedule second-level task, see `frequent` for the FixRate and FixDelay semantics.
     pub fn schedule_frequent_job(&self, ids: &[u64]) -> Result<()> {
         if ids.is_empty() {
             return Ok(());
         }
-        let mut chunks = ids.chunks(10);
-        for chunk in chunks {
-            rt::build().block_on(async move {
-                let mut job_infos = self.storage.find_frequent_jobs(chunk).context("")?;
-                if job_infos.is_empty() {
-                    return;
+        for chunk in ids.chunks(10) {
+            let job_infos = self
+                .storage
+                .find_frequent_jobs(chunk)
+                .context(error::SchedStorageError)?;
+            if job_infos.is_empty() {
+                continue;
+            }
+            let job_ids: Vec<u64> = job_infos.iter().filter_map(|job| job.id).collect();
+            let running = self
+                .storage
+                .find_frequent_instance_by_job_id(&job_ids)
+                .context(error::SchedStorageError)?;
+            for job in job_infos {
+                let job_id = job.id.unwrap_or_default();
+                if let Err(e) = self.schedule_frequent(job, running.contains(&job_id)) {
+                    error!("[Frequent Scheduler] schedule job {} failed: {}", job_id, e);
                 }
+            }
+        }
+        Ok(())
+    }
 
-                let job_ids = self
+    fn schedule_frequent(&self, mut job: JobInfo, running: bool) -> Result<()> {
+        let job_id = job.id.unwrap_or_default();
+        let interval = match parse_interval(job.time_expression.as_deref()) {
+            Some(interval) => interval,
+            None => {
+                warn!(
+                    "[Frequent Scheduler] job {} has an invalid interval {:?}, at least {} ms.",
+                    job_id, job.time_expression, MIN_FREQUENT_INTERVAL_MS
+                );
+                return Ok(());
+            }
+        };
+        let previous_trigger_time = job.next_trigger_time;
+        // a job never planned starts when it was created, the same on every server.
+        let planned = previous_trigger_time.or(job.gmt_create.map(|t| t as i64));
+        match JobTimeExpressionType::try_from(job.time_expression_type.unwrap_or_default()) {
+            Ok(JobTimeExpressionType::FixRate) => {
+                let plan = self.frequent.fix_rate(planned, interval);
+                job.next_trigger_time = Some(plan.next_trigger_time);
+                let fires = plan.ticks.into_iter().map(Fire::on_time).collect();
+                self.trigger_round(previous_trigger_time, &job, fires)?;
+            }
+            Ok(JobTimeExpressionType::FixDelay) => {
+                let last_finished_time = self
                     .storage
-                    .find_frequent_instance_by_job_id(chunk)
-                    .context("")?;
-
-                job_infos.retain(|job| !job_ids.contains(&job.id.unwrap()));
+                    .find_last_finished_time(job_id)
+                    .context(error::SchedStorageError)?;
+                if let Some(fire) =
+                    self.frequent.fix_delay(last_finished_time, planned, interval, running)
+                {
+                    job.next_trigger_time = Some(fire.expected_trigger_time);
+                    self.trigger_round(previous_trigger_time, &job, vec![fire])?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
 
-                if job_infos.is_empty() {
-                    return Ok(());
+    /// Schedule the one-shot jobs due before the next round, see `one_shot`.
+    pub fn schedule_one_shot_job(&self, ids: &[u64]) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let now = self.frequent.now();
+        for chunk in ids.chunks(10) {
+            let job_infos = self
+                .storage
+                .find_one_shot_jobs(chunk, now + SCHEDULE_INTERVAL.as_millis() as i64)
+                .context(error::SchedStorageError)?;
+            for job in job_infos {
+                let job_id = job.id.unwrap_or_default();
+                if let Err(e) = self.schedule_one_shot(job, now) {
+                    error!("[OneShot Scheduler] schedule job {} failed: {}", job_id, e);
                 }
+            }
+        }
+        Ok(())
+    }
 
-                info!(
-                    "[Frequent Scheduler] all frequent jobs ready to scheduled: {}",
-                    job_infos
+    fn schedule_one_shot(&self, mut job: JobInfo, now: i64) -> Result<()> {
+        let job_id = job.id.unwrap_or_default();
+        let fire_time = match job.next_trigger_time {
+            Some(fire_time) => fire_time,
+            None => {
+                // its fire time is resolved when it is saved, see `prepare_job`.
+                warn!(
+                    "[OneShot Scheduler] disable job {}: its fire time {:?} was never resolved.",
+                    job_id, job.time_expression
                 );
+                job.status = Some(JobStatus::DISABLED.into());
+                return self
+                    .storage
+                    .update(&mut [job])
+                    .context(error::SchedStorageError);
+            }
+        };
+        let policy = job
+            .misfire_policy
+            .and_then(|p| MisfirePolicy::try_from(p).ok())
+            .unwrap_or_default();
+        let missed = fire_time < now - MISFIRE_THRESHOLD;
+        if missed {
+            warn!(
+                "[OneShot Scheduler] job {} missed its fire time {}, {:?}.",
+                job_id, fire_time, policy
+            );
+        }
+        let fires = match (missed, policy) {
+            (true, MisfirePolicy::Skip) => vec![],
+            (true, _) => vec![Fire {
+                expected_trigger_time: fire_time,
+                trigger_time: now,
+                action: Some(MisfirePolicy::FireOnce),
+            }],
+            (false, _) => vec![Fire::on_time(fire_time)],
+        };
+        job.status = Some(JobStatus::Finished.into());
+        self.trigger_round(job.next_trigger_time, &job, fires)
+    }
 
-                for job in job_infos {
-                    let instance_info = InstanceInfo::create(
-                        job.get_id(),
-                        job.get_app_id(),
-                        job.get_job_params(),
-                        None,
-                        None,
-                        Some(chrono::Local::now().timestamp_millis()),
-                    );
-                    self.storage.save(instance_info)?;
-                    self.dispatch
-                        .dispatch(job.clone(), instance_info.id.unwrap());
-                }
-                Ok(())
-            })
+    /// Create an instance of a running API job on demand, dispatched after `delay` ms. A
+    /// retried request with the same `request_key` gets back the id of the instance it created.
+    pub fn run_job(
+        &self,
+        job: &JobInfo,
+        instance_params: Option<String>,
+        delay: i64,
+        request_key: Option<&str>,
+    ) -> Result<u64> {
+        let job_id = job.id.unwrap_or_default();
+        if job.time_expression_type != Some(JobTimeExpressionType::API.into()) {
+            return error::NotApiJob { job_id }.fail();
+        }
+        if job.status != Some(JobStatus::Running.into()) {
+            return error::JobNotRunning { job_id }.fail();
+        }
+        let request_key = request_key.map(str::trim).filter(|key| !key.is_empty());
+        let created = |key: &str| -> Result<Option<u64>> {
+            let instance = self
+                .storage
+                .find_instance_by_request_key(job_id, key)
+                .context(error::SchedStorageError)?;
+            Ok(instance.and_then(|instance| instance.instance_id))
+        };
+        if let Some(instance_id) = request_key.map(created).transpose()?.flatten() {
+            return Ok(instance_id);
+        }
+
+        let trigger_time = self.frequent.now() + delay.max(0);
+        let mut instance_info = InstanceInfo::create(
+            job.get_id(),
+            job.get_app_id(),
+            job.get_job_params(),
+            instance_params,
+            None,
+            Some(trigger_time),
+        )
+        .context(error::IdGeneratorFailed)?;
+        instance_info.request_key = request_key.map(str::to_string);
+        match (self.launch(job, instance_info, trigger_time), request_key) {
+            // a concurrent request with the same key saved its instance first.
+            (Err(e @ error::SchedError::SchedStorageError { .. }), Some(key)) => {
+                created(key)?.ok_or(e)
+            }
+            (rs, _) => rs,
+        }
+    }
+
+    /// Save the instances of a schedule round with the job advanced from
+    /// `previous_trigger_time` in one transaction, then push them to the timing wheel. A
+    /// round another server already saved creates nothing.
+    fn trigger_round(
+        &self,
+        previous_trigger_time: Option<i64>,
+        job: &JobInfo,
+        fires: Vec<Fire>,
+    ) -> Result<()> {
+        let instances = fires
+            .iter()
+            .map(|fire| scheduled_instance(job, fire))
+            .collect::<Result<Vec<InstanceInfo>>>()?;
+        let saved = self
+            .storage
+            .save_scheduled_instances(previous_trigger_time, job, &instances)
+            .context(error::SchedStorageError)?;
+        if !saved {
+            info!(
+                "[JobScheduler] job {} was scheduled from {:?} by another server.",
+                job.id.unwrap_or_default(),
+                previous_trigger_time
+            );
+            return Ok(());
+        }
+        for (instance, fire) in instances.iter().zip(fires) {
+            let instance_id = instance.instance_id.unwrap_or_default();
+            if let Some(task) = self.build_task(job.clone(), instance_id, fire.trigger_time)? {
+                self.delay_timer.add_task(task);
+            }
         }
         Ok(())
     }
 
-    /// Schedule tasks of type worker-flow.
+    /// Save an on-demand instance the same way as a schedule round, checked against the
+    /// stored job, before pushing it to the timing wheel. An instance whose server fails over
+    /// before `trigger_time` waits for dispatch in storage, where the status check finds it.
+    fn launch(&self, job: &JobInfo, instance_info: InstanceInfo, trigger_time: i64) -> Result<u64> {
+        let job_id = job.id.unwrap_or_default();
+        let instance_id = instance_info.instance_id.unwrap_or_default();
+        // an on-demand run doesn't advance the job.
+        let saved = self
+            .storage
+            .save_scheduled_instances(job.next_trigger_time, job, &[instance_info])
+            .context(error::SchedStorageError)?;
+        if !saved {
+            // the job was stopped or changed since it was read.
+            return error::JobNotRunning { job_id }.fail();
+        }
+        if let Some(task) = self.build_task(job.clone(), instance_id, trigger_time)? {
+            self.delay_timer.add_task(task);
+        }
+        Ok(instance_id)
+    }
+
+    /// Schedule tasks of type worker-flow, a CRON workflow starts on the first round after
+    /// it is due.
     pub fn schedule_worker_flow(&self, ids: &[u64]) -> Result<()> {
-        //todo
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let now = self.frequent.now();
+        for chunk in ids.chunks(10) {
+            let workflows = self
+                .storage
+                .find_cron_workflows(chunk, now)
+                .context(error::SchedStorageError)?;
+            for workflow in workflows {
+                info!(
+                    "[Workflow Scheduler] workflow {} ready to scheduled.",
+                    workflow.id.unwrap_or_default()
+                );
+                let nodes = self.workflow.start(&workflow, workflow.next_trigger_time)?;
+                self.dispatch_workflow_nodes(nodes);
+                self.refresh_workflow(workflow)?;
+            }
+        }
         Ok(())
     }
 
-    fn refresh_job(&self, mut job: JobInfo) -> Result<()> {
-        match job.time_expression {
-            Some(express) => {
-                let next_trigger_time = self.calculate_next_trigger_time(express.as_str())?;
-                job.next_trigger_time = Some(next_trigger_time);
-            }
-            None => {
-                job.status = Some(JobStatus::DISABLED.into());
+    /// Advance the workflow instance of a node whose instance finished.
+    pub fn workflow_node_finished(
+        &self,
+        wf_instance_id: u64,
+        instance_id: u64,
+        status: InstanceStatus,
+        result: &str,
+    ) -> Result<()> {
+        let nodes = self
+            .workflow
+            .node_finished(wf_instance_id, instance_id, status, result)?;
+        self.dispatch_workflow_nodes(nodes);
+        Ok(())
+    }
+
+    /// Merge key/values a node appended into the context of its workflow instance.
+    pub fn append_workflow_context(
+        &self,
+        wf_instance_id: u64,
+        appended: &std::collections::HashMap<String, String>,
+    ) -> Result<bool> {
+        self.workflow.append_context(wf_instance_id, appended)
+    }
+
+    /// Run the failed nodes of a failed or stopped workflow instance again.
+    pub fn retry_workflow_instance(&self, wf_instance_id: u64) -> Result<()> {
+        let nodes = self.workflow.retry_failed(wf_instance_id)?;
+        self.dispatch_workflow_nodes(nodes);
+        Ok(())
+    }
+
+    /// Mark a failed node of a workflow instance as succeeded to unblock its downstream nodes.
+    pub fn mark_workflow_node_success(&self, wf_instance_id: u64, node_id: u64) -> Result<()> {
+        let nodes = self.workflow.mark_node_success(wf_instance_id, node_id)?;
+        self.dispatch_workflow_nodes(nodes);
+        Ok(())
+    }
+
+    /// Stop a workflow instance and its unfinished node instances, returns their ids.
+    pub fn stop_workflow_instance(&self, wf_instance_id: u64) -> Result<Vec<u64>> {
+        self.workflow.stop(wf_instance_id)
+    }
+
+    /// Catch up the unfinished workflow instances of the apps with their node instances.
+    pub fn check_workflow(&self, ids: &[u64]) -> Result<()> {
+        let nodes = self.workflow.refresh(ids)?;
+        self.dispatch_workflow_nodes(nodes);
+        Ok(())
+    }
+
+    fn dispatch_workflow_nodes(&self, nodes: Vec<(JobInfo, u64)>) {
+        for (job, instance_id) in nodes {
+            // the instance stays waiting for dispatch, the status checker dispatches it again.
+            if let Err(e) = self.task_sender.try_send((job, instance_id)) {
+                warn!("[Workflow Scheduler] dispatch node instance {} failed: {}", instance_id, e);
             }
         }
-        self.storage.update(&mut job)?;
-        Ok(())
     }
 
-    fn refresh_workflow(&self) {}
+    fn refresh_workflow(&self, mut workflow: WorkflowInfo) -> Result<()> {
+        let after = workflow
+            .next_trigger_time
+            .unwrap_or_default()
+            .max(self.frequent.now());
+        match self.next_fire_time(workflow.time_expression.as_deref(), None, after) {
+            Some(next_trigger_time) => workflow.next_trigger_time = Some(next_trigger_time),
+            None => workflow.status = Some(JobStatus::DISABLED.into()),
+        }
+        self.storage
+            .update(&mut [workflow])
+            .context(error::SchedStorageError)?;
+        Ok(())
+    }
 
-    fn calculate_next_trigger_time(&self, expression: &str) -> Result<i64> {
-        let next_trigger_time = Schedule::from_str(expression)?
-            .upcoming(Local)
-            .next()
-            .unwrap();
-        Ok(next_trigger_time.timestamp_millis())
+    /// The next fire time after `after`, `None` when the expression is missing, invalid or
+    /// never fires again.
+    fn next_fire_time(
+        &self,
+        expression: Option<&str>,
+        time_zone: Option<&str>,
+        after: i64,
+    ) -> Option<i64> {
+        match trigger::next_trigger_time(expression?, time_zone, after) {
+            Ok(next_trigger_time) => next_trigger_time,
+            Err(e) => {
+                warn!("[Cron Scheduler] {}", e);
+                None
+            }
+        }
     }
 
     pub fn filter_task_record_id<P>(&self, predicate: P) -> Option<i64>
@@ -234,11 +611,13 @@ impl<S: Storage> Scheduler<S> {
         Ok(())
     }
 
-    // pub(crate) fn build_task<F>(&self, job: JobInfo, delay: i64, instance_id: u64) -> Result<Option<Task>>
-    pub(crate) fn build_task<F>(&self, job: JobInfo, instance_id: u64) -> Result<Option<Task>>
-        where
-            F: Fn(TaskContext) -> Box<dyn DelayTaskHandler> + 'static + Send + Sync,
-    {
+    /// A one-shot task sending the instance to dispatch at `trigger_time`.
+    pub(crate) fn build_task(
+        &self,
+        job: JobInfo,
+        instance_id: u64,
+        trigger_time: i64,
+    ) -> Result<Option<Task>> {
         let body = match JobType::try_from(job.processor_type.unwrap()) {
             Ok(_) => {
                 create_async_fn_body!({
@@ -253,25 +632,17 @@ impl<S: Storage> Scheduler<S> {
             }
         };
 
-        let frequency = match JobTimeExpressionType::try_from(job.time_expression_type.unwrap()) {
-            Ok(_) => CandyFrequency::Once(CandyCronStr("".to_string())),
-            Err(_) => {
-                error!(
-                    "Unknown atomic number: {}",
-                    job.time_expression_type.unwrap()
-                );
-                // i think unimportant for this error, so just log
-                return Ok(None);
-            }
-        };
+        let frequency =
+            CandyFrequency::Once(CandyCronStr(once_at(trigger_time, self.frequent.now())));
 
+        // an instance per task, a job has a task per tick.
         let task = TaskBuilder::default()
-            .set_task_id(job.id.unwrap())
-            .set_frequency_by_candy(CandyFrequency::Once(job.time_expression.unwrap()))
+            .set_task_id(instance_id)
+            .set_frequency_by_candy(frequency)
             .set_maximun_parallel_runable_num(job.concurrency.unwrap_or(1) as u64)
             .spawn(body)
             .context(error::ConstructorTaskFailed {
-                task_id: job.id.unwrap(),
+                task_id: instance_id,
             })?;
 
         Ok(Some(task))
@@ -282,9 +653,204 @@ impl<S: Storage> Scheduler<S> {
     }
 }
 
+/// The instance the schedule creates for `fire`, its expected trigger time is its slot.
+fn scheduled_instance(job: &JobInfo, fire: &Fire) -> Result<InstanceInfo> {
+    let mut instance_info = InstanceInfo::create(
+        job.get_id(),
+        job.get_app_id(),
+        job.get_job_params(),
+        None,
+        None,
+        Some(fire.expected_trigger_time),
+    )
+    .context(error::IdGeneratorFailed)?;
+    instance_info.misfire_action = fire.action.map(Into::into);
+    instance_info.trigger_slot = Some(fire.expected_trigger_time);
+    Ok(instance_info)
+}
+
+/// A cron expression matching only the second of `trigger_time`, rounded up so it isn't
+/// missed, a trigger time already past fires on the next second.
+fn once_at(trigger_time: i64, now: i64) -> String {
+    let millis = trigger_time.max(now + 1000);
+    let at = Local.timestamp((millis + 999) / 1000, 0);
+    format!(
+        "{} {} {} {} {} ? {}",
+        at.second(),
+        at.minute(),
+        at.hour(),
+        at.day(),
+        at.month(),
+        at.year()
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use fastjob_components_storage::MemoryStorage;
+    use fastjob_components_utils::id_generator;
+    use fastjob_components_utils::time::ManualClock;
+
+    fn scheduler(storage: &MemoryStorage, now: i64) -> Scheduler<MemoryStorage> {
+        let (tx, _rx) = tokio::sync::mpsc::channel(16);
+        Scheduler::new(Arc::new(storage.clone()), tx).with_clock(Arc::new(ManualClock::new(now)))
+    }
+
+    #[test]
+    fn t_prepare_cron_job() {
+        let now = 1_633_050_000_000;
+        let scheduler = scheduler(&MemoryStorage::new(), now);
+        let cron_job = |expression: &str| JobInfo {
+            time_expression_type: Some(JobTimeExpressionType::CRON.into()),
+            time_expression: Some(expression.to_string()),
+            time_zone: Some("UTC".to_string()),
+            ..Default::default()
+        };
+
+        let mut job = cron_job("0 0 * * * *");
+        scheduler.prepare_job(&mut job).unwrap();
+        assert_eq!(job.next_trigger_time, Some(now + 3_600_000));
+
+        let mut job = cron_job("0 0 25 * * *");
+        assert!(matches!(
+            scheduler.prepare_job(&mut job),
+            Err(error::SchedError::InvalidCronExpression { .. })
+        ));
+        // a cron that never fires again.
+        let mut job = cron_job("0 0 0 1 1 * 2020");
+        assert!(scheduler.prepare_job(&mut job).is_err());
+    }
+
+    #[test]
+    fn t_prepare_one_shot_job() {
+        let now = 1_633_050_000_000;
+        let scheduler = scheduler(&MemoryStorage::new(), now);
+        let one_shot_job = |expression: &str| JobInfo {
+            time_expression_type: Some(JobTimeExpressionType::OneShot.into()),
+            time_expression: Some(expression.to_string()),
+            ..Default::default()
+        };
+
+        // the delay is resolved once, a failover doesn't postpone the job.
+        let mut job = one_shot_job("+30000");
+        scheduler.prepare_job(&mut job).unwrap();
+        assert_eq!(job.next_trigger_time, Some(now + 30_000));
+
+        let mut job = one_shot_job("1000");
+        assert!(matches!(
+            scheduler.prepare_job(&mut job),
+            Err(error::SchedError::InvalidFireTime { .. })
+        ));
+    }
+
+    #[test]
+    fn t_run_job() {
+        // instance ids are generated with the worker id this server leased.
+        id_generator::init(1).unwrap();
+        let now = 1_633_050_000_000;
+        let storage = MemoryStorage::new();
+        let scheduler = scheduler(&storage, now);
+        let job = |id: u64, typ: JobTimeExpressionType, status: JobStatus| JobInfo {
+            id: Some(id),
+            app_id: Some(1),
+            processor_type: Some(JobType::Java.into()),
+            status: Some(status.into()),
+            time_expression_type: Some(typ.into()),
+            ..Default::default()
+        };
+        let api = job(1, JobTimeExpressionType::API, JobStatus::Running);
+        let cron = job(2, JobTimeExpressionType::CRON, JobStatus::Running);
+        let stopped = job(3, JobTimeExpressionType::API, JobStatus::DISABLED);
+        for job in vec![api.clone(), cron.clone(), stopped.clone()] {
+            storage.save(job).unwrap();
+        }
+
+        let instance_id = scheduler
+            .run_job(&api, Some("params".to_string()), 0, Some("key"))
+            .unwrap();
+        let instance = storage.find_instance_by_id(instance_id).unwrap().unwrap();
+        assert_eq!(instance.instance_params.as_deref(), Some("params"));
+        // a retried request doesn't create a second instance.
+        assert_eq!(scheduler.run_job(&api, None, 0, Some("key")).unwrap(), instance_id);
+
+        assert!(matches!(
+            scheduler.run_job(&cron, None, 0, None),
+            Err(error::SchedError::NotApiJob { job_id: 2 })
+        ));
+        assert!(matches!(
+            scheduler.run_job(&stopped, None, 0, None),
+            Err(error::SchedError::JobNotRunning { job_id: 3 })
+        ));
+        // the job was disabled after it was read.
+        storage
+            .update(&mut [JobInfo {
+                id: Some(1),
+                status: Some(JobStatus::DISABLED.into()),
+                ..Default::default()
+            }])
+            .unwrap();
+        assert!(matches!(
+            scheduler.run_job(&api, None, 0, None),
+            Err(error::SchedError::JobNotRunning { job_id: 1 })
+        ));
+    }
+
+    #[test]
+    fn t_run_job_racing_request_key() {
+        id_generator::init(1).unwrap();
+        let now = 1_633_050_000_000;
+        let storage = MemoryStorage::new();
+        let api = JobInfo {
+            id: Some(1),
+            app_id: Some(1),
+            processor_type: Some(JobType::Java.into()),
+            status: Some(JobStatus::Running.into()),
+            time_expression_type: Some(JobTimeExpressionType::API.into()),
+            ..Default::default()
+        };
+        storage.save(api.clone()).unwrap();
+
+        // both servers look the key up before either saves its instance.
+        storage.set_latency(Duration::from_millis(50));
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let storage = storage.clone();
+                let api = api.clone();
+                std::thread::spawn(move || {
+                    scheduler(&storage, now)
+                        .run_job(&api, None, 0, Some("key"))
+                        .unwrap()
+                })
+            })
+            .collect();
+        let ids: Vec<u64> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        storage.clear_faults();
+        assert_eq!(ids[0], ids[1]);
+        let instance = storage.find_instance_by_request_key(1, "key").unwrap().unwrap();
+        assert_eq!(instance.instance_id, Some(ids[0]));
+    }
+
+    #[test]
+    fn t_schedule_unresolved_one_shot() {
+        let now = 1_633_050_000_000;
+        let storage = MemoryStorage::new();
+        let scheduler = scheduler(&storage, now);
+        let job = JobInfo {
+            id: Some(1),
+            app_id: Some(1),
+            status: Some(JobStatus::Running.into()),
+            time_expression_type: Some(JobTimeExpressionType::OneShot.into()),
+            time_expression: Some("+30000".to_string()),
+            ..Default::default()
+        };
+        storage.save(job.clone()).unwrap();
+
+        // a job without a fire time isn't fired right away.
+        scheduler.schedule_one_shot(job, now).unwrap();
+        let job = storage.find_job_info_by_id(1).unwrap().unwrap();
+        assert_eq!(job.status, Some(JobStatus::DISABLED.into()));
+    }
 
     fn t_cron() {
         let expression = "0   30   9,12,15     1,15       May-Aug  Mon,Wed,Fri  2018/2";
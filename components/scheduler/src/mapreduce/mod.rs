@@ -1 +1,512 @@
+//! MapReduce execution of an instance.
+//!
+//! The instance is dispatched to one worker, the task tracker, where its root task runs.
+//! The root (and any map task) emits child map tasks through `map`, they are persisted in
+//! `task_info` and `dispatch_pending` spreads them over up to `max_worker_count` workers.
+//! A failed map or reduce task is dispatched again until it failed `task_retry_num` times.
+//! Once the root succeeded and every map task finished, a reduce task receives the results
+//! of all map tasks on the task tracker and its status becomes the instance's.
+//!
+//! The engine only keeps the state, sending a task to a worker is left to the caller.
+use crate::error::{self, Result};
+use fastjob_components_storage::model::instance_info::{InstanceInfo, InstanceStatus};
+use fastjob_components_storage::model::job_info::JobInfo;
+use fastjob_components_storage::model::task_info::{TaskInfo, TaskStatusCount, TaskType};
+use fastjob_components_storage::Storage;
+use fastjob_components_utils::id_generator::{self, GeneratorTyp};
+use snafu::{OptionExt, ResultExt};
+use std::convert::TryFrom;
+use std::sync::Arc;
 
+pub const ROOT_TASK_ID: &str = "0";
+pub const REDUCE_TASK_ID: &str = "reduce";
+/// The maximum number of tasks dispatched by one `dispatch_pending`.
+const DISPATCH_BATCH_SIZE: u64 = 256;
+
+/// What a MapReduce instance is waiting for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Step {
+    /// Tasks are still waiting or running.
+    Wait,
+    /// Every map task finished, the reduce task was created and waits for dispatch.
+    Reduce,
+    /// The instance finished with the status.
+    Finished(InstanceStatus),
+}
+
+/// The map tasks of an instance by status.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MapReduceStats {
+    pub total: u64,
+    /// Waiting to be dispatched or received by a worker.
+    pub waiting: u64,
+    pub running: u64,
+    pub succeeded: u64,
+    pub failed: u64,
+}
+
+impl MapReduceStats {
+    pub fn from_counts(counts: &[TaskStatusCount]) -> Self {
+        let mut stats = MapReduceStats::default();
+        for count in counts
+            .iter()
+            .filter(|c| c.task_type == Some(TaskType::Map.into()))
+        {
+            stats.total += count.count;
+            match status_of(count) {
+                Some(InstanceStatus::Running) => stats.running += count.count,
+                Some(InstanceStatus::Success) => stats.succeeded += count.count,
+                Some(s) if is_unsuccessful(s) => stats.failed += count.count,
+                _ => stats.waiting += count.count,
+            }
+        }
+        stats
+    }
+
+    #[inline]
+    pub fn finished(&self) -> u64 {
+        self.succeeded + self.failed
+    }
+}
+
+impl std::fmt::Display for MapReduceStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "map tasks {}/{} finished, {} succeeded, {} failed",
+            self.finished(),
+            self.total,
+            self.succeeded,
+            self.failed
+        )
+    }
+}
+
+/// Decide the step of an instance from the task counts.
+pub fn next_step(counts: &[TaskStatusCount]) -> Step {
+    let status = |typ: TaskType| {
+        counts
+            .iter()
+            .filter(move |c| c.task_type == Some(typ.into()))
+            .map(status_of)
+    };
+    match status(TaskType::Root).next() {
+        Some(Some(InstanceStatus::Success)) => {}
+        Some(Some(s)) if is_unsuccessful(s) => return Step::Finished(InstanceStatus::Failed),
+        _ => return Step::Wait,
+    }
+    match status(TaskType::Reduce).next() {
+        Some(Some(InstanceStatus::Success)) => Step::Finished(InstanceStatus::Success),
+        Some(Some(s)) if is_unsuccessful(s) => Step::Finished(InstanceStatus::Failed),
+        Some(_) => Step::Wait,
+        None if status(TaskType::Map).all(|s| s.map_or(false, is_finished)) => Step::Reduce,
+        None => Step::Wait,
+    }
+}
+
+fn status_of(count: &TaskStatusCount) -> Option<InstanceStatus> {
+    count.status.and_then(|s| InstanceStatus::try_from(s).ok())
+}
+
+fn is_unsuccessful(status: InstanceStatus) -> bool {
+    matches!(
+        status,
+        InstanceStatus::Failed | InstanceStatus::Canceled | InstanceStatus::Stopped
+    )
+}
+
+fn is_finished(status: InstanceStatus) -> bool {
+    status == InstanceStatus::Success || is_unsuccessful(status)
+}
+
+pub struct MapReduceEngine<S: Storage> {
+    storage: Arc<S>,
+}
+
+impl<S: Storage> Clone for MapReduceEngine<S> {
+    fn clone(&self) -> Self {
+        Self {
+            storage: self.storage.clone(),
+        }
+    }
+}
+
+impl<S: Storage> MapReduceEngine<S> {
+    pub fn new(storage: Arc<S>) -> Self {
+        Self { storage }
+    }
+
+    /// Record the root task once the instance was sent to its task tracker.
+    pub fn start(&self, instance: &InstanceInfo, tracker: &str) -> Result<()> {
+        let instance_id = instance.instance_id.unwrap_or_default();
+        // a redispatched instance starts over.
+        self.storage
+            .delete_tasks_by_instance_id(instance_id)
+            .context(error::SchedStorageError)?;
+        let mut root = self.task(instance, ROOT_TASK_ID, TaskType::Root);
+        root.task_params = instance.instance_params.clone();
+        root.worker_address = Some(tracker.to_string());
+        root.status = Some(InstanceStatus::WaitingWorkerReceive.into());
+        self.storage.save(root).context(error::SchedStorageError)
+    }
+
+    /// The worker running `parent_task_id` emitted `children` as (name, params),
+    /// returns the ids of the new map tasks.
+    pub fn map(
+        &self,
+        instance: &InstanceInfo,
+        parent_task_id: &str,
+        children: Vec<(String, String)>,
+    ) -> Result<Vec<String>> {
+        let instance_id = instance.instance_id.unwrap_or_default();
+        let parent = self.find_task(instance_id, parent_task_id)?;
+        if parent.task_type == Some(TaskType::Reduce.into()) {
+            warn!(
+                "[MapReduce] reduce task of instance {} can't emit map tasks.",
+                instance_id
+            );
+            return Ok(vec![]);
+        }
+        // generated ids, concurrent maps of the instance never collide.
+        let mut tasks = Vec::with_capacity(children.len());
+        for (name, params) in children {
+            let id = id_generator::generator_id(GeneratorTyp::Task)
+                .context(error::IdGeneratorFailed)?;
+            let mut task = self.task(instance, &format!("{}.{}", parent_task_id, id), TaskType::Map);
+            task.task_name = Some(name);
+            task.task_params = Some(params);
+            tasks.push(task);
+        }
+        if tasks.is_empty() {
+            return Ok(vec![]);
+        }
+        self.storage
+            .save_batch(&tasks)
+            .context(error::SchedStorageError)?;
+        debug!(
+            "[MapReduce] task {} of instance {} emitted {} map tasks.",
+            parent_task_id,
+            instance_id,
+            tasks.len()
+        );
+        Ok(tasks.into_iter().filter_map(|t| t.task_id).collect())
+    }
+
+    /// Send the tasks waiting for dispatch, map tasks to one of `workers` and the reduce
+    /// task, along with every map task, to the task tracker. Returns the number of sent tasks.
+    ///
+    /// `send(worker, task, map_tasks)` gets the map tasks only for the reduce task.
+    pub fn dispatch_pending<F>(
+        &self,
+        job_info: &JobInfo,
+        instance_id: u64,
+        workers: &[String],
+        mut send: F,
+    ) -> Result<usize>
+        where
+            F: FnMut(&str, &TaskInfo, &[TaskInfo]) -> std::result::Result<(), String>,
+    {
+        let workers = match job_info.max_worker_count.filter(|c| *c > 0) {
+            Some(count) => &workers[..count.min(workers.len())],
+            None => workers,
+        };
+        let pending = self
+            .storage
+            .find_tasks_by_status(
+                instance_id,
+                InstanceStatus::WaitingDispatch.into(),
+                DISPATCH_BATCH_SIZE,
+            )
+            .context(error::SchedStorageError)?;
+        let mut sent = 0;
+        for mut task in pending {
+            let is_map = task.task_type == Some(TaskType::Map.into());
+            let (worker, map_tasks) = if is_map {
+                if workers.is_empty() {
+                    continue;
+                }
+                // spread by id so consecutive batches don't all start on the same worker.
+                let idx = task.id.unwrap_or_default() as usize % workers.len();
+                (workers[idx].clone(), vec![])
+            } else {
+                let map_tasks = self
+                    .storage
+                    .find_tasks_by_instance_id(instance_id)
+                    .context(error::SchedStorageError)?
+                    .into_iter()
+                    .filter(|t| t.task_type == Some(TaskType::Map.into()))
+                    .collect();
+                (task.worker_address.clone().unwrap_or_default(), map_tasks)
+            };
+            match send(&worker, &task, &map_tasks) {
+                Ok(_) => {
+                    task.worker_address = Some(worker);
+                    task.status = Some(InstanceStatus::WaitingWorkerReceive.into());
+                    task.gmt_modified = Some(chrono::Local::now().timestamp_millis());
+                    self.storage
+                        .update(&mut [task])
+                        .context(error::SchedStorageError)?;
+                    sent += 1;
+                }
+                Err(e) => {
+                    warn!(
+                        "[MapReduce] send task {} of instance {} to worker {} failed: {}",
+                        task.task_id.as_deref().unwrap_or_default(),
+                        instance_id,
+                        worker,
+                        e
+                    );
+                    task.worker_address = Some(worker);
+                    self.fail_or_retry(job_info, task, &e)?;
+                }
+            }
+        }
+        Ok(sent)
+    }
+
+    /// A worker reported the status of a task, returns the step of the instance. The caller
+    /// dispatches the pending tasks unless the instance finished.
+    pub fn report(
+        &self,
+        job_info: &JobInfo,
+        instance: &InstanceInfo,
+        task_id: &str,
+        status: InstanceStatus,
+        result: &str,
+    ) -> Result<Step> {
+        let instance_id = instance.instance_id.unwrap_or_default();
+        let mut task = self.find_task(instance_id, task_id)?;
+        let current = task.status.and_then(|s| InstanceStatus::try_from(s).ok());
+        // duplicated or late report of a finished task.
+        if !current.map_or(false, is_finished) {
+            if is_unsuccessful(status) {
+                self.fail_or_retry(job_info, task, result)?;
+            } else {
+                task.status = Some(status.into());
+                task.result = Some(result.to_string());
+                task.gmt_modified = Some(chrono::Local::now().timestamp_millis());
+                self.storage
+                    .update(&mut [task])
+                    .context(error::SchedStorageError)?;
+            }
+        }
+
+        let step = next_step(
+            &self
+                .storage
+                .count_tasks_by_status(instance_id)
+                .context(error::SchedStorageError)?,
+        );
+        if step == Step::Reduce {
+            self.create_reduce(instance)?;
+        }
+        Ok(step)
+    }
+
+    pub fn stats(&self, instance_id: u64) -> Result<MapReduceStats> {
+        let counts = self
+            .storage
+            .count_tasks_by_status(instance_id)
+            .context(error::SchedStorageError)?;
+        Ok(MapReduceStats::from_counts(&counts))
+    }
+
+    fn create_reduce(&self, instance: &InstanceInfo) -> Result<()> {
+        let instance_id = instance.instance_id.unwrap_or_default();
+        // the unique key on (instance_id, task_id) stops a concurrent report creating it twice.
+        if self
+            .storage
+            .find_task(instance_id, REDUCE_TASK_ID)
+            .context(error::SchedStorageError)?
+            .is_some()
+        {
+            return Ok(());
+        }
+        let root = self.find_task(instance_id, ROOT_TASK_ID)?;
+        let mut reduce = self.task(instance, REDUCE_TASK_ID, TaskType::Reduce);
+        reduce.worker_address = root.worker_address;
+        info!(
+            "[MapReduce] every map task of instance {} finished, start reducing.",
+            instance_id
+        );
+        self.storage.save(reduce).context(error::SchedStorageError)
+    }
+
+    /// Fail the task, or queue it for dispatch again while it has retries left.
+    /// The root isn't retried, retrying it is retrying the instance.
+    fn fail_or_retry(&self, job_info: &JobInfo, mut task: TaskInfo, result: &str) -> Result<()> {
+        let retry_times = task.retry_times.unwrap_or_default();
+        let retryable = task.task_type != Some(TaskType::Root.into())
+            && (retry_times as usize) < job_info.task_retry_num.unwrap_or_default();
+        if retryable {
+            task.retry_times = Some(retry_times + 1);
+            task.status = Some(InstanceStatus::WaitingDispatch.into());
+        } else {
+            task.status = Some(InstanceStatus::Failed.into());
+        }
+        task.result = Some(result.to_string());
+        task.gmt_modified = Some(chrono::Local::now().timestamp_millis());
+        self.storage
+            .update(&mut [task])
+            .context(error::SchedStorageError)?;
+        Ok(())
+    }
+
+    fn find_task(&self, instance_id: u64, task_id: &str) -> Result<TaskInfo> {
+        self.storage
+            .find_task(instance_id, task_id)
+            .context(error::SchedStorageError)?
+            .context(error::TaskNotFound {
+                instance_id,
+                task_id,
+            })
+    }
+
+    fn task(&self, instance: &InstanceInfo, task_id: &str, task_type: TaskType) -> TaskInfo {
+        let now = chrono::Local::now().timestamp_millis();
+        TaskInfo {
+            app_id: instance.app_id,
+            job_id: instance.job_id,
+            instance_id: instance.instance_id,
+            task_id: Some(task_id.to_string()),
+            task_type: Some(task_type.into()),
+            status: Some(InstanceStatus::WaitingDispatch.into()),
+            retry_times: Some(0),
+            gmt_create: Some(now),
+            gmt_modified: Some(now),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fastjob_components_storage::MemoryStorage;
+
+    fn count(task_type: TaskType, status: InstanceStatus, count: u64) -> TaskStatusCount {
+        TaskStatusCount {
+            task_type: Some(task_type.into()),
+            status: Some(status.into()),
+            count,
+        }
+    }
+
+    #[test]
+    fn t_next_step() {
+        use InstanceStatus::*;
+        use TaskType::*;
+        assert_eq!(next_step(&[]), Step::Wait);
+        assert_eq!(next_step(&[count(Root, Running, 1), count(Map, Success, 2)]), Step::Wait);
+        assert_eq!(next_step(&[count(Root, Failed, 1)]), Step::Finished(Failed));
+        assert_eq!(
+            next_step(&[count(Root, Success, 1), count(Map, Success, 2), count(Map, Running, 1)]),
+            Step::Wait
+        );
+        assert_eq!(
+            next_step(&[count(Root, Success, 1), count(Map, Success, 2), count(Map, Failed, 1)]),
+            Step::Reduce
+        );
+        assert_eq!(
+            next_step(&[count(Root, Success, 1), count(Reduce, WaitingDispatch, 1)]),
+            Step::Wait
+        );
+        assert_eq!(
+            next_step(&[count(Root, Success, 1), count(Reduce, Success, 1)]),
+            Step::Finished(Success)
+        );
+    }
+
+    #[test]
+    fn t_map_retry_reduce() {
+        id_generator::init(1).unwrap();
+        let engine = MapReduceEngine::new(Arc::new(MemoryStorage::new()));
+        let job = JobInfo {
+            task_retry_num: Some(1),
+            max_worker_count: Some(2),
+            ..Default::default()
+        };
+        let instance = InstanceInfo {
+            instance_id: Some(7),
+            ..Default::default()
+        };
+        let workers: Vec<String> = vec!["a".into(), "b".into(), "c".into()];
+        engine.start(&instance, "a").unwrap();
+        let children = (0..3).map(|i| (format!("map-{}", i), i.to_string())).collect();
+        let ids = engine.map(&instance, ROOT_TASK_ID, children).unwrap();
+        assert_eq!(ids.len(), 3);
+
+        let mut targets = vec![];
+        let sent = engine
+            .dispatch_pending(&job, 7, &workers, |worker, _, _| {
+                targets.push(worker.to_string());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(sent, 3);
+        // capped at `max_worker_count` workers.
+        assert!(targets.iter().all(|w| w != "c"));
+
+        let report = |task_id: &str, status| engine.report(&job, &instance, task_id, status, "r").unwrap();
+        assert_eq!(report(ROOT_TASK_ID, InstanceStatus::Success), Step::Wait);
+        assert_eq!(report(&ids[0], InstanceStatus::Success), Step::Wait);
+        assert_eq!(report(&ids[1], InstanceStatus::Success), Step::Wait);
+        // retried once, then failed for good.
+        assert_eq!(report(&ids[2], InstanceStatus::Failed), Step::Wait);
+        assert_eq!(engine.stats(7).unwrap().waiting, 1);
+        engine.dispatch_pending(&job, 7, &workers, |_, _, _| Ok(())).unwrap();
+        assert_eq!(report(&ids[2], InstanceStatus::Failed), Step::Reduce);
+        assert_eq!(
+            engine.stats(7).unwrap(),
+            MapReduceStats {
+                total: 3,
+                succeeded: 2,
+                failed: 1,
+                ..Default::default()
+            }
+        );
+
+        let mut inputs = 0;
+        engine
+            .dispatch_pending(&job, 7, &workers, |worker, task, map_tasks| {
+                assert_eq!(worker, "a");
+                assert_eq!(task.task_id.as_deref(), Some(REDUCE_TASK_ID));
+                inputs = map_tasks.len();
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(inputs, 3);
+        assert_eq!(
+            report(REDUCE_TASK_ID, InstanceStatus::Success),
+            Step::Finished(InstanceStatus::Success)
+        );
+    }
+
+    #[test]
+    fn t_concurrent_maps() {
+        id_generator::init(1).unwrap();
+        let engine = MapReduceEngine::new(Arc::new(MemoryStorage::new()));
+        let instance = InstanceInfo {
+            instance_id: Some(8),
+            ..Default::default()
+        };
+        engine.start(&instance, "a").unwrap();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let (engine, instance) = (engine.clone(), instance.clone());
+                std::thread::spawn(move || {
+                    let children = (0..50).map(|i| (format!("map-{}", i), String::new())).collect();
+                    engine.map(&instance, ROOT_TASK_ID, children).unwrap()
+                })
+            })
+            .collect();
+        let mut ids: Vec<String> = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 200);
+        assert_eq!(engine.stats(8).unwrap().total, 200);
+    }
+}
@@ -0,0 +1,181 @@
+//! What a cron job fires when its `next_trigger_time` passed while no server scheduled it.
+//!
+//! A trigger time is missed once it is more than `MISFIRE_THRESHOLD` in the past, later ones
+//! are only late because of the schedule round. The job's `MisfirePolicy` decides whether
+//! the missed runs fire once, fire each up to `max_catch_up` or are skipped, the instances
+//! fired for missed runs record the policy in `misfire_action`.
+use fastjob_components_storage::model::job_info::MisfirePolicy;
+
+use crate::trigger::CronTrigger;
+use crate::SCHEDULE_INTERVAL;
+
+pub const MISFIRE_THRESHOLD: i64 = SCHEDULE_INTERVAL.as_millis() as i64;
+
+/// The missed runs a `CatchUp` job fires when its `max_catch_up` is unset.
+pub const DEFAULT_MAX_CATCH_UP: usize = 10;
+
+/// An every-second job can miss days of runs, the dropped ones are only counted up to this.
+const MAX_COUNTED_DROPS: usize = 100;
+
+/// An instance to create.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Fire {
+    pub expected_trigger_time: i64,
+    /// When the instance is dispatched, now for a missed run.
+    pub trigger_time: i64,
+    /// The policy that fired a missed run, `None` for a run on time.
+    pub action: Option<MisfirePolicy>,
+}
+
+impl Fire {
+    pub fn on_time(trigger_time: i64) -> Self {
+        Self {
+            expected_trigger_time: trigger_time,
+            trigger_time,
+            action: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CronPlan {
+    pub fires: Vec<Fire>,
+    /// How many missed runs weren't fired, at most `MAX_COUNTED_DROPS`.
+    pub dropped: usize,
+    /// `None` when the expression never fires again.
+    pub next_trigger_time: Option<i64>,
+}
+
+/// The instances of a cron job due from `next_trigger_time` until the next schedule round
+/// and the trigger time that round starts at. Only the runs missed by more than
+/// `MISFIRE_THRESHOLD` are subject to `policy`, the others fire as usual.
+pub fn plan_cron(
+    trigger: &CronTrigger,
+    next_trigger_time: i64,
+    now: i64,
+    policy: MisfirePolicy,
+    max_catch_up: usize,
+) -> CronPlan {
+    let missed_before = now - MISFIRE_THRESHOLD;
+    let fire = |expected_trigger_time| Fire {
+        expected_trigger_time,
+        trigger_time: now,
+        action: Some(policy),
+    };
+    let limit = match policy {
+        MisfirePolicy::FireOnce => 1,
+        MisfirePolicy::CatchUp => max_catch_up,
+        MisfirePolicy::Skip => 0,
+    };
+    let mut fires = vec![];
+    let mut dropped = 0;
+    let mut next = Some(next_trigger_time);
+    while let Some(t) = next.filter(|t| *t < missed_before) {
+        if fires.len() < limit {
+            fires.push(fire(t));
+        } else {
+            dropped += 1;
+            if dropped == MAX_COUNTED_DROPS {
+                next = trigger.next_after(missed_before - 1);
+                break;
+            }
+        }
+        next = trigger.next_after(t);
+    }
+
+    let next_round = now + SCHEDULE_INTERVAL.as_millis() as i64;
+    while let Some(t) = next.filter(|t| *t < next_round) {
+        fires.push(Fire::on_time(t));
+        next = trigger.next_after(t);
+    }
+    CronPlan {
+        fires,
+        dropped,
+        next_trigger_time: next,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: i64 = 60_000;
+
+    fn plan(policy: MisfirePolicy, max_catch_up: usize) -> CronPlan {
+        // every minute in utc, the servers were down from 10:00 to 10:05:30.
+        let trigger = CronTrigger::validate("0 * * * * *", Some("UTC"), 0).unwrap();
+        let ten = 36_000 * 1000;
+        plan_cron(&trigger, ten, ten + 5 * MINUTE + 30_000, policy, max_catch_up)
+    }
+
+    #[test]
+    fn t_on_time() {
+        let trigger = CronTrigger::validate("0 * * * * *", Some("UTC"), 0).unwrap();
+        let plan = plan_cron(&trigger, MINUTE, MINUTE - 5000, MisfirePolicy::Skip, 0);
+        assert_eq!(plan.fires, vec![Fire::on_time(MINUTE)]);
+        assert_eq!(plan.next_trigger_time, Some(2 * MINUTE));
+
+        // late by less than the threshold is not a misfire.
+        let plan = plan_cron(&trigger, MINUTE, MINUTE + 5000, MisfirePolicy::Skip, 0);
+        assert_eq!(plan.fires, vec![Fire::on_time(MINUTE)]);
+        assert_eq!(plan.next_trigger_time, Some(2 * MINUTE));
+    }
+
+    #[test]
+    fn t_misfire() {
+        let ten = 36_000 * 1000;
+        let now = ten + 5 * MINUTE + 30_000;
+        let missed = |i: i64| Fire {
+            expected_trigger_time: ten + i * MINUTE,
+            trigger_time: now,
+            action: None,
+        };
+
+        let fire_once = plan(MisfirePolicy::FireOnce, 0);
+        assert_eq!(
+            fire_once.fires,
+            vec![Fire { action: Some(MisfirePolicy::FireOnce), ..missed(0) }]
+        );
+        assert_eq!(fire_once.dropped, 5);
+        assert_eq!(fire_once.next_trigger_time, Some(ten + 6 * MINUTE));
+
+        let catch_up = plan(MisfirePolicy::CatchUp, 4);
+        let expected: Vec<Fire> = (0..4)
+            .map(|i| Fire { action: Some(MisfirePolicy::CatchUp), ..missed(i) })
+            .collect();
+        assert_eq!(catch_up.fires, expected);
+        assert_eq!(catch_up.dropped, 2);
+
+        let skip = plan(MisfirePolicy::Skip, 4);
+        assert!(skip.fires.is_empty());
+        assert_eq!(skip.dropped, 6);
+        assert_eq!(skip.next_trigger_time, Some(ten + 6 * MINUTE));
+    }
+
+    #[test]
+    fn t_every_second() {
+        let trigger = CronTrigger::validate("* * * * * *", Some("UTC"), 0).unwrap();
+        let now = 100_000;
+        let on_time = |from: i64, to: i64| -> Vec<Fire> {
+            (from..to).step_by(1000).map(Fire::on_time).collect()
+        };
+
+        // every run until the next round fires, the late ones included.
+        let plan = plan_cron(&trigger, now - 2000, now, MisfirePolicy::Skip, 0);
+        assert_eq!(plan.fires, on_time(98_000, 110_000));
+        assert_eq!(plan.dropped, 0);
+        assert_eq!(plan.next_trigger_time, Some(110_000));
+
+        // only the runs older than the threshold are missed.
+        let plan = plan_cron(&trigger, now - 15_000, now, MisfirePolicy::FireOnce, 0);
+        let mut expected = vec![Fire {
+            expected_trigger_time: 85_000,
+            trigger_time: now,
+            action: Some(MisfirePolicy::FireOnce),
+        }];
+        expected.extend(on_time(90_000, 110_000));
+        assert_eq!(plan.fires, expected);
+        assert_eq!(plan.dropped, 4);
+        assert_eq!(plan.next_trigger_time, Some(110_000));
+    }
+}
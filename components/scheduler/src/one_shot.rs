@@ -0,0 +1,74 @@
+//! The fire time of a OneShot job, its `time_expression` is one of:
+//! - `1633050000000`, milliseconds since the epoch,
+//! - `2021-10-01T09:00:00+08:00`, an RFC 3339 date time,
+//! - `2021-10-01 09:00:00`, a wall time in the job's time zone, the server's when unset,
+//! - `+30000`, a delay in milliseconds from when the job is saved.
+//!
+//! The fire time is resolved when the job is saved and kept in `next_trigger_time`, so a
+//! server taking over the app after a failover fires the job at the same time. Once its
+//! instance is created the job is `Finished`.
+use chrono::{DateTime, Local, NaiveDateTime};
+
+use crate::error::{self, Result};
+use crate::trigger::{parse_time_zone, resolve};
+
+/// The fire time of `expression` saved at `now`, a time already past is rejected.
+pub fn fire_time(expression: &str, time_zone: Option<&str>, now: i64) -> Result<i64> {
+    let expression = expression.trim();
+    let invalid = |reason: &str| {
+        error::InvalidFireTime {
+            expression,
+            reason,
+        }
+        .fail()
+    };
+    let fire_time = if let Some(delay) = expression.strip_prefix('+') {
+        match delay.trim().parse::<i64>() {
+            Ok(delay) if delay >= 0 => now.saturating_add(delay),
+            _ => return invalid("the delay isn't a number of milliseconds"),
+        }
+    } else if let Ok(millis) = expression.parse::<i64>() {
+        millis
+    } else if let Ok(t) = DateTime::parse_from_rfc3339(expression) {
+        t.timestamp_millis()
+    } else if let Ok(local) = NaiveDateTime::parse_from_str(expression, "%Y-%m-%d %H:%M:%S") {
+        match parse_time_zone(time_zone)? {
+            Some(tz) => resolve(&tz, local),
+            None => resolve(&Local, local),
+        }
+    } else {
+        return invalid("neither a time nor a delay");
+    };
+    if fire_time < now {
+        return invalid("the time is past");
+    }
+    Ok(fire_time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_fire_time() {
+        let now = 1_633_050_000_000;
+        assert_eq!(fire_time("+30000", None, now).unwrap(), now + 30_000);
+        assert_eq!(fire_time(" 1633053600000 ", None, now).unwrap(), now + 3_600_000);
+        assert_eq!(
+            fire_time("2021-10-01T10:00:00+08:00", Some("UTC"), now).unwrap(),
+            now + 3_600_000
+        );
+        assert_eq!(
+            fire_time("2021-10-01 10:00:00", Some("Asia/Shanghai"), now).unwrap(),
+            now + 3_600_000
+        );
+
+        let invalid = |expression: &str| fire_time(expression, None, now).unwrap_err().to_string();
+        assert_eq!(
+            invalid("+-5"),
+            "Invalid fire time +-5: the delay isn't a number of milliseconds."
+        );
+        assert_eq!(invalid("tomorrow"), "Invalid fire time tomorrow: neither a time nor a delay.");
+        assert_eq!(invalid("1000"), "Invalid fire time 1000: the time is past.");
+    }
+}
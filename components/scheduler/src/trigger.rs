@@ -0,0 +1,333 @@
+//! Next fire times of cron expressions in the time zone of a job.
+//!
+//! The expression is matched against the wall clock of the zone, then each match is
+//! resolved to an instant. A wall time skipped by a DST gap fires shifted by the length of
+//! the gap, a wall time repeated by a DST overlap fires once, at its first occurrence.
+//! With a calendar, the matches on excluded days are adjusted by the job's `CalendarPolicy`
+//! before they are resolved.
+use chrono::{DateTime, Duration, LocalResult, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+use fastjob_components_storage::model::job_info::CalendarPolicy;
+use snafu::OptionExt;
+use std::str::FromStr;
+
+use crate::calendar::{last_of_month, Calendar, MAX_EXCLUDED_DAYS};
+use crate::error::{self, Result};
+
+/// The most matches looked at for a fire time with a calendar, an excluded day costs one.
+const MAX_SCANNED_MATCHES: usize = 10_000;
+
+/// The zone of a job, `None` for the time zone of the server.
+pub fn parse_time_zone(time_zone: Option<&str>) -> Result<Option<Tz>> {
+    match time_zone.map(str::trim).filter(|tz| !tz.is_empty()) {
+        Some(tz) => tz.parse::<Tz>().map(Some).map_err(|_| {
+            error::InvalidTimeZone {
+                time_zone: tz.to_string(),
+            }
+            .build()
+        }),
+        None => Ok(None),
+    }
+}
+
+/// The most fire times a preview returns.
+pub const MAX_PREVIEW_COUNT: usize = 100;
+
+/// A validated cron expression and the zone it is evaluated in.
+#[derive(Clone, Debug)]
+pub struct CronTrigger {
+    expression: String,
+    schedule: Schedule,
+    time_zone: Option<Tz>,
+    calendar: Option<(Calendar, CalendarPolicy)>,
+}
+
+impl CronTrigger {
+    /// Parse the expression and the zone of a job, an expression that never fires after
+    /// `now` is rejected as well.
+    pub fn validate(expression: &str, time_zone: Option<&str>, now: i64) -> Result<Self> {
+        let trigger = Self::parse(expression, time_zone)?;
+        trigger.first_after(now)?;
+        Ok(trigger)
+    }
+
+    pub fn parse(expression: &str, time_zone: Option<&str>) -> Result<Self> {
+        Ok(Self {
+            expression: expression.to_string(),
+            schedule: parse_cron(expression)?,
+            time_zone: parse_time_zone(time_zone)?,
+            calendar: None,
+        })
+    }
+
+    pub fn with_calendar(mut self, calendar: Calendar, policy: CalendarPolicy) -> Self {
+        self.calendar = Some((calendar, policy));
+        self
+    }
+
+    /// The first fire time strictly after `after`, in milliseconds.
+    pub fn next_after(&self, after: i64) -> Option<i64> {
+        match (&self.time_zone, &self.calendar) {
+            (Some(tz), None) => next_after(&self.schedule, tz, after),
+            (None, None) => next_after(&self.schedule, &chrono::Local, after),
+            (Some(tz), Some(calendar)) => next_allowed_after(&self.schedule, tz, calendar, after),
+            (None, Some(calendar)) => {
+                next_allowed_after(&self.schedule, &chrono::Local, calendar, after)
+            }
+        }
+    }
+
+    /// The first fire time after `now`, an error when it never fires again.
+    pub fn first_after(&self, now: i64) -> Result<i64> {
+        self.next_after(now).context(error::InvalidCronExpression {
+            expression: &self.expression,
+            reason: "it never fires again",
+        })
+    }
+
+    /// The next `count` fire times after `after`, at most `MAX_PREVIEW_COUNT`.
+    pub fn preview(&self, mut after: i64, count: usize) -> Vec<i64> {
+        let count = count.min(MAX_PREVIEW_COUNT);
+        let mut times = Vec::with_capacity(count);
+        while times.len() < count {
+            match self.next_after(after) {
+                Some(next) => {
+                    times.push(next);
+                    after = next;
+                }
+                None => break,
+            }
+        }
+        times
+    }
+}
+
+fn parse_cron(expression: &str) -> Result<Schedule> {
+    Schedule::from_str(expression).map_err(|e| {
+        error::InvalidCronExpression {
+            expression,
+            reason: e.to_string(),
+        }
+        .build()
+    })
+}
+
+/// The first fire time of `expression` strictly after `after`, in milliseconds.
+pub fn next_trigger_time(
+    expression: &str,
+    time_zone: Option<&str>,
+    after: i64,
+) -> Result<Option<i64>> {
+    Ok(CronTrigger::parse(expression, time_zone)?.next_after(after))
+}
+
+fn next_after<Z: TimeZone>(schedule: &Schedule, tz: &Z, after: i64) -> Option<i64> {
+    let after_local = tz.timestamp_millis(after).naive_local();
+    // the schedule walks the wall clock, utc is only a zone without transitions.
+    let wall_clock: DateTime<Utc> = DateTime::from_utc(after_local, Utc);
+    schedule
+        .after(&wall_clock)
+        .map(|matched| resolve(tz, matched.naive_utc()))
+        // the repeated wall times of an overlap already fired on their first occurrence.
+        .find(|instant| *instant > after)
+}
+
+fn next_allowed_after<Z: TimeZone>(
+    schedule: &Schedule,
+    tz: &Z,
+    (calendar, policy): &(Calendar, CalendarPolicy),
+    after: i64,
+) -> Option<i64> {
+    let end_of_day = |day: NaiveDate| day.and_hms(23, 59, 59);
+    let mut cursor = tz.timestamp_millis(after).naive_local();
+    if *policy == CalendarPolicy::NextAllowedDay {
+        // the matches on the excluded days just before may be shifted past `after`.
+        let mut day = cursor.date();
+        for _ in 0..MAX_EXCLUDED_DAYS {
+            if calendar.is_allowed(day.pred()) {
+                break;
+            }
+            day = day.pred();
+        }
+        cursor = cursor.min(end_of_day(day.pred()));
+    }
+    // a shifted match can be beaten by a later match shifted to an earlier time of the day.
+    let mut best: Option<(NaiveDateTime, i64)> = None;
+    for _ in 0..MAX_SCANNED_MATCHES {
+        let wall_clock: DateTime<Utc> = DateTime::from_utc(cursor, Utc);
+        let matched = match schedule.after(&wall_clock).next() {
+            Some(matched) => matched.naive_utc(),
+            None => break,
+        };
+        if best.map_or(false, |(wall, _)| matched > wall) {
+            break;
+        }
+        let day = matched.date();
+        let (wall, next_cursor) = match policy {
+            CalendarPolicy::LastAllowedDayOfMonth => match calendar.last_allowed_of_month(day) {
+                Some(last) if last == day => (Some(matched), matched),
+                Some(last) if last > day => (None, end_of_day(last.pred())),
+                _ => (None, end_of_day(last_of_month(day))),
+            },
+            _ if calendar.is_allowed(day) => (Some(matched), matched),
+            CalendarPolicy::Skip => (None, end_of_day(day)),
+            CalendarPolicy::NextAllowedDay => (
+                calendar
+                    .next_allowed(day)
+                    .map(|allowed| allowed.and_time(matched.time())),
+                matched,
+            ),
+        };
+        cursor = next_cursor;
+        if let Some(wall) = wall {
+            let instant = resolve(tz, wall);
+            if instant > after {
+                if best.map_or(true, |(_, b)| instant < b) {
+                    best = Some((wall, instant));
+                }
+                if wall == matched {
+                    // later matches can't be earlier.
+                    break;
+                }
+                // nor the later matches of the day shifted along with this one.
+                cursor = end_of_day(day);
+            }
+        }
+    }
+    best.map(|(_, instant)| instant)
+}
+
+/// The instant of a wall time, see the module doc for gaps and overlaps.
+pub(crate) fn resolve<Z: TimeZone>(tz: &Z, local: NaiveDateTime) -> i64 {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(t) => t.timestamp_millis(),
+        LocalResult::Ambiguous(earliest, _) => earliest.timestamp_millis(),
+        LocalResult::None => {
+            // zones never change twice a day, so a day earlier has the offset before the gap.
+            let before = tz
+                .offset_from_utc_datetime(&(local - Duration::days(1)))
+                .fix();
+            (local - before).timestamp_millis()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::America::New_York;
+    use fastjob_components_storage::model::calendar_info::CalendarInfo;
+
+    fn at(tz: &Tz, y: i32, m: u32, d: u32, h: u32, min: u32) -> i64 {
+        tz.ymd(y, m, d).and_hms(h, min, 0).timestamp_millis()
+    }
+
+    fn fires(expression: &str, tz: &str, mut after: i64, n: usize) -> Vec<i64> {
+        let mut times = vec![];
+        for _ in 0..n {
+            after = next_trigger_time(expression, Some(tz), after).unwrap().unwrap();
+            times.push(after);
+        }
+        times
+    }
+
+    #[test]
+    fn t_time_zone() {
+        let after = Utc.ymd(2021, 6, 1).and_hms(0, 0, 0).timestamp_millis();
+        // 09:00 in Tokyo is exactly `after`, the next run is a day later.
+        let tokyo = fires("0 0 9 * * *", "Asia/Tokyo", after, 1);
+        assert_eq!(tokyo, vec![Utc.ymd(2021, 6, 2).and_hms(0, 0, 0).timestamp_millis()]);
+        let ny = fires("0 0 9 * * *", "America/New_York", after, 1);
+        assert_eq!(ny, vec![Utc.ymd(2021, 6, 1).and_hms(13, 0, 0).timestamp_millis()]);
+
+        assert!(parse_time_zone(Some(" ")).unwrap().is_none());
+    }
+
+    #[test]
+    fn t_validate() {
+        let now = Utc.ymd(2021, 6, 1).and_hms(0, 0, 0).timestamp_millis();
+        let invalid = |expression: &str, time_zone: Option<&str>| {
+            CronTrigger::validate(expression, time_zone, now)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            invalid("0 0 9 * * *", Some("Mars/Olympus")),
+            "Invalid time zone Mars/Olympus."
+        );
+        assert!(invalid("every day", None).starts_with("Invalid cron expression every day: "));
+        assert_eq!(
+            invalid("0 0 9 1 1 * 2020", None),
+            "Invalid cron expression 0 0 9 1 1 * 2020: it never fires again."
+        );
+
+        let trigger = CronTrigger::validate("0 0 9 * * Mon", Some("Europe/London"), now).unwrap();
+        let day = 24 * 3_600_000;
+        let first = Utc.ymd(2021, 6, 7).and_hms(8, 0, 0).timestamp_millis();
+        assert_eq!(trigger.preview(now, 3), vec![first, first + 7 * day, first + 14 * day]);
+        assert_eq!(trigger.preview(now, 1000).len(), MAX_PREVIEW_COUNT);
+    }
+
+    #[test]
+    fn t_calendar() {
+        let calendar = || {
+            Calendar::from_info(&CalendarInfo {
+                holidays: Some("2021-12-24,2021-12-31".to_string()),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let trigger = |expression: &str, policy| {
+            CronTrigger::parse(expression, Some("UTC"))
+                .unwrap()
+                .with_calendar(calendar(), policy)
+        };
+        let at = |d: u32, h: u32| Utc.ymd(2021, 12, d).and_hms(h, 0, 0).timestamp_millis();
+        let after = at(22, 0);
+
+        let skip = trigger("0 0 18 * * *", CalendarPolicy::Skip);
+        assert_eq!(skip.preview(after, 4), vec![at(22, 18), at(23, 18), at(27, 18), at(28, 18)]);
+
+        // the holiday and weekend runs move to monday, once.
+        let next = trigger("0 0 9,18 * * Fri,Sat", CalendarPolicy::NextAllowedDay);
+        let january = Utc.ymd(2022, 1, 3).and_hms(9, 0, 0).timestamp_millis();
+        assert_eq!(next.preview(after, 3), vec![at(27, 9), at(27, 18), january]);
+
+        let last = trigger("0 0 18 * * *", CalendarPolicy::LastAllowedDayOfMonth);
+        let january = Utc.ymd(2022, 1, 31).and_hms(18, 0, 0).timestamp_millis();
+        assert_eq!(last.preview(after, 2), vec![at(30, 18), january]);
+    }
+
+    #[test]
+    fn t_dst() {
+        // 2021-03-14 02:00 EST jumps to 03:00 EDT, the 02:30 run shifts to 03:30.
+        let after = at(&New_York, 2021, 3, 13, 12, 0);
+        let times = fires("0 30 2 * * *", "America/New_York", after, 3);
+        assert_eq!(
+            times,
+            vec![
+                at(&New_York, 2021, 3, 14, 3, 30),
+                at(&New_York, 2021, 3, 15, 2, 30),
+                at(&New_York, 2021, 3, 16, 2, 30),
+            ]
+        );
+
+        // 2021-11-07 02:00 EDT falls back to 01:00 EST, 01:30 runs only once.
+        let after = at(&New_York, 2021, 11, 6, 12, 0);
+        let times = fires("0 30 1 * * *", "America/New_York", after, 2);
+        let first = Utc.ymd(2021, 11, 7).and_hms(5, 30, 0).timestamp_millis();
+        assert_eq!(times, vec![first, at(&New_York, 2021, 11, 8, 1, 30)]);
+
+        // a half-hourly job doesn't run the repeated 01:00 and 01:30 again.
+        let times = fires("0 0/30 * * * *", "America/New_York", first, 3);
+        assert_eq!(
+            times,
+            vec![
+                Utc.ymd(2021, 11, 7).and_hms(7, 0, 0).timestamp_millis(),
+                Utc.ymd(2021, 11, 7).and_hms(7, 30, 0).timestamp_millis(),
+                Utc.ymd(2021, 11, 7).and_hms(8, 0, 0).timestamp_millis(),
+            ]
+        );
+    }
+}
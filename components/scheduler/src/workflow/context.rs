@@ -0,0 +1,58 @@
+//! The context shared by the nodes of a workflow instance.
+//!
+//! Workers append key/values to `workflow_instance_info.wf_context`, a json object of
+//! strings, and every node instance created afterwards receives the merged context as its
+//! `instance_params`.
+use std::collections::{BTreeMap, HashMap};
+
+/// The maximum length of the serialized context, appends that would exceed it are dropped.
+pub const MAX_WF_CONTEXT_LENGTH: usize = 16 * 1024;
+
+pub const EMPTY_WF_CONTEXT: &str = "{}";
+
+/// Merge `appended` into the context, a key appended again keeps the latest value.
+/// `None` when the merged context would be longer than `max_length`.
+pub fn merge_context(
+    current: Option<&str>,
+    appended: &HashMap<String, String>,
+    max_length: usize,
+) -> Option<String> {
+    let mut context = parse_context(current);
+    for (k, v) in appended {
+        context.insert(k.clone(), v.clone());
+    }
+    let merged = serde_json::to_string(&context).ok()?;
+    if merged.len() > max_length {
+        return None;
+    }
+    Some(merged)
+}
+
+/// The key/values of a context, a missing or malformed context is empty.
+pub fn parse_context(context: Option<&str>) -> BTreeMap<String, String> {
+    context
+        .and_then(|c| serde_json::from_str(c).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_merge_context() {
+        let mut appended = HashMap::new();
+        appended.insert("partition".to_string(), "dt=2021-06-01".to_string());
+        appended.insert("rows".to_string(), "42".to_string());
+        let merged = merge_context(None, &appended, MAX_WF_CONTEXT_LENGTH).unwrap();
+        assert_eq!(merged, r#"{"partition":"dt=2021-06-01","rows":"42"}"#);
+
+        let mut appended = HashMap::new();
+        appended.insert("rows".to_string(), "43".to_string());
+        let merged = merge_context(Some(&merged), &appended, MAX_WF_CONTEXT_LENGTH).unwrap();
+        assert_eq!(merged, r#"{"partition":"dt=2021-06-01","rows":"43"}"#);
+
+        assert_eq!(merge_context(Some(&merged), &appended, 10), None);
+        assert_eq!(parse_context(Some("not json")).len(), 0);
+    }
+}
@@ -1 +1,879 @@
+//! DAG workflows, a node runs its job once every upstream node finished.
+//!
+//! `workflow_info.pedag` holds the graph, its nodes refer to `workflow_node_info` by id.
+//! A workflow instance copies the graph into `workflow_instance_info.dag` along with the
+//! instance, status and result of every node, each node finishing advances it from there:
+//! the nodes whose upstream nodes all passed get an instance, a failed node fails the
+//! workflow unless it is `skip_when_failed`, disabled nodes are skipped. Nodes pass data
+//! downstream through the workflow context, see `context`.
+//!
+//! The engine only creates the node instances, dispatching them is left to the caller.
+use crate::error::{self, Result};
+use context::{merge_context, EMPTY_WF_CONTEXT, MAX_WF_CONTEXT_LENGTH};
+use fastjob_components_storage::model::instance_info::{InstanceInfo, InstanceStatus};
+use fastjob_components_storage::model::job_info::JobInfo;
+use fastjob_components_storage::model::workflow_info::WorkflowInfo;
+use fastjob_components_storage::model::workflow_instance_info::{
+    WorkflowInstanceInfo, WorkflowInstanceStatus,
+};
+use fastjob_components_storage::Storage;
+use fastjob_components_utils::id_generator::{self, GeneratorTyp};
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, ResultExt};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::sync::Arc;
 
+pub mod context;
+
+/// How often an update of a workflow instance is retried against concurrent ones.
+const MAX_WRITE_ATTEMPTS: usize = 16;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Dag {
+    pub nodes: Vec<DagNode>,
+    #[serde(default)]
+    pub edges: Vec<DagEdge>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DagNode {
+    /// The id of the `workflow_node_info`.
+    pub node_id: u64,
+    pub job_id: Option<u64>,
+    pub node_name: Option<String>,
+    pub node_params: Option<String>,
+    #[serde(default = "enabled")]
+    pub enable: bool,
+    #[serde(default)]
+    pub skip_when_failed: bool,
+    pub instance_id: Option<u64>,
+    /// An `InstanceStatus`, unset until the node is ready.
+    pub status: Option<u32>,
+    pub result: Option<String>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct DagEdge {
+    pub from: u64,
+    pub to: u64,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum NodeState {
+    /// Its upstream nodes haven't all passed yet.
+    Pending,
+    Running,
+    /// Succeeded, skipped or failed with `skip_when_failed`.
+    Passed,
+    /// Failed, so the workflow fails.
+    Blocked,
+}
+
+impl DagNode {
+    fn state(&self) -> NodeState {
+        match self.status.and_then(|s| InstanceStatus::try_from(s).ok()) {
+            None => NodeState::Pending,
+            Some(InstanceStatus::Success) => NodeState::Passed,
+            Some(InstanceStatus::Failed)
+            | Some(InstanceStatus::Canceled)
+            | Some(InstanceStatus::Stopped) => {
+                if self.skip_when_failed || !self.enable {
+                    NodeState::Passed
+                } else {
+                    NodeState::Blocked
+                }
+            }
+            Some(_) => NodeState::Running,
+        }
+    }
+
+    fn name(&self) -> String {
+        self.node_name
+            .clone()
+            .unwrap_or_else(|| self.node_id.to_string())
+    }
+}
+
+impl Dag {
+    /// Parse and validate a DAG.
+    pub fn parse(dag: &str) -> Result<Dag> {
+        let dag: Dag = serde_json::from_str(dag).map_err(|e| error::SchedError::InvalidDag {
+            reason: e.to_string(),
+        })?;
+        dag.validate()?;
+        Ok(dag)
+    }
+
+    /// The DAG has nodes, unique node ids, edges between its nodes and no cycle.
+    pub fn validate(&self) -> Result<()> {
+        if self.nodes.is_empty() {
+            return error::InvalidDag { reason: "the DAG has no node" }.fail();
+        }
+        let mut ids = HashSet::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            if !ids.insert(node.node_id) {
+                return error::InvalidDag {
+                    reason: format!("node {} appears twice", node.node_id),
+                }
+                .fail();
+            }
+        }
+        for edge in &self.edges {
+            if !ids.contains(&edge.from) || !ids.contains(&edge.to) {
+                return error::InvalidDag {
+                    reason: format!("edge {} -> {} refers to an unknown node", edge.from, edge.to),
+                }
+                .fail();
+            }
+        }
+        if self.topological_order().len() != self.nodes.len() {
+            return error::InvalidDag { reason: "the DAG has a cycle" }.fail();
+        }
+        Ok(())
+    }
+
+    /// The node ids in topological order, the nodes on a cycle are missing.
+    fn topological_order(&self) -> Vec<u64> {
+        let mut in_degree: HashMap<u64, usize> =
+            self.nodes.iter().map(|n| (n.node_id, 0)).collect();
+        for edge in &self.edges {
+            *in_degree.entry(edge.to).or_default() += 1;
+        }
+        let mut queue: VecDeque<u64> = self
+            .nodes
+            .iter()
+            .map(|n| n.node_id)
+            .filter(|id| in_degree[id] == 0)
+            .collect();
+        let mut order = Vec::with_capacity(self.nodes.len());
+        while let Some(id) = queue.pop_front() {
+            order.push(id);
+            for edge in self.edges.iter().filter(|e| e.from == id) {
+                let degree = in_degree.get_mut(&edge.to).unwrap();
+                *degree -= 1;
+                if *degree == 0 {
+                    queue.push_back(edge.to);
+                }
+            }
+        }
+        order
+    }
+
+    fn node(&self, node_id: u64) -> Option<&DagNode> {
+        self.nodes.iter().find(|n| n.node_id == node_id)
+    }
+
+    fn node_mut(&mut self, node_id: u64) -> Option<&mut DagNode> {
+        self.nodes.iter_mut().find(|n| n.node_id == node_id)
+    }
+
+    /// The pending nodes whose upstream nodes all passed.
+    pub fn ready_nodes(&self) -> Vec<u64> {
+        self.nodes
+            .iter()
+            .filter(|node| node.state() == NodeState::Pending)
+            .filter(|node| {
+                self.edges
+                    .iter()
+                    .filter(|e| e.to == node.node_id)
+                    .all(|e| self.node(e.from).map_or(false, |n| n.state() == NodeState::Passed))
+            })
+            .map(|node| node.node_id)
+            .collect()
+    }
+
+    /// The final status of the workflow instance, `None` while nodes can still run.
+    pub fn outcome(&self) -> Option<WorkflowInstanceStatus> {
+        let states: Vec<NodeState> = self.nodes.iter().map(DagNode::state).collect();
+        if states.contains(&NodeState::Blocked) {
+            Some(WorkflowInstanceStatus::Failed)
+        } else if states.iter().all(|s| *s == NodeState::Passed) {
+            Some(WorkflowInstanceStatus::Success)
+        } else {
+            None
+        }
+    }
+
+    fn summary(&self, status: WorkflowInstanceStatus) -> String {
+        match self.nodes.iter().find(|n| n.state() == NodeState::Blocked) {
+            Some(node) if status == WorkflowInstanceStatus::Failed => format!(
+                "node {} failed: {}",
+                node.name(),
+                node.result.as_deref().unwrap_or_default()
+            ),
+            _ => format!("{} nodes finished", self.nodes.len()),
+        }
+    }
+}
+
+pub struct WorkflowEngine<S: Storage> {
+    storage: Arc<S>,
+}
+
+impl<S: Storage> Clone for WorkflowEngine<S> {
+    fn clone(&self) -> Self {
+        Self {
+            storage: self.storage.clone(),
+        }
+    }
+}
+
+impl<S: Storage> WorkflowEngine<S> {
+    pub fn new(storage: Arc<S>) -> Self {
+        Self { storage }
+    }
+
+    /// Create an instance of the workflow and the instances of its first nodes, returns
+    /// the node instances to dispatch. An invalid DAG fails the workflow instance.
+    pub fn start(
+        &self,
+        workflow: &WorkflowInfo,
+        expected_trigger_time: Option<i64>,
+    ) -> Result<Vec<(JobInfo, u64)>> {
+        let wf_instance_id =
+            id_generator::generator_id(GeneratorTyp::Workflow).context(error::IdGeneratorFailed)?;
+        let now = chrono::Local::now().timestamp_millis();
+        let mut wf_instance = WorkflowInstanceInfo {
+            app_id: workflow.app_id,
+            workflow_id: workflow.id,
+            wf_instance_id: Some(wf_instance_id),
+            status: Some(WorkflowInstanceStatus::Running.into()),
+            expected_trigger_time,
+            actual_trigger_time: Some(now),
+            wf_context: Some(EMPTY_WF_CONTEXT.to_string()),
+            version: Some(0),
+            ..Default::default()
+        };
+        let dag = match self.build_dag(workflow) {
+            Ok(dag) => dag,
+            Err(e) => {
+                warn!("[Workflow] workflow {} has an invalid DAG: {}", workflow.id.unwrap_or_default(), e);
+                wf_instance.dag = workflow.pedag.clone();
+                wf_instance.status = Some(WorkflowInstanceStatus::Failed.into());
+                wf_instance.result = Some(e.to_string());
+                wf_instance.finished_time = Some(now);
+                self.storage
+                    .save(wf_instance)
+                    .context(error::SchedStorageError)?;
+                return Ok(vec![]);
+            }
+        };
+        wf_instance.dag = Some(serde_json::to_string(&dag).context(error::DagEncodeFailed)?);
+        self.storage
+            .save(wf_instance)
+            .context(error::SchedStorageError)?;
+        // advanced from the stored row, which has the id and version.
+        self.retry(wf_instance_id, |wf_instance| self.advance(wf_instance, dag.clone()))
+    }
+
+    /// Record the finished instance of a node and create the instances of the nodes that
+    /// became ready, returns them to dispatch.
+    pub fn node_finished(
+        &self,
+        wf_instance_id: u64,
+        instance_id: u64,
+        status: InstanceStatus,
+        result: &str,
+    ) -> Result<Vec<(JobInfo, u64)>> {
+        self.retry(wf_instance_id, |wf_instance| {
+            if is_finished(&wf_instance) {
+                return Ok(Some(vec![]));
+            }
+            let mut dag = Dag::parse(wf_instance.dag.as_deref().unwrap_or_default())?;
+            match dag
+                .nodes
+                .iter_mut()
+                .find(|n| n.instance_id == Some(instance_id))
+            {
+                Some(node) => {
+                    node.status = Some(status.into());
+                    node.result = Some(result.to_string());
+                }
+                None => {
+                    warn!("[Workflow] workflow instance {} has no node of instance {}.", wf_instance_id, instance_id);
+                    return Ok(Some(vec![]));
+                }
+            }
+            self.advance(wf_instance, dag)
+        })
+    }
+
+    /// Merge key/values a node appended into the context of the workflow instance, returns
+    /// false when they were dropped because the context would grow past its limit.
+    pub fn append_context(
+        &self,
+        wf_instance_id: u64,
+        appended: &HashMap<String, String>,
+    ) -> Result<bool> {
+        if appended.is_empty() {
+            return Ok(true);
+        }
+        self.retry(wf_instance_id, |mut wf_instance| {
+            if is_finished(&wf_instance) {
+                return Ok(Some(true));
+            }
+            let merged = match merge_context(
+                wf_instance.wf_context.as_deref(),
+                appended,
+                MAX_WF_CONTEXT_LENGTH,
+            ) {
+                Some(merged) => merged,
+                None => {
+                    warn!("[Workflow] the context of workflow instance {} would exceed {} bytes, drop the appended {:?}.",
+                          wf_instance_id,
+                          MAX_WF_CONTEXT_LENGTH,
+                          appended.keys());
+                    return Ok(Some(false));
+                }
+            };
+            wf_instance.wf_context = Some(merged);
+            Ok(if self.write(&wf_instance)? { Some(true) } else { None })
+        })
+    }
+
+    /// Run the failed nodes of a failed or stopped workflow instance again, the nodes they
+    /// blocked follow once they pass. Returns the node instances to dispatch.
+    pub fn retry_failed(&self, wf_instance_id: u64) -> Result<Vec<(JobInfo, u64)>> {
+        self.retry(wf_instance_id, |mut wf_instance| {
+            if !matches!(
+                status_of(&wf_instance),
+                Some(WorkflowInstanceStatus::Failed) | Some(WorkflowInstanceStatus::Stopped)
+            ) {
+                return error::InvalidWorkflowOperation {
+                    wf_instance_id,
+                    reason: "only a failed or stopped workflow instance can be retried",
+                }
+                .fail();
+            }
+            let mut dag = Dag::parse(wf_instance.dag.as_deref().unwrap_or_default())?;
+            for node in dag.nodes.iter_mut().filter(|n| {
+                n.enable
+                    && n.status
+                        .and_then(|s| InstanceStatus::try_from(s).ok())
+                        .map_or(false, is_unsuccessful)
+            }) {
+                info!("[Workflow] retry node {} of workflow instance {}.", node.name(), wf_instance_id);
+                node.instance_id = None;
+                node.status = None;
+                node.result = None;
+            }
+            reopen(&mut wf_instance);
+            self.advance(wf_instance, dag)
+        })
+    }
+
+    /// Mark a failed node as succeeded, along with its instance, so the nodes it blocked can
+    /// run. Returns the node instances to dispatch.
+    pub fn mark_node_success(
+        &self,
+        wf_instance_id: u64,
+        node_id: u64,
+    ) -> Result<Vec<(JobInfo, u64)>> {
+        self.retry(wf_instance_id, |mut wf_instance| {
+            if status_of(&wf_instance) == Some(WorkflowInstanceStatus::Stopped) {
+                return error::InvalidWorkflowOperation {
+                    wf_instance_id,
+                    reason: "the workflow instance is stopped, retry it instead",
+                }
+                .fail();
+            }
+            let mut dag = Dag::parse(wf_instance.dag.as_deref().unwrap_or_default())?;
+            let node = match dag.node_mut(node_id) {
+                Some(node) => node,
+                None => {
+                    return error::InvalidWorkflowOperation {
+                        wf_instance_id,
+                        reason: format!("node {} doesn't exist", node_id),
+                    }
+                    .fail()
+                }
+            };
+            let failed = node
+                .status
+                .and_then(|s| InstanceStatus::try_from(s).ok())
+                .map_or(false, is_unsuccessful);
+            if !failed {
+                return error::InvalidWorkflowOperation {
+                    wf_instance_id,
+                    reason: format!("node {} hasn't failed", node.name()),
+                }
+                .fail();
+            }
+            let result = "marked success manually";
+            node.status = Some(InstanceStatus::Success.into());
+            node.result = Some(result.to_string());
+            if let Some(instance_id) = node.instance_id {
+                self.update_node_instance(instance_id, InstanceStatus::Success, result)?;
+            }
+            if status_of(&wf_instance) == Some(WorkflowInstanceStatus::Failed) {
+                reopen(&mut wf_instance);
+            }
+            info!("[Workflow] node {} of workflow instance {} is marked success.", node_id, wf_instance_id);
+            self.advance(wf_instance, dag)
+        })
+    }
+
+    /// Stop an unfinished workflow instance and every node instance that hasn't finished,
+    /// returns the ids of the stopped node instances.
+    pub fn stop(&self, wf_instance_id: u64) -> Result<Vec<u64>> {
+        let stopped = self.retry(wf_instance_id, |mut wf_instance| {
+            if is_finished(&wf_instance) {
+                return error::InvalidWorkflowOperation {
+                    wf_instance_id,
+                    reason: "the workflow instance has finished",
+                }
+                .fail();
+            }
+            let mut dag = Dag::parse(wf_instance.dag.as_deref().unwrap_or_default())?;
+            let result = "stopped manually";
+            let mut stopped = vec![];
+            for node in dag
+                .nodes
+                .iter_mut()
+                .filter(|n| n.state() == NodeState::Running)
+            {
+                node.status = Some(InstanceStatus::Stopped.into());
+                node.result = Some(result.to_string());
+                if let Some(instance_id) = node.instance_id {
+                    self.update_node_instance(instance_id, InstanceStatus::Stopped, result)?;
+                    stopped.push(instance_id);
+                }
+            }
+            wf_instance.status = Some(WorkflowInstanceStatus::Stopped.into());
+            wf_instance.result = Some(result.to_string());
+            wf_instance.finished_time = Some(chrono::Local::now().timestamp_millis());
+            Ok(if self.save_dag(wf_instance, &dag)? { Some(stopped) } else { None })
+        })?;
+        info!("[Workflow] workflow instance {} is stopped, stopped node instances: {:?}.", wf_instance_id, stopped);
+        Ok(stopped)
+    }
+
+    fn update_node_instance(&self, instance_id: u64, status: InstanceStatus, result: &str) -> Result<()> {
+        if let Some(mut instance) = self
+            .storage
+            .find_instance_by_id(instance_id)
+            .context(error::SchedStorageError)?
+        {
+            instance.status = Some(status.into());
+            instance.result = Some(result.to_string());
+            instance.finished_time = Some(chrono::Local::now().timestamp_millis());
+            self.storage
+                .update(&mut [instance])
+                .context(error::SchedStorageError)?;
+        }
+        Ok(())
+    }
+
+    /// Catch up the running workflow instances of the apps with the status of their node
+    /// instances, in case a node finishing wasn't recorded. Returns the node instances to
+    /// dispatch.
+    pub fn refresh(&self, app_ids: &[u64]) -> Result<Vec<(JobInfo, u64)>> {
+        let wf_instances = self
+            .storage
+            .find_workflow_instances_by_status(
+                app_ids,
+                vec![
+                    WorkflowInstanceStatus::Waiting.into(),
+                    WorkflowInstanceStatus::Running.into(),
+                ],
+            )
+            .context(error::SchedStorageError)?;
+        let mut created = vec![];
+        for wf_instance in wf_instances {
+            let mut dag = match Dag::parse(wf_instance.dag.as_deref().unwrap_or_default()) {
+                Ok(dag) => dag,
+                Err(e) => {
+                    warn!("[Workflow] workflow instance {} has an invalid DAG: {}", wf_instance.wf_instance_id.unwrap_or_default(), e);
+                    continue;
+                }
+            };
+            let mut changed = !dag.ready_nodes().is_empty() || dag.outcome().is_some();
+            for node in dag
+                .nodes
+                .iter_mut()
+                .filter(|n| n.state() == NodeState::Running)
+            {
+                let instance = match node.instance_id {
+                    Some(id) => self
+                        .storage
+                        .find_instance_by_id(id)
+                        .context(error::SchedStorageError)?,
+                    None => None,
+                };
+                let status = instance
+                    .as_ref()
+                    .and_then(|i| i.status)
+                    .and_then(|s| InstanceStatus::try_from(s).ok());
+                match (instance, status) {
+                    (Some(instance), Some(status))
+                    if status == InstanceStatus::Success || is_unsuccessful(status) =>
+                        {
+                            node.status = Some(status.into());
+                            node.result = instance.result;
+                            changed = true;
+                        }
+                    (None, _) => {
+                        node.status = Some(InstanceStatus::Failed.into());
+                        node.result = Some("the node instance doesn't exist".to_string());
+                        changed = true;
+                    }
+                    _ => {}
+                }
+            }
+            // a workflow instance written meanwhile is caught up by the next refresh.
+            if changed {
+                created.extend(self.advance(wf_instance, dag)?.unwrap_or_default());
+            }
+        }
+        Ok(created)
+    }
+
+    /// Create the instances of the ready nodes, until none is ready, then persist the DAG
+    /// and finish the workflow instance once its outcome is decided. Returns `None` when the
+    /// workflow instance was written since it was read, the created instances are canceled.
+    fn advance(
+        &self,
+        mut wf_instance: WorkflowInstanceInfo,
+        mut dag: Dag,
+    ) -> Result<Option<Vec<(JobInfo, u64)>>> {
+        let wf_instance_id = wf_instance.wf_instance_id.unwrap_or_default();
+        let now = chrono::Local::now().timestamp_millis();
+        let mut created = vec![];
+        while dag.outcome().is_none() {
+            let ready = dag.ready_nodes();
+            if ready.is_empty() {
+                break;
+            }
+            for node_id in ready {
+                let node = dag.node_mut(node_id).unwrap();
+                if !node.enable {
+                    node.status = Some(InstanceStatus::Canceled.into());
+                    node.result = Some("the node is disabled".to_string());
+                    continue;
+                }
+                let job = match node.job_id {
+                    Some(job_id) => self
+                        .storage
+                        .find_job_info_by_id(job_id)
+                        .context(error::SchedStorageError)?,
+                    None => None,
+                };
+                let job = match job {
+                    Some(job) => job,
+                    None => {
+                        node.status = Some(InstanceStatus::Failed.into());
+                        node.result = Some(format!(
+                            "job {} doesn't exist",
+                            node.job_id.unwrap_or_default()
+                        ));
+                        continue;
+                    }
+                };
+                // the node params override the job's, the context becomes the instance params.
+                let instance = InstanceInfo::create(
+                    job.id,
+                    job.app_id,
+                    node.node_params.clone().or_else(|| job.get_job_params()),
+                    wf_instance.wf_context.clone(),
+                    Some(wf_instance_id),
+                    Some(now),
+                )
+                .context(error::IdGeneratorFailed)?;
+                let instance_id = instance.instance_id.unwrap_or_default();
+                self.storage
+                    .save(instance)
+                    .context(error::SchedStorageError)?;
+                node.instance_id = Some(instance_id);
+                node.status = Some(InstanceStatus::WaitingDispatch.into());
+                created.push((job, instance_id));
+            }
+        }
+        if let Some(status) = dag.outcome() {
+            info!("[Workflow] workflow instance {} finished: {:?}", wf_instance_id, status);
+            wf_instance.status = Some(status.into());
+            wf_instance.result = Some(dag.summary(status));
+            wf_instance.finished_time = Some(now);
+        }
+        if !self.save_dag(wf_instance, &dag)? {
+            for (_, instance_id) in created {
+                self.update_node_instance(
+                    instance_id,
+                    InstanceStatus::Canceled,
+                    "the workflow instance was updated concurrently",
+                )?;
+            }
+            return Ok(None);
+        }
+        Ok(Some(created))
+    }
+
+    /// Returns false when the workflow instance was written since it was read.
+    fn save_dag(&self, mut wf_instance: WorkflowInstanceInfo, dag: &Dag) -> Result<bool> {
+        wf_instance.dag = Some(serde_json::to_string(dag).context(error::DagEncodeFailed)?);
+        self.write(&wf_instance)
+    }
+
+    fn write(&self, wf_instance: &WorkflowInstanceInfo) -> Result<bool> {
+        self.storage
+            .update_workflow_instance(wf_instance)
+            .context(error::SchedStorageError)
+    }
+
+    /// Run `attempt` on the latest workflow instance until its write, if any, didn't lose
+    /// the race with another one. `attempt` returns `None` when it lost.
+    fn retry<T>(
+        &self,
+        wf_instance_id: u64,
+        mut attempt: impl FnMut(WorkflowInstanceInfo) -> Result<Option<T>>,
+    ) -> Result<T> {
+        for _ in 0..MAX_WRITE_ATTEMPTS {
+            let wf_instance = self.find_wf_instance(wf_instance_id)?;
+            if let Some(t) = attempt(wf_instance)? {
+                return Ok(t);
+            }
+            debug!("[Workflow] workflow instance {} was written concurrently, retry.", wf_instance_id);
+        }
+        error::WorkflowConflict { wf_instance_id }.fail()
+    }
+
+    /// The DAG of `pedag` with the job, params and flags of its `workflow_node_info`s.
+    fn build_dag(&self, workflow: &WorkflowInfo) -> Result<Dag> {
+        let mut dag = Dag::parse(workflow.pedag.as_deref().unwrap_or_default())?;
+        let nodes: HashMap<u64, _> = self
+            .storage
+            .find_workflow_nodes(workflow.id.unwrap_or_default())
+            .context(error::SchedStorageError)?
+            .into_iter()
+            .filter_map(|n| n.id.map(|id| (id, n)))
+            .collect();
+        for node in dag.nodes.iter_mut() {
+            let info = match nodes.get(&node.node_id) {
+                Some(info) => info,
+                None => {
+                    return error::InvalidDag {
+                        reason: format!("node {} doesn't exist", node.node_id),
+                    }
+                    .fail()
+                }
+            };
+            node.job_id = info.job_id;
+            node.node_name = info.node_name.clone();
+            node.node_params = info.node_params.clone();
+            node.enable = info.enable.unwrap_or(true);
+            node.skip_when_failed = info.skip_when_failed.unwrap_or_default();
+            node.instance_id = None;
+            node.status = None;
+            node.result = None;
+        }
+        Ok(dag)
+    }
+
+    fn find_wf_instance(&self, wf_instance_id: u64) -> Result<WorkflowInstanceInfo> {
+        self.storage
+            .find_workflow_instance(wf_instance_id)
+            .context(error::SchedStorageError)?
+            .context(error::WorkflowInstanceNotFound { wf_instance_id })
+    }
+}
+
+fn status_of(wf_instance: &WorkflowInstanceInfo) -> Option<WorkflowInstanceStatus> {
+    wf_instance
+        .status
+        .and_then(|s| WorkflowInstanceStatus::try_from(s).ok())
+}
+
+fn is_finished(wf_instance: &WorkflowInstanceInfo) -> bool {
+    status_of(wf_instance).map_or(false, |s| s.is_finished())
+}
+
+/// Run a finished workflow instance again, -1 marks the finished time as unset.
+fn reopen(wf_instance: &mut WorkflowInstanceInfo) {
+    wf_instance.status = Some(WorkflowInstanceStatus::Running.into());
+    wf_instance.result = Some(String::new());
+    wf_instance.finished_time = Some(-1);
+}
+
+fn is_unsuccessful(status: InstanceStatus) -> bool {
+    matches!(
+        status,
+        InstanceStatus::Failed | InstanceStatus::Canceled | InstanceStatus::Stopped
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fastjob_components_storage::model::workflow_node_info::WorkflowNodeInfo;
+    use fastjob_components_storage::MemoryStorage;
+
+    #[test]
+    fn t_validate_dag() {
+        let dag = r#"{"nodes":[{"nodeId":1},{"nodeId":2},{"nodeId":3}],
+                      "edges":[{"from":1,"to":2},{"from":2,"to":3}]}"#;
+        assert!(Dag::parse(dag).is_ok());
+        let cycle = r#"{"nodes":[{"nodeId":1},{"nodeId":2}],
+                        "edges":[{"from":1,"to":2},{"from":2,"to":1}]}"#;
+        assert!(Dag::parse(cycle).is_err());
+        let unknown = r#"{"nodes":[{"nodeId":1}],"edges":[{"from":1,"to":2}]}"#;
+        assert!(Dag::parse(unknown).is_err());
+        assert!(Dag::parse(r#"{"nodes":[]}"#).is_err());
+    }
+
+    /// Start a workflow of 1 -> 2 -> 3 and 1 -> 3, node 2 is skipped when it fails.
+    fn start() -> (Arc<MemoryStorage>, WorkflowEngine<MemoryStorage>, u64, u64) {
+        id_generator::init(1).unwrap();
+        let storage = Arc::new(MemoryStorage::new());
+        let engine = WorkflowEngine::new(storage.clone());
+        for id in 1..=3 {
+            storage
+                .save(JobInfo { id: Some(id), app_id: Some(1), ..Default::default() })
+                .unwrap();
+            storage
+                .save(WorkflowNodeInfo {
+                    id: Some(id),
+                    job_id: Some(id),
+                    workflow_id: Some(1),
+                    enable: Some(true),
+                    skip_when_failed: Some(id == 2),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let workflow = WorkflowInfo {
+            id: Some(1),
+            app_id: Some(1),
+            pedag: Some(
+                r#"{"nodes":[{"nodeId":1},{"nodeId":2},{"nodeId":3}],
+                    "edges":[{"from":1,"to":2},{"from":2,"to":3},{"from":1,"to":3}]}"#
+                    .to_string(),
+            ),
+            ..Default::default()
+        };
+        let created = engine.start(&workflow, None).unwrap();
+        assert_eq!(created.len(), 1);
+        assert_eq!(created[0].0.id, Some(1));
+        let wf_instance = storage
+            .find_workflow_instances_by_status(&[1], vec![WorkflowInstanceStatus::Running.into()])
+            .unwrap()
+            .remove(0);
+        (storage, engine, wf_instance.wf_instance_id.unwrap(), created[0].1)
+    }
+
+    fn wf_status(storage: &MemoryStorage, wf_instance_id: u64) -> Option<u32> {
+        storage
+            .find_workflow_instance(wf_instance_id)
+            .unwrap()
+            .unwrap()
+            .status
+    }
+
+    #[test]
+    fn t_run_workflow() {
+        let (storage, engine, wf_instance_id, instance_id) = start();
+        let mut appended = HashMap::new();
+        appended.insert("rows".to_string(), "42".to_string());
+        assert!(engine.append_context(wf_instance_id, &appended).unwrap());
+        let created = engine
+            .node_finished(wf_instance_id, instance_id, InstanceStatus::Success, "ok")
+            .unwrap();
+        assert_eq!(created.len(), 1);
+        assert_eq!(created[0].0.id, Some(2));
+        let instance = storage.find_instance_by_id(created[0].1).unwrap().unwrap();
+        assert_eq!(instance.instance_params.as_deref(), Some(r#"{"rows":"42"}"#));
+
+        // node 2 is skipped when failed, so node 3 still runs.
+        let created = engine
+            .node_finished(wf_instance_id, created[0].1, InstanceStatus::Failed, "boom")
+            .unwrap();
+        assert_eq!(created.len(), 1);
+        assert_eq!(created[0].0.id, Some(3));
+
+        engine
+            .node_finished(wf_instance_id, created[0].1, InstanceStatus::Success, "ok")
+            .unwrap();
+        assert_eq!(
+            wf_status(&storage, wf_instance_id),
+            Some(WorkflowInstanceStatus::Success.into())
+        );
+    }
+
+    #[test]
+    fn t_control_workflow() {
+        let (storage, engine, wf_instance_id, instance_id) = start();
+        engine
+            .node_finished(wf_instance_id, instance_id, InstanceStatus::Failed, "boom")
+            .unwrap();
+        assert_eq!(
+            wf_status(&storage, wf_instance_id),
+            Some(WorkflowInstanceStatus::Failed.into())
+        );
+
+        // retrying runs node 1 again with a new instance.
+        let created = engine.retry_failed(wf_instance_id).unwrap();
+        assert_eq!(created.len(), 1);
+        assert_ne!(created[0].1, instance_id);
+        engine
+            .node_finished(wf_instance_id, created[0].1, InstanceStatus::Failed, "boom")
+            .unwrap();
+
+        // marking it success unblocks node 2 and its instance row follows.
+        let created = engine.mark_node_success(wf_instance_id, 1).unwrap();
+        assert_eq!(created.len(), 1);
+        assert_eq!(created[0].0.id, Some(2));
+        assert!(engine.mark_node_success(wf_instance_id, 2).is_err());
+
+        let stopped = engine.stop(wf_instance_id).unwrap();
+        assert_eq!(stopped, vec![created[0].1]);
+        let instance = storage.find_instance_by_id(created[0].1).unwrap().unwrap();
+        assert_eq!(instance.status, Some(InstanceStatus::Stopped.into()));
+        assert_eq!(
+            wf_status(&storage, wf_instance_id),
+            Some(WorkflowInstanceStatus::Stopped.into())
+        );
+        assert!(engine.stop(wf_instance_id).is_err());
+    }
+
+    #[test]
+    fn t_concurrent_writes() {
+        let (storage, engine, wf_instance_id, instance_id) = start();
+        let stale = storage.find_workflow_instance(wf_instance_id).unwrap().unwrap();
+        let engine = Arc::new(engine);
+        // fewer writes than the attempts of one update, so none can lose every race.
+        let handles: Vec<_> = (0..3)
+            .map(|i| {
+                let engine = engine.clone();
+                std::thread::spawn(move || {
+                    for j in 0..5 {
+                        let mut appended = HashMap::new();
+                        appended.insert(format!("{}-{}", i, j), "v".to_string());
+                        assert!(engine.append_context(wf_instance_id, &appended).unwrap());
+                    }
+                })
+            })
+            .collect();
+        let created = engine
+            .node_finished(wf_instance_id, instance_id, InstanceStatus::Success, "ok")
+            .unwrap();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(created.len(), 1);
+
+        // neither the appends nor the finished node are lost.
+        let wf_instance = storage.find_workflow_instance(wf_instance_id).unwrap().unwrap();
+        let context: HashMap<String, String> =
+            serde_json::from_str(wf_instance.wf_context.as_deref().unwrap()).unwrap();
+        assert_eq!(context.len(), 15);
+        let mut dag = Dag::parse(wf_instance.dag.as_deref().unwrap()).unwrap();
+        assert_eq!(dag.node_mut(1).unwrap().status, Some(InstanceStatus::Success.into()));
+
+        // a write based on an outdated read is rejected.
+        assert!(!storage.update_workflow_instance(&stale).unwrap());
+    }
+}
@@ -0,0 +1,10 @@
+DROP TABLE IF EXISTS `workflow_node_info`;
+DROP TABLE IF EXISTS `workflow_instance_info`;
+DROP TABLE IF EXISTS `workflow_info`;
+DROP TABLE IF EXISTS `user_info`;
+DROP TABLE IF EXISTS `server_info`;
+DROP TABLE IF EXISTS `lock`;
+DROP TABLE IF EXISTS `job_info`;
+DROP TABLE IF EXISTS `instance_info`;
+DROP TABLE IF EXISTS `container_info`;
+DROP TABLE IF EXISTS `app_info`;
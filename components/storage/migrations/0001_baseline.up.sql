@@ -0,0 +1,215 @@
+-- Baseline schema, equivalent to `other/fastjob.sql` as of the first release.
+-- Tables are only created when missing so it is safe to run on an existing database.
+
+-- ----------------------------
+-- Table structure for app_info
+-- ----------------------------
+CREATE TABLE IF NOT EXISTS `app_info`
+(
+    `id`             bigint(20) NOT NULL AUTO_INCREMENT,
+    `app_name`       varchar(255) DEFAULT NULL,
+    `current_server` varchar(255) DEFAULT NULL,
+    `gmt_create`     datetime(6) DEFAULT CURRENT_TIMESTAMP (6),
+    `gmt_modified`   datetime(6) DEFAULT CURRENT_TIMESTAMP (6) ON UPDATE CURRENT_TIMESTAMP (6),
+    `password`       varchar(255) DEFAULT NULL,
+    PRIMARY KEY (`id`),
+    UNIQUE KEY `appNameUK` (`app_name`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+
+-- ----------------------------
+-- Table structure for container_info
+-- ----------------------------
+CREATE TABLE IF NOT EXISTS `container_info`
+(
+    `id`               bigint(20) NOT NULL AUTO_INCREMENT,
+    `app_id`           bigint(20) DEFAULT NULL,
+    `container_name`   varchar(255) DEFAULT NULL,
+    `gmt_create`       datetime(6) DEFAULT CURRENT_TIMESTAMP (6),
+    `gmt_modified`     datetime(6) DEFAULT CURRENT_TIMESTAMP (6) ON UPDATE CURRENT_TIMESTAMP (6),
+    `last_deploy_time` datetime(6) DEFAULT NULL,
+    `source_info`      varchar(255) DEFAULT NULL,
+    `source_type`      int(11) DEFAULT NULL,
+    `status`           int(11) DEFAULT NULL,
+    `version`          varchar(255) DEFAULT NULL,
+    PRIMARY KEY (`id`),
+    KEY                `IDX8hixyaktlnwil2w9up6b0p898` (`app_id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+
+-- ----------------------------
+-- Table structure for instance_info
+-- ----------------------------
+CREATE TABLE IF NOT EXISTS `instance_info`
+(
+    `id`                    bigint(20) NOT NULL AUTO_INCREMENT,
+    `actual_trigger_time`   bigint(20) DEFAULT NULL,
+    `app_id`                bigint(20) DEFAULT NULL,
+    `expected_trigger_time` bigint(20) DEFAULT NULL,
+    `finished_time`         bigint(20) DEFAULT NULL,
+    `gmt_create`            datetime(6) DEFAULT CURRENT_TIMESTAMP (6),
+    `gmt_modified`          datetime(6) DEFAULT CURRENT_TIMESTAMP (6) ON UPDATE CURRENT_TIMESTAMP (6),
+    `instance_id`           bigint(20) DEFAULT NULL,
+    `instance_params`       longtext,
+    `job_id`                bigint(20) DEFAULT NULL,
+    `job_params`            longtext,
+    `last_report_time`      bigint(20) DEFAULT NULL,
+    `result`                text,
+    `running_times`         bigint(20) DEFAULT NULL,
+    `status`                int(11) DEFAULT NULL,
+    `task_tracker_address`  varchar(255) DEFAULT NULL,
+    `instance_type`         int(11) DEFAULT NULL,
+    `wf_instance_id`        bigint(20) DEFAULT NULL,
+    PRIMARY KEY (`id`),
+    KEY                     `IDX5b1nhpe5je7gc5s1ur200njr7` (`job_id`),
+    KEY                     `IDXjnji5lrr195kswk6f7mfhinrs` (`app_id`),
+    KEY                     `IDXa98hq3yu0l863wuotdjl7noum` (`instance_id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+
+-- ----------------------------
+-- Table structure for job_info
+-- ----------------------------
+CREATE TABLE IF NOT EXISTS `job_info`
+(
+    `id`                   bigint(20) NOT NULL AUTO_INCREMENT,
+    `app_id`               bigint(20) DEFAULT NULL,
+    `concurrency`          int(11) DEFAULT NULL,
+    `designated_workers`   varchar(255) DEFAULT NULL,
+    `dispatch_strategy`    int(11) DEFAULT NULL,
+    `execute_type`         int(11) DEFAULT NULL,
+    `extra`                varchar(255) DEFAULT NULL,
+    `gmt_create`           datetime(6) DEFAULT CURRENT_TIMESTAMP (6),
+    `gmt_modified`         datetime(6) DEFAULT CURRENT_TIMESTAMP (6) ON UPDATE CURRENT_TIMESTAMP (6),
+    `instance_retry_num`   int(11) DEFAULT NULL,
+    `instance_time_limit`  bigint(20) DEFAULT NULL,
+    `job_description`      varchar(255) DEFAULT NULL,
+    `job_name`             varchar(255) DEFAULT NULL,
+    `job_params`           text,
+    `lifecycle`            varchar(255) DEFAULT NULL,
+    `max_instance_num`     int(11) unsigned DEFAULT NULL,
+    `max_worker_count`     int(11) DEFAULT NULL,
+    `min_cpu_cores`        double NOT NULL,
+    `min_disk_space`       double NOT NULL,
+    `min_memory_space`     double NOT NULL,
+    `next_trigger_time`    bigint(20) DEFAULT NULL,
+    `notify_user_ids`      varchar(255) DEFAULT NULL,
+    `processor_info`       varchar(255) DEFAULT NULL,
+    `processor_type`       int(11) DEFAULT NULL,
+    `status`               int(11) DEFAULT NULL,
+    `task_retry_num`       int(11) DEFAULT NULL,
+    `time_expression`      varchar(255) DEFAULT NULL,
+    `time_expression_type` int(11) DEFAULT NULL,
+    PRIMARY KEY (`id`),
+    KEY                    `IDXk2xprmn3lldmlcb52i36udll1` (`app_id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+
+-- ----------------------------
+-- Table structure for lock
+-- ----------------------------
+CREATE TABLE IF NOT EXISTS `lock`
+(
+    `id`            bigint(20) NOT NULL AUTO_INCREMENT,
+    `gmt_create`    datetime(6) DEFAULT CURRENT_TIMESTAMP (6),
+    `gmt_modified`  datetime(6) DEFAULT CURRENT_TIMESTAMP (6) ON UPDATE CURRENT_TIMESTAMP (6),
+    `lock_name`     varchar(255)                                                  DEFAULT NULL,
+    `max_lock_time` bigint(20) DEFAULT NULL,
+    `owner_ip`      varchar(255) CHARACTER SET utf8mb4 COLLATE utf8mb4_0900_ai_ci DEFAULT NULL,
+    PRIMARY KEY (`id`),
+    UNIQUE KEY `lockNameUK` (`lock_name`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+
+-- ----------------------------
+-- Table structure for server_info
+-- ----------------------------
+CREATE TABLE IF NOT EXISTS `server_info`
+(
+    `id`           bigint(20) NOT NULL AUTO_INCREMENT,
+    `gmt_create`   datetime(6) DEFAULT CURRENT_TIMESTAMP (6),
+    `gmt_modified` datetime(6) DEFAULT CURRENT_TIMESTAMP (6) ON UPDATE CURRENT_TIMESTAMP (6),
+    `ip`           varchar(255) DEFAULT NULL,
+    PRIMARY KEY (`id`),
+    UNIQUE KEY `UKtk8ytgpl7mpukhnvhbl82kgvy` (`ip`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+
+-- ----------------------------
+-- Table structure for user_info
+-- ----------------------------
+CREATE TABLE IF NOT EXISTS `user_info`
+(
+    `id`           bigint(20) NOT NULL AUTO_INCREMENT,
+    `email`        varchar(255) DEFAULT NULL,
+    `extra`        varchar(255) DEFAULT NULL,
+    `gmt_create`   datetime(6) DEFAULT CURRENT_TIMESTAMP (6),
+    `gmt_modified` datetime(6) DEFAULT CURRENT_TIMESTAMP (6) ON UPDATE CURRENT_TIMESTAMP (6),
+    `password`     varchar(255) DEFAULT NULL,
+    `phone`        varchar(255) DEFAULT NULL,
+    `username`     varchar(255) DEFAULT NULL,
+    `web_hook`     varchar(255) DEFAULT NULL,
+    PRIMARY KEY (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+
+-- ----------------------------
+-- Table structure for workflow_info
+-- ----------------------------
+CREATE TABLE IF NOT EXISTS `workflow_info`
+(
+    `id`                   bigint(20) NOT NULL AUTO_INCREMENT,
+    `app_id`               bigint(20) DEFAULT NULL,
+    `extra`                varchar(255) DEFAULT NULL,
+    `gmt_create`           datetime(6) DEFAULT CURRENT_TIMESTAMP (6),
+    `gmt_modified`         datetime(6) DEFAULT CURRENT_TIMESTAMP (6) ON UPDATE CURRENT_TIMESTAMP (6),
+    `lifecycle`            varchar(255) DEFAULT NULL,
+    `max_wf_instance_num`  int(11) DEFAULT NULL,
+    `next_trigger_time`    bigint(20) DEFAULT NULL,
+    `notify_user_ids`      varchar(255) DEFAULT NULL,
+    `pedag`                text,
+    `status`               int(11) DEFAULT NULL,
+    `time_expression`      varchar(255) DEFAULT NULL,
+    `time_expression_type` int(11) DEFAULT NULL,
+    `wf_description`       varchar(255) DEFAULT NULL,
+    `wf_name`              varchar(255) DEFAULT NULL,
+    PRIMARY KEY (`id`),
+    KEY                    `IDX7uo5w0e3beeho3fnx9t7eiol3` (`app_id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+
+-- ----------------------------
+-- Table structure for workflow_instance_info
+-- ----------------------------
+CREATE TABLE IF NOT EXISTS `workflow_instance_info`
+(
+    `id`                    bigint(20) NOT NULL AUTO_INCREMENT,
+    `actual_trigger_time`   bigint(20) DEFAULT NULL,
+    `app_id`                bigint(20) DEFAULT NULL,
+    `dag`                   text,
+    `expected_trigger_time` bigint(20) DEFAULT NULL,
+    `finished_time`         bigint(20) DEFAULT NULL,
+    `gmt_create`            datetime(6) DEFAULT CURRENT_TIMESTAMP (6),
+    `gmt_modified`          datetime(6) DEFAULT CURRENT_TIMESTAMP (6) ON UPDATE CURRENT_TIMESTAMP (6),
+    `result`                text,
+    `status`                int(11) DEFAULT NULL,
+    `wf_context`            text,
+    `wf_init_params`        text,
+    `wf_instance_id`        bigint(20) DEFAULT NULL,
+    `workflow_id`           bigint(20) DEFAULT NULL,
+    PRIMARY KEY (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+
+-- ----------------------------
+-- Table structure for workflow_node_info
+-- ----------------------------
+CREATE TABLE IF NOT EXISTS `workflow_node_info`
+(
+    `id`               bigint(20) NOT NULL AUTO_INCREMENT,
+    `app_id`           bigint(20) NOT NULL,
+    `enable`           bit(1) NOT NULL,
+    `extra`            text,
+    `gmt_create`       datetime(6) NOT NULL DEFAULT CURRENT_TIMESTAMP (6),
+    `gmt_modified`     datetime(6) NOT NULL DEFAULT CURRENT_TIMESTAMP (6) ON UPDATE CURRENT_TIMESTAMP (6),
+    `job_id`           bigint(20) DEFAULT NULL,
+    `node_name`        varchar(255) DEFAULT NULL,
+    `node_params`      text,
+    `skip_when_failed` bit(1) NOT NULL,
+    `type`             int(11) DEFAULT NULL,
+    `workflow_id`      bigint(20) DEFAULT NULL,
+    PRIMARY KEY (`id`),
+    KEY                `IDX36t7rhj4mkg2a5pb4ttorscta` (`app_id`),
+    KEY                `IDXacr0i6my8jr002ou8i1gmygju` (`workflow_id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
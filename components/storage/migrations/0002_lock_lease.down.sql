@@ -0,0 +1,8 @@
+ALTER TABLE `app_info` DROP COLUMN `fencing_token`;
+
+DELETE FROM `lock`;
+
+ALTER TABLE `lock`
+    DROP COLUMN `fencing_token`,
+    MODIFY `gmt_create` datetime(6) DEFAULT CURRENT_TIMESTAMP (6),
+    MODIFY `gmt_modified` datetime(6) DEFAULT CURRENT_TIMESTAMP (6) ON UPDATE CURRENT_TIMESTAMP (6);
@@ -0,0 +1,12 @@
+-- Lease based locks: expiry is computed from millisecond timestamps and every
+-- acquisition bumps a fencing token. Locks are transient, existing rows are dropped
+-- so the columns can change type.
+DELETE FROM `lock`;
+
+ALTER TABLE `lock`
+    MODIFY `gmt_create` bigint(20) DEFAULT NULL,
+    MODIFY `gmt_modified` bigint(20) DEFAULT NULL,
+    ADD COLUMN `fencing_token` bigint(20) NOT NULL DEFAULT 0;
+
+ALTER TABLE `app_info`
+    ADD COLUMN `fencing_token` bigint(20) NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+ALTER TABLE `server_info`
+    DROP KEY `IDX_server_info_last_heartbeat`,
+    DROP COLUMN `last_heartbeat`;
@@ -0,0 +1,4 @@
+-- Servers upsert their heartbeat periodically, peers with a stale heartbeat are expired.
+ALTER TABLE `server_info`
+    ADD COLUMN `last_heartbeat` bigint(20) DEFAULT NULL,
+    ADD KEY `IDX_server_info_last_heartbeat` (`last_heartbeat`);
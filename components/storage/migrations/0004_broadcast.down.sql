@@ -0,0 +1,4 @@
+ALTER TABLE `job_info`
+    DROP COLUMN `success_ratio`;
+
+DROP TABLE IF EXISTS `task_info`;
@@ -0,0 +1,19 @@
+-- Broadcast instances fan out one sub-task per worker, each worker's result is a row
+-- in `task_info`. `success_ratio` is the fraction of sub-tasks that must succeed.
+CREATE TABLE IF NOT EXISTS `task_info`
+(
+    `id`             bigint(20) NOT NULL AUTO_INCREMENT,
+    `app_id`         bigint(20) DEFAULT NULL,
+    `job_id`         bigint(20) DEFAULT NULL,
+    `instance_id`    bigint(20) DEFAULT NULL,
+    `worker_address` varchar(255) DEFAULT NULL,
+    `status`         int(11) DEFAULT NULL,
+    `result`         text,
+    `gmt_create`     bigint(20) DEFAULT NULL,
+    `gmt_modified`   bigint(20) DEFAULT NULL,
+    PRIMARY KEY (`id`),
+    UNIQUE KEY `UK_task_info_instance_worker` (`instance_id`, `worker_address`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+
+ALTER TABLE `job_info`
+    ADD COLUMN `success_ratio` double DEFAULT NULL;
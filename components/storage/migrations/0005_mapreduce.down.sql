@@ -0,0 +1,11 @@
+DELETE FROM `task_info` WHERE `task_type` <> 1;
+
+ALTER TABLE `task_info`
+    DROP KEY `UK_task_info_instance_task`,
+    DROP KEY `IDX_task_info_instance_status`,
+    DROP COLUMN `task_id`,
+    DROP COLUMN `task_name`,
+    DROP COLUMN `task_type`,
+    DROP COLUMN `task_params`,
+    DROP COLUMN `retry_times`,
+    ADD UNIQUE KEY `UK_task_info_instance_worker` (`instance_id`, `worker_address`);
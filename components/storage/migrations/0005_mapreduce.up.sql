@@ -0,0 +1,15 @@
+-- MapReduce instances track many tasks per worker, so a task is identified by its
+-- `task_id` within the instance. Existing broadcast sub-tasks use the worker address.
+ALTER TABLE `task_info`
+    DROP KEY `UK_task_info_instance_worker`,
+    ADD COLUMN `task_id` varchar(255) DEFAULT NULL,
+    ADD COLUMN `task_name` varchar(255) DEFAULT NULL,
+    ADD COLUMN `task_type` int(11) DEFAULT NULL,
+    ADD COLUMN `task_params` longtext,
+    ADD COLUMN `retry_times` int(11) NOT NULL DEFAULT 0;
+
+UPDATE `task_info` SET `task_id` = `worker_address`, `task_type` = 1 WHERE `task_id` IS NULL;
+
+ALTER TABLE `task_info`
+    ADD UNIQUE KEY `UK_task_info_instance_task` (`instance_id`, `task_id`),
+    ADD KEY `IDX_task_info_instance_status` (`instance_id`, `status`);
@@ -0,0 +1,3 @@
+ALTER TABLE `workflow_instance_info`
+    DROP KEY `UK_workflow_instance_info_wf_instance`,
+    DROP KEY `IDX_workflow_instance_info_app_status`;
@@ -0,0 +1,5 @@
+-- Workflow instances are looked up by `wf_instance_id` when a node instance finishes
+-- and by app and status when the unfinished ones are checked.
+ALTER TABLE `workflow_instance_info`
+    ADD UNIQUE KEY `UK_workflow_instance_info_wf_instance` (`wf_instance_id`),
+    ADD KEY `IDX_workflow_instance_info_app_status` (`app_id`, `status`);
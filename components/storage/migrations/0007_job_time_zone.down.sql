@@ -0,0 +1,2 @@
+ALTER TABLE `job_info`
+    DROP COLUMN `time_zone`;
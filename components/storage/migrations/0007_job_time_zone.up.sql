@@ -0,0 +1,4 @@
+-- Cron expressions of a job are evaluated in its IANA time zone, null for the time zone
+-- of the server.
+ALTER TABLE `job_info`
+    ADD COLUMN `time_zone` varchar(64) DEFAULT NULL;
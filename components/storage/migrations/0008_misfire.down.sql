@@ -0,0 +1,6 @@
+ALTER TABLE `instance_info`
+    DROP COLUMN `misfire_action`;
+
+ALTER TABLE `job_info`
+    DROP COLUMN `max_catch_up`,
+    DROP COLUMN `misfire_policy`;
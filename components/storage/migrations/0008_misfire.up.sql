@@ -0,0 +1,8 @@
+-- A cron job's `misfire_policy` decides what fires for the trigger times missed while no
+-- server was scheduling it, the instances fired for missed runs record it in `misfire_action`.
+ALTER TABLE `job_info`
+    ADD COLUMN `max_catch_up` int(11) DEFAULT NULL,
+    ADD COLUMN `misfire_policy` int(11) DEFAULT NULL;
+
+ALTER TABLE `instance_info`
+    ADD COLUMN `misfire_action` int(11) DEFAULT NULL;
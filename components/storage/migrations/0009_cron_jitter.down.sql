@@ -0,0 +1,5 @@
+ALTER TABLE `app_info`
+    DROP COLUMN `cron_spread`;
+
+ALTER TABLE `job_info`
+    DROP COLUMN `jitter`;
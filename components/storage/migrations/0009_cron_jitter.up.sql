@@ -0,0 +1,7 @@
+-- Cron runs are delayed by an offset fixed per job within the job's `jitter` window, or
+-- the `cron_spread` window of its app, so jobs sharing a fire time don't dispatch at once.
+ALTER TABLE `job_info`
+    ADD COLUMN `jitter` bigint(20) DEFAULT NULL;
+
+ALTER TABLE `app_info`
+    ADD COLUMN `cron_spread` bigint(20) DEFAULT NULL;
@@ -0,0 +1,5 @@
+ALTER TABLE `job_info`
+    DROP COLUMN `calendar_id`,
+    DROP COLUMN `calendar_policy`;
+
+DROP TABLE IF EXISTS `calendar_info`;
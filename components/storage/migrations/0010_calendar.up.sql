@@ -0,0 +1,18 @@
+-- Named calendars of an app, a job referencing one has its cron runs adjusted to the
+-- allowed days according to its `calendar_policy`.
+CREATE TABLE IF NOT EXISTS `calendar_info`
+(
+    `id`            bigint(20) NOT NULL AUTO_INCREMENT,
+    `app_id`        bigint(20) DEFAULT NULL,
+    `calendar_name` varchar(255) DEFAULT NULL,
+    `gmt_create`    bigint(20) DEFAULT NULL,
+    `gmt_modified`  bigint(20) DEFAULT NULL,
+    `holidays`      text,
+    `working_days`  varchar(32) DEFAULT NULL,
+    PRIMARY KEY (`id`),
+    UNIQUE KEY `UK_calendar_info_app_name` (`app_id`, `calendar_name`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+
+ALTER TABLE `job_info`
+    ADD COLUMN `calendar_id` bigint(20) DEFAULT NULL,
+    ADD COLUMN `calendar_policy` int(11) DEFAULT NULL;
@@ -0,0 +1,3 @@
+ALTER TABLE `instance_info`
+    DROP INDEX `UK_instance_info_request_key`,
+    DROP COLUMN `request_key`;
@@ -0,0 +1,5 @@
+-- An on-demand run records the key its caller supplied, a retried request finds the
+-- instance it created instead of creating a second one.
+ALTER TABLE `instance_info`
+    ADD COLUMN `request_key` varchar(255) DEFAULT NULL,
+    ADD UNIQUE KEY `UK_instance_info_request_key` (`job_id`, `request_key`);
@@ -0,0 +1,3 @@
+ALTER TABLE `instance_info`
+    DROP INDEX `UK_instance_info_trigger_slot`,
+    DROP COLUMN `trigger_slot`;
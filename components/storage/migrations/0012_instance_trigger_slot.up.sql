@@ -0,0 +1,5 @@
+-- The instances the schedule creates record their expected trigger time as a slot unique per
+-- job, so a trigger time is instantiated once even when two servers schedule the same job.
+ALTER TABLE `instance_info`
+    ADD COLUMN `trigger_slot` bigint(20) DEFAULT NULL,
+    ADD UNIQUE KEY `UK_instance_info_trigger_slot` (`job_id`, `trigger_slot`);
@@ -0,0 +1,2 @@
+ALTER TABLE `workflow_instance_info`
+    DROP COLUMN `version`;
@@ -0,0 +1,4 @@
+-- Every write of a workflow instance bumps its version and only succeeds against the version
+-- it read, so nodes finishing or appending to the context concurrently don't lose updates.
+ALTER TABLE `workflow_instance_info`
+    ADD COLUMN `version` bigint(20) NOT NULL DEFAULT 0;
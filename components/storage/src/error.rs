@@ -9,6 +9,22 @@ pub type Result<T, E = rbatis::Error> = std::result::Result<T, E>;
 pub enum StorageError {
     #[snafu(display("Storage saved failure; msg: {}, error : {}", msg, source))]
     SaveError { source: rbatis::Error, msg: &'static str },
+    #[snafu(display("Storage connect failure; address: {}, error : {}", address, source))]
+    ConnectError { source: rbatis::Error, address: String },
+    #[snafu(display("Schema migration {} failure; error : {}", version, source))]
+    MigrationError { source: rbatis::Error, version: i64 },
+    #[snafu(display(
+        "Database schema version {} is newer than this binary supports ({}), upgrade fastjob first",
+        db_version,
+        binary_version
+    ))]
+    SchemaVersionTooNew { db_version: i64, binary_version: i64 },
+    #[snafu(display(
+        "Schema migration lock {} not acquired within {} s, another server is still migrating",
+        name,
+        timeout_secs
+    ))]
+    MigrationLocked { name: &'static str, timeout_secs: u64 },
     // #[snafu(display("Storage batch saved failure; msg : {}, error : {}", msg.display(), source))]
     // SaveBatchError { msg: T, source: rbatis::Error },
     // #[snafu(display("Storage deleted failure; msg: {}, error : {}", msg.display(), source))]
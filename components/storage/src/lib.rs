@@ -14,17 +14,47 @@ pub mod model;
 mod rbatis_test;
 
 pub mod error;
+mod lock_service;
+mod lock_sql;
+mod memory_storage;
+mod migration;
 mod mysql_storage;
+mod schedule_sql;
+mod server_sql;
+mod sqlite_storage;
+mod task_sql;
+mod workflow_sql;
 
+use crate::model::app_info::AppInfo;
+use crate::model::calendar_info::CalendarInfo;
 use crate::model::instance_info::InstanceInfo;
 use crate::model::job_info::JobInfo;
-use crate::mysql_storage::MysqlStorage;
+use crate::model::lock::Lock;
+use crate::model::server_info::ServerInfo;
+use crate::model::task_info::{TaskInfo, TaskStatusCount};
+use crate::model::workflow_info::WorkflowInfo;
+use crate::model::workflow_instance_info::WorkflowInstanceInfo;
+use crate::model::workflow_node_info::WorkflowNodeInfo;
+pub use crate::lock_service::{Lease, LockService};
+pub use crate::memory_storage::MemoryStorage;
+pub use crate::migration::{latest_version, Dialect, Migration, Migrator, MIGRATIONS};
+pub use crate::mysql_storage::MysqlStorage;
+pub use crate::sqlite_storage::SqliteStorage;
 use error::{Result, StorageError};
 use snafu::ResultExt;
 use std::fmt::{Debug, Display, Formatter};
 
+/// The backend that `StorageBuilder` constructs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StorageType {
+    Mysql,
+    /// Embedded database, `StorageConfig.address` is the database file path (or `:memory:`).
+    Sqlite,
+}
+
 #[derive(Clone)]
 pub struct StorageConfig {
+    pub storage_type: StorageType,
     pub address: &'static str,
     pub username: &'static str,
     pub password: &'static str,
@@ -38,6 +68,7 @@ pub struct StorageConfig {
 impl Debug for StorageConfig {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("storageConfig")
+            .field("storageType", &self.storage_type)
             .field("address", &self.address)
             .field("username", &self.username)
             .field("password", &self.password)
@@ -53,6 +84,7 @@ impl Debug for StorageConfig {
 impl Default for StorageConfig {
     fn default() -> Self {
         Self {
+            storage_type: StorageType::Mysql,
             address: "",
             username: "",
             password: "",
@@ -94,15 +126,133 @@ pub trait Storage {
 
     fn find_instance_by_ids(&self, instance_id: &[u64]) -> Result<Option<Vec<InstanceInfo>>>;
 
-    fn find_all_app_id_by_current_server(&self, current_server: &str) -> Result<Option<&[u64]>>;
+    /// The instance an on-demand run of the job created for `request_key`.
+    fn find_instance_by_request_key(
+        &self,
+        job_id: u64,
+        request_key: &str,
+    ) -> Result<Option<InstanceInfo>>;
+
+    fn find_all_app_id_by_current_server(&self, current_server: &str) -> Result<Option<Vec<u64>>>;
+
+    fn find_all_app_infos(&self) -> Result<Vec<AppInfo>>;
 
     fn find_cron_jobs(&self, ids: &[u64], time_threshold: i64) -> Result<Vec<JobInfo>>;
 
     fn find_frequent_jobs(&self, ids: &[u64]) -> Result<Vec<JobInfo>>;
 
+    /// The running one-shot jobs of the apps due before `time_threshold`.
+    fn find_one_shot_jobs(&self, ids: &[u64], time_threshold: i64) -> Result<Vec<JobInfo>>;
+
     fn find_frequent_instance_by_job_id(&self, ids: &[u64]) -> Result<Vec<u64>>;
 
+    /// The finished time of the job's latest finished instance.
+    fn find_last_finished_time(&self, job_id: u64) -> Result<Option<i64>>;
+
+    /// Save the instances a schedule round created for the job and its advanced
+    /// `next_trigger_time` and status in one transaction, saves nothing and returns false
+    /// when the job isn't running at `previous_trigger_time` anymore.
+    fn save_scheduled_instances(
+        &self,
+        previous_trigger_time: Option<i64>,
+        job: &JobInfo,
+        instances: &[InstanceInfo],
+    ) -> Result<bool>;
+
     fn count_instance_by_status(&self, id: u64, status: Vec<u32>) -> Result<u64>;
+
+    /// Instances of the app whose status is one of `status`.
+    fn find_instances_by_app_status(&self, app_id: u64, status: Vec<u32>)
+        -> Result<Vec<InstanceInfo>>;
+
+    fn find_app_info_by_id(&self, id: u64) -> Result<Option<AppInfo>>;
+
+    fn find_calendar(&self, id: u64) -> Result<Option<CalendarInfo>>;
+
+    fn find_calendar_by_name(&self, app_id: u64, calendar_name: &str)
+        -> Result<Option<CalendarInfo>>;
+
+    fn find_lock_by_name(&self, lock_name: &str) -> Result<Option<Lock>>;
+
+    /// Take `lock_name` for `owner` if it is free at `now`, returns the new fencing token.
+    fn acquire_lock(
+        &self,
+        lock_name: &str,
+        owner: &str,
+        max_lock_time: u64,
+        now: i64,
+    ) -> Result<Option<u64>>;
+
+    /// Extend the lease, fails if the lock was taken over since `fencing_token` was issued.
+    fn renew_lock(&self, lock_name: &str, owner: &str, fencing_token: u64, now: i64)
+        -> Result<bool>;
+
+    fn release_lock(&self, lock_name: &str, owner: &str, fencing_token: u64) -> Result<bool>;
+
+    /// Set the app's server unless it was already set with a newer fencing token. A token
+    /// is issued once per acquisition, so the lease that set the server may move the app.
+    fn update_app_current_server(
+        &self,
+        app_id: u64,
+        current_server: &str,
+        fencing_token: u64,
+    ) -> Result<bool>;
+
+    /// Clear `current_server` of every app owned by `current_server`.
+    fn release_app_current_server(&self, current_server: &str) -> Result<u64>;
+
+    /// Register `ip` or refresh its heartbeat.
+    fn upsert_server_heartbeat(&self, ip: &str, now: i64) -> Result<()>;
+
+    /// Servers whose last heartbeat is at or after `since`, ordered by ip.
+    fn find_alive_servers(&self, since: i64) -> Result<Vec<ServerInfo>>;
+
+    /// Remove servers whose last heartbeat is before `before`.
+    fn delete_expired_servers(&self, before: i64) -> Result<u64>;
+
+    fn delete_server(&self, ip: &str) -> Result<u64>;
+
+    /// The sub-tasks of a broadcast instance ordered by worker address.
+    fn find_tasks_by_instance_id(&self, instance_id: u64) -> Result<Vec<TaskInfo>>;
+
+    fn delete_tasks_by_instance_id(&self, instance_id: u64) -> Result<u64>;
+
+    /// Record the status a worker reported for its sub-task, returns false when the
+    /// instance has no sub-task on that worker.
+    fn update_task_status(
+        &self,
+        instance_id: u64,
+        worker_address: &str,
+        status: u32,
+        result: &str,
+        now: i64,
+    ) -> Result<bool>;
+
+    fn find_task(&self, instance_id: u64, task_id: &str) -> Result<Option<TaskInfo>>;
+
+    /// At most `limit` tasks of the instance with the status, oldest first.
+    fn find_tasks_by_status(&self, instance_id: u64, status: u32, limit: u64)
+        -> Result<Vec<TaskInfo>>;
+
+    fn count_tasks_by_status(&self, instance_id: u64) -> Result<Vec<TaskStatusCount>>;
+
+    /// Running CRON workflows of the apps due before `time_threshold`.
+    fn find_cron_workflows(&self, ids: &[u64], time_threshold: i64) -> Result<Vec<WorkflowInfo>>;
+
+    fn find_workflow_nodes(&self, workflow_id: u64) -> Result<Vec<WorkflowNodeInfo>>;
+
+    fn find_workflow_instance(&self, wf_instance_id: u64) -> Result<Option<WorkflowInstanceInfo>>;
+
+    fn find_workflow_instances_by_status(
+        &self,
+        ids: &[u64],
+        status: Vec<u32>,
+    ) -> Result<Vec<WorkflowInstanceInfo>>;
+
+    /// Write the DAG, context, status, result and finished time of a workflow instance and
+    /// bump its version, unless it was written since `wf_instance` was read, i.e. its
+    /// `version` changed. Returns false when nothing was written.
+    fn update_workflow_instance(&self, wf_instance: &WorkflowInstanceInfo) -> Result<bool>;
 }
 
 /// Storage Builder.
@@ -121,7 +271,284 @@ impl StorageBuilder {
         Self { config }
     }
 
-    pub fn build(self) -> MysqlStorage {
-        MysqlStorage::new(self.config)
+    pub fn storage_type(mut self, storage_type: StorageType) -> Self {
+        self.config.storage_type = storage_type;
+        self
+    }
+
+    /// Fails when the database can't be linked or migrated, including a database migrated
+    /// by a newer binary, the server refuses to start then.
+    pub fn build(self) -> std::result::Result<StorageEngine, StorageError> {
+        match self.config.storage_type {
+            StorageType::Mysql => {
+                let storage = MysqlStorage::new(self.config);
+                storage.init()?;
+                Ok(StorageEngine::Mysql(storage))
+            }
+            StorageType::Sqlite => {
+                let storage = SqliteStorage::new(self.config);
+                storage.init()?;
+                Ok(StorageEngine::Sqlite(storage))
+            }
+        }
+    }
+}
+
+/// The storage built by `StorageBuilder`, delegates every call to the selected backend.
+#[derive(Clone, Debug)]
+pub enum StorageEngine {
+    Mysql(MysqlStorage),
+    Sqlite(SqliteStorage),
+}
+
+macro_rules! delegate {
+    ($self:ident, $s:ident => $call:expr) => {
+        match $self {
+            StorageEngine::Mysql($s) => $call,
+            StorageEngine::Sqlite($s) => $call,
+        }
+    };
+}
+
+impl Storage for StorageEngine {
+    fn save<T>(&self, t: T) -> Result<()>
+        where
+            T: CRUDTable,
+    {
+        delegate!(self, s => s.save(t))
+    }
+
+    fn save_batch<T>(&self, t: &[T]) -> Result<()>
+        where
+            T: CRUDTable,
+    {
+        delegate!(self, s => s.save_batch(t))
+    }
+
+    fn delete<T>(&self, id: &T::IdType) -> Result<u64>
+        where
+            T: CRUDTable,
+    {
+        delegate!(self, s => s.delete::<T>(id))
+    }
+
+    fn delete_batch<T>(&self, ids: &[T::IdType]) -> Result<()>
+        where
+            T: CRUDTable,
+    {
+        delegate!(self, s => s.delete_batch::<T>(ids))
+    }
+
+    fn update<T>(&self, models: &mut [T]) -> Result<()>
+        where
+            T: CRUDTable,
+    {
+        delegate!(self, s => s.update(models))
+    }
+
+    fn find_job_info_by_instance_id(&self, instance_id: u64) -> Result<Option<JobInfo>> {
+        delegate!(self, s => s.find_job_info_by_instance_id(instance_id))
+    }
+
+    fn find_job_info_by_id(&self, instance_id: u64) -> Result<Option<JobInfo>> {
+        delegate!(self, s => s.find_job_info_by_id(instance_id))
+    }
+
+    fn find_instance_by_id(&self, instance_id: u64) -> Result<Option<InstanceInfo>> {
+        delegate!(self, s => s.find_instance_by_id(instance_id))
+    }
+
+    fn find_instance_by_ids(&self, instance_id: &[u64]) -> Result<Option<Vec<InstanceInfo>>> {
+        delegate!(self, s => s.find_instance_by_ids(instance_id))
+    }
+
+    fn find_instance_by_request_key(
+        &self,
+        job_id: u64,
+        request_key: &str,
+    ) -> Result<Option<InstanceInfo>> {
+        delegate!(self, s => s.find_instance_by_request_key(job_id, request_key))
+    }
+
+    fn find_all_app_id_by_current_server(&self, current_server: &str) -> Result<Option<Vec<u64>>> {
+        delegate!(self, s => s.find_all_app_id_by_current_server(current_server))
+    }
+
+    fn find_all_app_infos(&self) -> Result<Vec<AppInfo>> {
+        delegate!(self, s => s.find_all_app_infos())
+    }
+
+    fn find_cron_jobs(&self, ids: &[u64], time_threshold: i64) -> Result<Vec<JobInfo>> {
+        delegate!(self, s => s.find_cron_jobs(ids, time_threshold))
+    }
+
+    fn find_frequent_jobs(&self, ids: &[u64]) -> Result<Vec<JobInfo>> {
+        delegate!(self, s => s.find_frequent_jobs(ids))
+    }
+
+    fn find_one_shot_jobs(&self, ids: &[u64], time_threshold: i64) -> Result<Vec<JobInfo>> {
+        delegate!(self, s => s.find_one_shot_jobs(ids, time_threshold))
+    }
+
+    fn find_frequent_instance_by_job_id(&self, ids: &[u64]) -> Result<Vec<u64>> {
+        delegate!(self, s => s.find_frequent_instance_by_job_id(ids))
+    }
+
+    fn save_scheduled_instances(
+        &self,
+        previous_trigger_time: Option<i64>,
+        job: &JobInfo,
+        instances: &[InstanceInfo],
+    ) -> Result<bool> {
+        delegate!(self, s => s.save_scheduled_instances(previous_trigger_time, job, instances))
+    }
+
+    fn find_last_finished_time(&self, job_id: u64) -> Result<Option<i64>> {
+        delegate!(self, s => s.find_last_finished_time(job_id))
+    }
+
+    fn count_instance_by_status(&self, id: u64, status: Vec<u32>) -> Result<u64> {
+        delegate!(self, s => s.count_instance_by_status(id, status))
+    }
+
+    fn find_instances_by_app_status(
+        &self,
+        app_id: u64,
+        status: Vec<u32>,
+    ) -> Result<Vec<InstanceInfo>> {
+        delegate!(self, s => s.find_instances_by_app_status(app_id, status))
+    }
+
+    fn find_app_info_by_id(&self, id: u64) -> Result<Option<AppInfo>> {
+        delegate!(self, s => s.find_app_info_by_id(id))
+    }
+
+    fn find_calendar(&self, id: u64) -> Result<Option<CalendarInfo>> {
+        delegate!(self, s => s.find_calendar(id))
+    }
+
+    fn find_calendar_by_name(
+        &self,
+        app_id: u64,
+        calendar_name: &str,
+    ) -> Result<Option<CalendarInfo>> {
+        delegate!(self, s => s.find_calendar_by_name(app_id, calendar_name))
+    }
+
+    fn find_lock_by_name(&self, lock_name: &str) -> Result<Option<Lock>> {
+        delegate!(self, s => s.find_lock_by_name(lock_name))
+    }
+
+    fn acquire_lock(
+        &self,
+        lock_name: &str,
+        owner: &str,
+        max_lock_time: u64,
+        now: i64,
+    ) -> Result<Option<u64>> {
+        delegate!(self, s => s.acquire_lock(lock_name, owner, max_lock_time, now))
+    }
+
+    fn renew_lock(
+        &self,
+        lock_name: &str,
+        owner: &str,
+        fencing_token: u64,
+        now: i64,
+    ) -> Result<bool> {
+        delegate!(self, s => s.renew_lock(lock_name, owner, fencing_token, now))
+    }
+
+    fn release_lock(&self, lock_name: &str, owner: &str, fencing_token: u64) -> Result<bool> {
+        delegate!(self, s => s.release_lock(lock_name, owner, fencing_token))
+    }
+
+    fn update_app_current_server(
+        &self,
+        app_id: u64,
+        current_server: &str,
+        fencing_token: u64,
+    ) -> Result<bool> {
+        delegate!(self, s => s.update_app_current_server(app_id, current_server, fencing_token))
+    }
+
+    fn release_app_current_server(&self, current_server: &str) -> Result<u64> {
+        delegate!(self, s => s.release_app_current_server(current_server))
+    }
+
+    fn upsert_server_heartbeat(&self, ip: &str, now: i64) -> Result<()> {
+        delegate!(self, s => s.upsert_server_heartbeat(ip, now))
+    }
+
+    fn find_alive_servers(&self, since: i64) -> Result<Vec<ServerInfo>> {
+        delegate!(self, s => s.find_alive_servers(since))
+    }
+
+    fn delete_expired_servers(&self, before: i64) -> Result<u64> {
+        delegate!(self, s => s.delete_expired_servers(before))
+    }
+
+    fn delete_server(&self, ip: &str) -> Result<u64> {
+        delegate!(self, s => s.delete_server(ip))
+    }
+
+    fn find_tasks_by_instance_id(&self, instance_id: u64) -> Result<Vec<TaskInfo>> {
+        delegate!(self, s => s.find_tasks_by_instance_id(instance_id))
+    }
+
+    fn delete_tasks_by_instance_id(&self, instance_id: u64) -> Result<u64> {
+        delegate!(self, s => s.delete_tasks_by_instance_id(instance_id))
+    }
+
+    fn update_task_status(
+        &self,
+        instance_id: u64,
+        worker_address: &str,
+        status: u32,
+        result: &str,
+        now: i64,
+    ) -> Result<bool> {
+        delegate!(self, s => s.update_task_status(instance_id, worker_address, status, result, now))
+    }
+
+    fn find_task(&self, instance_id: u64, task_id: &str) -> Result<Option<TaskInfo>> {
+        delegate!(self, s => s.find_task(instance_id, task_id))
+    }
+
+    fn find_tasks_by_status(
+        &self,
+        instance_id: u64,
+        status: u32,
+        limit: u64,
+    ) -> Result<Vec<TaskInfo>> {
+        delegate!(self, s => s.find_tasks_by_status(instance_id, status, limit))
+    }
+
+    fn count_tasks_by_status(&self, instance_id: u64) -> Result<Vec<TaskStatusCount>> {
+        delegate!(self, s => s.count_tasks_by_status(instance_id))
+    }
+
+    fn find_cron_workflows(&self, ids: &[u64], time_threshold: i64) -> Result<Vec<WorkflowInfo>> {
+        delegate!(self, s => s.find_cron_workflows(ids, time_threshold))
+    }
+
+    fn find_workflow_nodes(&self, workflow_id: u64) -> Result<Vec<WorkflowNodeInfo>> {
+        delegate!(self, s => s.find_workflow_nodes(workflow_id))
+    }
+
+    fn find_workflow_instance(&self, wf_instance_id: u64) -> Result<Option<WorkflowInstanceInfo>> {
+        delegate!(self, s => s.find_workflow_instance(wf_instance_id))
+    }
+
+    fn find_workflow_instances_by_status(
+        &self,
+        ids: &[u64],
+        status: Vec<u32>,
+    ) -> Result<Vec<WorkflowInstanceInfo>> {
+        delegate!(self, s => s.find_workflow_instances_by_status(ids, status))
+    }
+
+    fn update_workflow_instance(&self, wf_instance: &WorkflowInstanceInfo) -> Result<bool> {
+        delegate!(self, s => s.update_workflow_instance(wf_instance))
     }
 }
@@ -0,0 +1,147 @@
+//! Lease based distributed lock on top of `Storage`.
+//!
+//! A lock is held until `gmt_modified + max_lock_time`, the holder has to `renew` it before
+//! then or any other server may take it over. Each acquisition hands out a fencing token
+//! larger than every earlier one, writes guarded by the lock should carry it so that a
+//! paused former holder can't overwrite the new holder's work.
+use crate::error::Result;
+use crate::Storage;
+use chrono::Local;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A held lock.
+#[derive(Clone, Debug)]
+pub struct Lease {
+    name: String,
+    owner: String,
+    fencing_token: u64,
+    ttl: Duration,
+    renewed_at: i64,
+}
+
+impl Lease {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    /// Strictly greater than the token of any earlier holder of the same lock.
+    pub fn fencing_token(&self) -> u64 {
+        self.fencing_token
+    }
+
+    /// Whether the lease is still held at `now`, from the holder's point of view.
+    pub fn is_valid(&self, now: i64) -> bool {
+        now < self.renewed_at + self.ttl.as_millis() as i64
+    }
+}
+
+pub struct LockService<S: Storage> {
+    storage: Arc<S>,
+}
+
+impl<S: Storage> Clone for LockService<S> {
+    fn clone(&self) -> Self {
+        Self {
+            storage: self.storage.clone(),
+        }
+    }
+}
+
+impl<S: Storage> LockService<S> {
+    pub fn new(storage: Arc<S>) -> Self {
+        Self { storage }
+    }
+
+    /// Take the lock `name` if it is free or its lease expired, `None` if someone else holds it.
+    pub fn try_acquire(&self, name: &str, owner: &str, ttl: Duration) -> Result<Option<Lease>> {
+        let now = Local::now().timestamp_millis();
+        let token = self
+            .storage
+            .acquire_lock(name, owner, ttl.as_millis() as u64, now)?;
+        Ok(token.map(|fencing_token| Lease {
+            name: name.to_string(),
+            owner: owner.to_string(),
+            fencing_token,
+            ttl,
+            renewed_at: now,
+        }))
+    }
+
+    /// Extend the lease by another `ttl`, `false` means the lock was lost and the lease
+    /// must not be used anymore.
+    pub fn renew(&self, lease: &mut Lease) -> Result<bool> {
+        let now = Local::now().timestamp_millis();
+        let renewed =
+            self.storage
+                .renew_lock(&lease.name, &lease.owner, lease.fencing_token, now)?;
+        if renewed {
+            lease.renewed_at = now;
+        }
+        Ok(renewed)
+    }
+
+    /// Give the lock up before it expires, `false` if it had already been taken over.
+    pub fn release(&self, lease: Lease) -> Result<bool> {
+        self.storage
+            .release_lock(&lease.name, &lease.owner, lease.fencing_token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::app_info::AppInfo;
+    use crate::MemoryStorage;
+
+    const TTL: Duration = Duration::from_secs(30);
+
+    #[test]
+    fn t_acquire_renew_release() {
+        let service = LockService::new(Arc::new(MemoryStorage::new()));
+        let mut lease = service.try_acquire("app-1", "server-a", TTL).unwrap().unwrap();
+        assert_eq!(lease.fencing_token(), 1);
+        assert!(service.try_acquire("app-1", "server-b", TTL).unwrap().is_none());
+        assert!(service.renew(&mut lease).unwrap());
+        assert!(service.release(lease).unwrap());
+
+        // tokens keep increasing after a release.
+        let lease = service.try_acquire("app-1", "server-b", TTL).unwrap().unwrap();
+        assert_eq!(lease.fencing_token(), 2);
+    }
+
+    #[test]
+    fn t_expired_lease_is_fenced() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage
+            .save(AppInfo {
+                id: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+        let service = LockService::new(storage.clone());
+
+        let mut stale = service
+            .try_acquire("app-1", "server-a", Duration::from_millis(0))
+            .unwrap()
+            .unwrap();
+        let fresh = service.try_acquire("app-1", "server-b", TTL).unwrap().unwrap();
+        assert!(fresh.fencing_token() > stale.fencing_token());
+
+        assert!(!service.renew(&mut stale).unwrap());
+        assert!(storage
+            .update_app_current_server(1, "server-b", fresh.fencing_token())
+            .unwrap());
+        assert!(!storage
+            .update_app_current_server(1, "server-a", stale.fencing_token())
+            .unwrap());
+        assert!(!service.release(stale).unwrap());
+
+        let app = storage.find_app_info_by_id(1).unwrap().unwrap();
+        assert_eq!(app.current_server.as_deref(), Some("server-b"));
+    }
+}
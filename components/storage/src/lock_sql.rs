@@ -0,0 +1,120 @@
+//! Lock statements shared by `MysqlStorage` and `SqliteStorage`.
+//!
+//! Every write is a compare-and-set on `fencing_token`, so two servers racing for the
+//! same expired lock can't both win.
+use crate::error::Result;
+use crate::model::lock::Lock;
+use rbatis::rbatis::Rbatis;
+use serde_json::json;
+
+pub(crate) async fn find_lock(rb: &Rbatis, lock_name: &str) -> Result<Option<Lock>> {
+    rb.fetch_prepare(
+        "",
+        "SELECT * FROM `lock` WHERE `lock_name` = ?",
+        &vec![json!(lock_name)],
+    )
+    .await
+}
+
+pub(crate) async fn acquire_lock(
+    rb: &Rbatis,
+    lock_name: &str,
+    owner: &str,
+    max_lock_time: u64,
+    now: i64,
+) -> Result<Option<u64>> {
+    let lock = match find_lock(rb, lock_name).await? {
+        Some(lock) => lock,
+        None => {
+            let rs = rb
+                .exec_prepare(
+                    "",
+                    "INSERT INTO `lock` (`lock_name`, `owner_ip`, `max_lock_time`, `fencing_token`, `gmt_create`, `gmt_modified`) VALUES (?, ?, ?, 1, ?, ?)",
+                    &vec![json!(lock_name), json!(owner), json!(max_lock_time), json!(now), json!(now)],
+                )
+                .await;
+            return match rs {
+                Ok(_) => Ok(Some(1)),
+                // lost the race on `lockNameUK`.
+                Err(_) if find_lock(rb, lock_name).await?.is_some() => Ok(None),
+                Err(e) => Err(e),
+            };
+        }
+    };
+    if !lock.is_free(now) && lock.owner_ip.as_deref() != Some(owner) {
+        return Ok(None);
+    }
+    let token = lock.fencing_token.unwrap_or(0);
+    let rs = rb
+        .exec_prepare(
+            "",
+            "UPDATE `lock` SET `owner_ip` = ?, `max_lock_time` = ?, `fencing_token` = ?, `gmt_modified` = ? WHERE `lock_name` = ? AND `fencing_token` = ?",
+            &vec![json!(owner), json!(max_lock_time), json!(token + 1), json!(now), json!(lock_name), json!(token)],
+        )
+        .await?;
+    if rs.rows_affected == 1 {
+        return Ok(Some(token + 1));
+    }
+    Ok(None)
+}
+
+pub(crate) async fn renew_lock(
+    rb: &Rbatis,
+    lock_name: &str,
+    owner: &str,
+    fencing_token: u64,
+    now: i64,
+) -> Result<bool> {
+    let rs = rb
+        .exec_prepare(
+            "",
+            "UPDATE `lock` SET `gmt_modified` = ? WHERE `lock_name` = ? AND `owner_ip` = ? AND `fencing_token` = ?",
+            &vec![json!(now), json!(lock_name), json!(owner), json!(fencing_token)],
+        )
+        .await?;
+    Ok(rs.rows_affected == 1)
+}
+
+pub(crate) async fn release_lock(
+    rb: &Rbatis,
+    lock_name: &str,
+    owner: &str,
+    fencing_token: u64,
+) -> Result<bool> {
+    // the row is kept so the next acquisition continues from `fencing_token`.
+    let rs = rb
+        .exec_prepare(
+            "",
+            "UPDATE `lock` SET `owner_ip` = NULL WHERE `lock_name` = ? AND `owner_ip` = ? AND `fencing_token` = ?",
+            &vec![json!(lock_name), json!(owner), json!(fencing_token)],
+        )
+        .await?;
+    Ok(rs.rows_affected == 1)
+}
+
+pub(crate) async fn update_app_current_server(
+    rb: &Rbatis,
+    app_id: u64,
+    current_server: &str,
+    fencing_token: u64,
+) -> Result<bool> {
+    let rs = rb
+        .exec_prepare(
+            "",
+            "UPDATE `app_info` SET `current_server` = ?, `fencing_token` = ? WHERE `id` = ? AND `fencing_token` <= ?",
+            &vec![json!(current_server), json!(fencing_token), json!(app_id), json!(fencing_token)],
+        )
+        .await?;
+    Ok(rs.rows_affected == 1)
+}
+
+pub(crate) async fn release_app_current_server(rb: &Rbatis, current_server: &str) -> Result<u64> {
+    let rs = rb
+        .exec_prepare(
+            "",
+            "UPDATE `app_info` SET `current_server` = NULL WHERE `current_server` = ?",
+            &vec![json!(current_server)],
+        )
+        .await?;
+    Ok(rs.rows_affected)
+}
@@ -0,0 +1,1018 @@
+use crate::error::Result;
+use crate::model::app_info::AppInfo;
+use crate::model::calendar_info::CalendarInfo;
+use crate::model::instance_info::InstanceInfo;
+use crate::model::job_info::{JobInfo, JobStatus, JobTimeExpressionType};
+use crate::model::lock::Lock;
+use crate::model::server_info::ServerInfo;
+use crate::model::task_info::{TaskInfo, TaskStatusCount};
+use crate::model::workflow_info::WorkflowInfo;
+use crate::model::workflow_instance_info::WorkflowInstanceInfo;
+use crate::model::workflow_node_info::WorkflowNodeInfo;
+use crate::Storage;
+use rbatis::crud::CRUDTable;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+type Table = BTreeMap<u64, Value>;
+
+/// A thread-safe `Storage` kept in memory, rows are stored as json per table.
+///
+/// Clones share the same tables so it can be handed to `WorkerManager`, `Scheduler` and
+/// `Dispatch` at the same time. Faults can be injected to exercise the error paths.
+#[derive(Clone, Default)]
+pub struct MemoryStorage {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    tables: RwLock<HashMap<String, Table>>,
+    calls: AtomicUsize,
+    fault: Mutex<Fault>,
+}
+
+#[derive(Default)]
+struct Fault {
+    /// The absolute call number that will fail.
+    fail_on: Option<usize>,
+    latency: Option<Duration>,
+}
+
+impl Debug for MemoryStorage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("memory-storage")
+            .field("calls", &self.calls())
+            .finish()
+    }
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The `n`th storage call from now on (starting at 1) returns an error.
+    pub fn fail_nth_call(&self, n: usize) {
+        let calls = self.calls();
+        self.inner.fault.lock().unwrap().fail_on = Some(calls + n.max(1));
+    }
+
+    /// Every storage call sleeps `latency` before it is executed.
+    pub fn set_latency(&self, latency: Duration) {
+        self.inner.fault.lock().unwrap().latency = Some(latency);
+    }
+
+    pub fn clear_faults(&self) {
+        *self.inner.fault.lock().unwrap() = Fault::default();
+    }
+
+    /// The number of storage calls so far.
+    pub fn calls(&self) -> usize {
+        self.inner.calls.load(Ordering::SeqCst)
+    }
+
+    fn inject(&self, op: &str) -> Result<()> {
+        let call = self.inner.calls.fetch_add(1, Ordering::SeqCst) + 1;
+        let (fail, latency) = {
+            let mut fault = self.inner.fault.lock().unwrap();
+            let fail = fault.fail_on == Some(call);
+            if fail {
+                fault.fail_on = None;
+            }
+            (fail, fault.latency)
+        };
+        if let Some(latency) = latency {
+            std::thread::sleep(latency);
+        }
+        if fail {
+            return Err(rbatis::Error::from(format!(
+                "memory storage injected failure on call {} ({})",
+                call, op
+            )));
+        }
+        Ok(())
+    }
+
+    fn insert<T>(&self, model: &T) -> Result<()>
+        where
+            T: CRUDTable,
+    {
+        let mut value = to_value(model)?;
+        let mut tables = self.inner.tables.write().unwrap();
+        let table = tables.entry(T::table_name()).or_default();
+        let id = match id_of::<T>(model.get_id()) {
+            Some(id) => id,
+            // auto increment.
+            None => {
+                let id = table.keys().next_back().map_or(1, |id| id + 1);
+                value["id"] = Value::from(id);
+                id
+            }
+        };
+        if table.contains_key(&id) {
+            return Err(rbatis::Error::from(format!(
+                "Duplicate entry '{}' for key '{}.PRIMARY'",
+                id,
+                T::table_name()
+            )));
+        }
+        table.insert(id, value);
+        Ok(())
+    }
+
+    fn rows<T>(&self, table: &str) -> Result<Vec<T>>
+        where
+            T: DeserializeOwned,
+    {
+        let tables = self.inner.tables.read().unwrap();
+        match tables.get(table) {
+            Some(rows) => rows
+                .values()
+                .map(|row| {
+                    serde_json::from_value(row.clone())
+                        .map_err(|e| rbatis::Error::from(e.to_string()))
+                })
+                .collect(),
+            None => Ok(vec![]),
+        }
+    }
+
+    fn jobs(&self) -> Result<Vec<JobInfo>> {
+        self.rows(&JobInfo::table_name())
+    }
+
+    fn instances(&self) -> Result<Vec<InstanceInfo>> {
+        self.rows(&InstanceInfo::table_name())
+    }
+
+    /// The tasks of the instance ordered by id.
+    fn tasks(&self, instance_id: u64) -> Result<Vec<TaskInfo>> {
+        Ok(self
+            .rows::<TaskInfo>(&TaskInfo::table_name())?
+            .into_iter()
+            .filter(|task| task.instance_id == Some(instance_id))
+            .collect())
+    }
+
+    /// Run `f` on the first row of `table` matching `predicate` while holding the write lock,
+    /// which makes the read-check-write of the lock methods atomic.
+    fn modify_row<T, R>(
+        &self,
+        predicate: impl Fn(&T) -> bool,
+        f: impl FnOnce(Option<&mut T>) -> R,
+    ) -> Result<R>
+        where
+            T: CRUDTable,
+    {
+        let mut tables = self.inner.tables.write().unwrap();
+        let table = tables.entry(T::table_name()).or_default();
+        for row in table.values_mut() {
+            let mut model: T = serde_json::from_value(row.clone())
+                .map_err(|e| rbatis::Error::from(e.to_string()))?;
+            if predicate(&model) {
+                let r = f(Some(&mut model));
+                *row = to_value(&model)?;
+                return Ok(r);
+            }
+        }
+        Ok(f(None))
+    }
+}
+
+fn to_value<T: serde::Serialize>(model: &T) -> Result<Value> {
+    serde_json::to_value(model).map_err(|e| rbatis::Error::from(e.to_string()))
+}
+
+fn id_of<T: CRUDTable>(id: Option<&T::IdType>) -> Option<u64> {
+    id.and_then(|id| serde_json::to_value(id).ok())
+        .and_then(|id| id.as_u64())
+}
+
+impl Storage for MemoryStorage {
+    fn save<T>(&self, model: T) -> Result<()>
+        where
+            T: CRUDTable,
+    {
+        self.inject("save")?;
+        self.insert(&model)
+    }
+
+    fn save_batch<T>(&self, models: &[T]) -> Result<()>
+        where
+            T: CRUDTable,
+    {
+        self.inject("save_batch")?;
+        for model in models {
+            self.insert(model)?;
+        }
+        Ok(())
+    }
+
+    fn delete<T>(&self, id: &T::IdType) -> Result<u64>
+        where
+            T: CRUDTable,
+    {
+        self.inject("delete")?;
+        let mut tables = self.inner.tables.write().unwrap();
+        let removed = match (tables.get_mut(&T::table_name()), id_of::<T>(Some(id))) {
+            (Some(table), Some(id)) => table.remove(&id).is_some(),
+            _ => false,
+        };
+        Ok(removed as u64)
+    }
+
+    fn delete_batch<T>(&self, ids: &[T::IdType]) -> Result<()>
+        where
+            T: CRUDTable,
+    {
+        self.inject("delete_batch")?;
+        let mut tables = self.inner.tables.write().unwrap();
+        if let Some(table) = tables.get_mut(&T::table_name()) {
+            for id in ids.iter().filter_map(|id| id_of::<T>(Some(id))) {
+                table.remove(&id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Same as `update_by_id` in rbatis, `None` fields are left untouched.
+    fn update<T>(&self, models: &mut [T]) -> Result<()>
+        where
+            T: CRUDTable,
+    {
+        self.inject("update")?;
+        let mut tables = self.inner.tables.write().unwrap();
+        let table = tables.entry(T::table_name()).or_default();
+        for model in models.iter() {
+            let id = match id_of::<T>(model.get_id()) {
+                Some(id) => id,
+                None => continue,
+            };
+            if let (Some(Value::Object(row)), Value::Object(fields)) =
+            (table.get_mut(&id), to_value(model)?)
+            {
+                for (k, v) in fields.into_iter().filter(|(_, v)| !v.is_null()) {
+                    row.insert(k, v);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn find_job_info_by_instance_id(&self, instance_id: u64) -> Result<Option<JobInfo>> {
+        self.inject("find_job_info_by_instance_id")?;
+        let job_id = self
+            .instances()?
+            .into_iter()
+            .find(|instance| instance.instance_id == Some(instance_id))
+            .and_then(|instance| instance.job_id);
+        match job_id {
+            Some(id) => Ok(self.jobs()?.into_iter().find(|job| job.id == Some(id))),
+            None => Ok(None),
+        }
+    }
+
+    fn find_job_info_by_id(&self, id: u64) -> Result<Option<JobInfo>> {
+        self.inject("find_job_info_by_id")?;
+        Ok(self.jobs()?.into_iter().find(|job| job.id == Some(id)))
+    }
+
+    fn find_instance_by_id(&self, instance_id: u64) -> Result<Option<InstanceInfo>> {
+        self.inject("find_instance_by_id")?;
+        Ok(self
+            .instances()?
+            .into_iter()
+            .find(|instance| instance.instance_id == Some(instance_id)))
+    }
+
+    fn find_instance_by_ids(&self, instance_id: &[u64]) -> Result<Option<Vec<InstanceInfo>>> {
+        self.inject("find_instance_by_ids")?;
+        let instances: Vec<_> = self
+            .instances()?
+            .into_iter()
+            .filter(|instance| {
+                instance
+                    .instance_id
+                    .map_or(false, |id| instance_id.contains(&id))
+            })
+            .collect();
+        if instances.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(instances))
+    }
+
+    fn find_instance_by_request_key(
+        &self,
+        job_id: u64,
+        request_key: &str,
+    ) -> Result<Option<InstanceInfo>> {
+        self.inject("find_instance_by_request_key")?;
+        Ok(self.instances()?.into_iter().find(|instance| {
+            instance.job_id == Some(job_id)
+                && instance.request_key.as_deref() == Some(request_key)
+        }))
+    }
+
+    fn find_all_app_id_by_current_server(&self, current_server: &str) -> Result<Option<Vec<u64>>> {
+        self.inject("find_all_app_id_by_current_server")?;
+        let ids: Vec<u64> = self
+            .rows::<AppInfo>(&AppInfo::table_name())?
+            .into_iter()
+            .filter(|app| app.current_server.as_deref() == Some(current_server))
+            .filter_map(|app| app.id)
+            .collect();
+        if ids.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(ids))
+    }
+
+    fn find_all_app_infos(&self) -> Result<Vec<AppInfo>> {
+        self.inject("find_all_app_infos")?;
+        self.rows(&AppInfo::table_name())
+    }
+
+    fn find_cron_jobs(&self, ids: &[u64], time_threshold: i64) -> Result<Vec<JobInfo>> {
+        self.inject("find_cron_jobs")?;
+        let status: usize = JobStatus::Running.into();
+        let typ: usize = JobTimeExpressionType::CRON.into();
+        Ok(self
+            .jobs()?
+            .into_iter()
+            .filter(|job| {
+                job.app_id.map_or(false, |id| ids.contains(&id))
+                    && job.status == Some(status)
+                    && job.time_expression_type == Some(typ)
+                    && job.next_trigger_time.map_or(false, |t| t <= time_threshold)
+            })
+            .collect())
+    }
+
+    fn find_frequent_jobs(&self, ids: &[u64]) -> Result<Vec<JobInfo>> {
+        self.inject("find_frequent_jobs")?;
+        let status: usize = JobStatus::Running.into();
+        let types: [usize; 2] = [
+            JobTimeExpressionType::FixRate.into(),
+            JobTimeExpressionType::FixDelay.into(),
+        ];
+        Ok(self
+            .jobs()?
+            .into_iter()
+            .filter(|job| {
+                job.app_id.map_or(false, |id| ids.contains(&id))
+                    && job.status == Some(status)
+                    && job
+                    .time_expression_type
+                    .map_or(false, |t| types.contains(&t))
+            })
+            .collect())
+    }
+
+    fn find_one_shot_jobs(&self, ids: &[u64], time_threshold: i64) -> Result<Vec<JobInfo>> {
+        self.inject("find_one_shot_jobs")?;
+        let status: usize = JobStatus::Running.into();
+        let typ: usize = JobTimeExpressionType::OneShot.into();
+        Ok(self
+            .jobs()?
+            .into_iter()
+            .filter(|job| {
+                job.app_id.map_or(false, |id| ids.contains(&id))
+                    && job.status == Some(status)
+                    && job.time_expression_type == Some(typ)
+                    && job.next_trigger_time.map_or(false, |t| t <= time_threshold)
+            })
+            .collect())
+    }
+
+    fn find_frequent_instance_by_job_id(&self, ids: &[u64]) -> Result<Vec<u64>> {
+        self.inject("find_frequent_instance_by_job_id")?;
+        let running = InstanceInfo::generalized_running_status();
+        let mut job_ids: Vec<u64> = self
+            .instances()?
+            .into_iter()
+            .filter(|instance| instance.status.map_or(false, |s| running.contains(&s)))
+            .filter_map(|instance| instance.job_id)
+            .filter(|id| ids.contains(id))
+            .collect();
+        job_ids.sort_unstable();
+        job_ids.dedup();
+        Ok(job_ids)
+    }
+
+    fn save_scheduled_instances(
+        &self,
+        previous_trigger_time: Option<i64>,
+        job: &JobInfo,
+        instances: &[InstanceInfo],
+    ) -> Result<bool> {
+        self.inject("save_scheduled_instances")?;
+        let running: usize = JobStatus::Running.into();
+        let job_id = job.id.unwrap_or_default();
+        // the write lock is the transaction.
+        let mut tables = self.inner.tables.write().unwrap();
+        let mut stored: JobInfo = match tables
+            .get(&JobInfo::table_name())
+            .and_then(|jobs| jobs.get(&job_id))
+        {
+            Some(row) => serde_json::from_value(row.clone())
+                .map_err(|e| rbatis::Error::from(e.to_string()))?,
+            None => return Ok(false),
+        };
+        if stored.status != Some(running)
+            || stored.next_trigger_time.unwrap_or(-1) != previous_trigger_time.unwrap_or(-1)
+        {
+            return Ok(false);
+        }
+
+        let table = tables.entry(InstanceInfo::table_name()).or_default();
+        for row in table.values() {
+            let saved: InstanceInfo = serde_json::from_value(row.clone())
+                .map_err(|e| rbatis::Error::from(e.to_string()))?;
+            let slot = saved.trigger_slot;
+            if slot.is_some()
+                && instances
+                    .iter()
+                    .any(|i| i.job_id == saved.job_id && i.trigger_slot == slot)
+            {
+                return Err(rbatis::Error::from(format!(
+                    "Duplicate entry '{}-{}' for key 'instance_info.UK_instance_info_trigger_slot'",
+                    job_id,
+                    slot.unwrap_or_default()
+                )));
+            }
+            let key = saved.request_key.as_deref();
+            if key.is_some()
+                && instances
+                    .iter()
+                    .any(|i| i.job_id == saved.job_id && i.request_key.as_deref() == key)
+            {
+                return Err(rbatis::Error::from(format!(
+                    "Duplicate entry '{}-{}' for key 'instance_info.UK_instance_info_request_key'",
+                    job_id,
+                    key.unwrap_or_default()
+                )));
+            }
+        }
+        for instance in instances {
+            let id = table.keys().next_back().map_or(1, |id| id + 1);
+            let mut value = to_value(instance)?;
+            value["id"] = Value::from(id);
+            table.insert(id, value);
+        }
+
+        stored.next_trigger_time = job.next_trigger_time;
+        stored.status = job.status.or(Some(running));
+        let row = to_value(&stored)?;
+        tables
+            .entry(JobInfo::table_name())
+            .or_default()
+            .insert(job_id, row);
+        Ok(true)
+    }
+
+    fn find_last_finished_time(&self, job_id: u64) -> Result<Option<i64>> {
+        self.inject("find_last_finished_time")?;
+        Ok(self
+            .instances()?
+            .into_iter()
+            .filter(|instance| instance.job_id == Some(job_id))
+            .filter_map(|instance| instance.finished_time)
+            .filter(|t| *t > 0)
+            .max())
+    }
+
+    fn count_instance_by_status(&self, id: u64, status: Vec<u32>) -> Result<u64> {
+        self.inject("count_instance_by_status")?;
+        Ok(self
+            .instances()?
+            .into_iter()
+            .filter(|instance| {
+                instance.job_id == Some(id)
+                    && instance.status.map_or(false, |s| status.contains(&s))
+            })
+            .count() as u64)
+    }
+
+    fn find_instances_by_app_status(
+        &self,
+        app_id: u64,
+        status: Vec<u32>,
+    ) -> Result<Vec<InstanceInfo>> {
+        self.inject("find_instances_by_app_status")?;
+        Ok(self
+            .instances()?
+            .into_iter()
+            .filter(|instance| {
+                instance.app_id == Some(app_id)
+                    && instance.status.map_or(false, |s| status.contains(&s))
+            })
+            .collect())
+    }
+
+    fn find_app_info_by_id(&self, id: u64) -> Result<Option<AppInfo>> {
+        self.inject("find_app_info_by_id")?;
+        Ok(self
+            .rows::<AppInfo>(&AppInfo::table_name())?
+            .into_iter()
+            .find(|app| app.id == Some(id)))
+    }
+
+    fn find_calendar(&self, id: u64) -> Result<Option<CalendarInfo>> {
+        self.inject("find_calendar")?;
+        Ok(self
+            .rows::<CalendarInfo>(&CalendarInfo::table_name())?
+            .into_iter()
+            .find(|calendar| calendar.id == Some(id)))
+    }
+
+    fn find_calendar_by_name(
+        &self,
+        app_id: u64,
+        calendar_name: &str,
+    ) -> Result<Option<CalendarInfo>> {
+        self.inject("find_calendar_by_name")?;
+        Ok(self
+            .rows::<CalendarInfo>(&CalendarInfo::table_name())?
+            .into_iter()
+            .find(|calendar| {
+                calendar.app_id == Some(app_id)
+                    && calendar.calendar_name.as_deref() == Some(calendar_name)
+            }))
+    }
+
+    fn find_lock_by_name(&self, lock_name: &str) -> Result<Option<Lock>> {
+        self.inject("find_lock_by_name")?;
+        Ok(self
+            .rows::<Lock>(&Lock::table_name())?
+            .into_iter()
+            .find(|lock| lock.lock_name.as_deref() == Some(lock_name)))
+    }
+
+    fn acquire_lock(
+        &self,
+        lock_name: &str,
+        owner: &str,
+        max_lock_time: u64,
+        now: i64,
+    ) -> Result<Option<u64>> {
+        self.inject("acquire_lock")?;
+        let mut tables = self.inner.tables.write().unwrap();
+        let table = tables.entry(Lock::table_name()).or_default();
+        for row in table.values_mut() {
+            let mut lock: Lock = serde_json::from_value(row.clone())
+                .map_err(|e| rbatis::Error::from(e.to_string()))?;
+            if lock.lock_name.as_deref() != Some(lock_name) {
+                continue;
+            }
+            if !lock.is_free(now) && lock.owner_ip.as_deref() != Some(owner) {
+                return Ok(None);
+            }
+            let token = lock.fencing_token.unwrap_or(0) + 1;
+            lock.owner_ip = Some(owner.to_string());
+            lock.max_lock_time = Some(max_lock_time);
+            lock.fencing_token = Some(token);
+            lock.gmt_modified = Some(now);
+            *row = to_value(&lock)?;
+            return Ok(Some(token));
+        }
+        let id = table.keys().next_back().map_or(1, |id| id + 1);
+        let mut lock = Lock::new(lock_name, max_lock_time, owner);
+        lock.id = Some(id);
+        lock.fencing_token = Some(1);
+        lock.gmt_create = Some(now);
+        lock.gmt_modified = Some(now);
+        table.insert(id, to_value(&lock)?);
+        Ok(Some(1))
+    }
+
+    fn renew_lock(
+        &self,
+        lock_name: &str,
+        owner: &str,
+        fencing_token: u64,
+        now: i64,
+    ) -> Result<bool> {
+        self.inject("renew_lock")?;
+        self.modify_row(
+            |lock: &Lock| {
+                lock.lock_name.as_deref() == Some(lock_name)
+                    && lock.owner_ip.as_deref() == Some(owner)
+                    && lock.fencing_token == Some(fencing_token)
+            },
+            |lock| lock.map(|lock| lock.gmt_modified = Some(now)).is_some(),
+        )
+    }
+
+    fn release_lock(&self, lock_name: &str, owner: &str, fencing_token: u64) -> Result<bool> {
+        self.inject("release_lock")?;
+        self.modify_row(
+            |lock: &Lock| {
+                lock.lock_name.as_deref() == Some(lock_name)
+                    && lock.owner_ip.as_deref() == Some(owner)
+                    && lock.fencing_token == Some(fencing_token)
+            },
+            |lock| lock.map(|lock| lock.owner_ip = None).is_some(),
+        )
+    }
+
+    fn update_app_current_server(
+        &self,
+        app_id: u64,
+        current_server: &str,
+        fencing_token: u64,
+    ) -> Result<bool> {
+        self.inject("update_app_current_server")?;
+        self.modify_row(
+            |app: &AppInfo| {
+                app.id == Some(app_id) && app.fencing_token.unwrap_or(0) <= fencing_token
+            },
+            |app| {
+                app.map(|app| {
+                    app.current_server = Some(current_server.to_string());
+                    app.fencing_token = Some(fencing_token);
+                })
+                .is_some()
+            },
+        )
+    }
+
+    fn find_task(&self, instance_id: u64, task_id: &str) -> Result<Option<TaskInfo>> {
+        self.inject("find_task")?;
+        Ok(self
+            .tasks(instance_id)?
+            .into_iter()
+            .find(|task| task.task_id.as_deref() == Some(task_id)))
+    }
+
+    fn find_tasks_by_status(
+        &self,
+        instance_id: u64,
+        status: u32,
+        limit: u64,
+    ) -> Result<Vec<TaskInfo>> {
+        self.inject("find_tasks_by_status")?;
+        Ok(self
+            .tasks(instance_id)?
+            .into_iter()
+            .filter(|task| task.status == Some(status))
+            .take(limit as usize)
+            .collect())
+    }
+
+    fn count_tasks_by_status(&self, instance_id: u64) -> Result<Vec<TaskStatusCount>> {
+        self.inject("count_tasks_by_status")?;
+        let mut counts: BTreeMap<(Option<u32>, Option<u32>), u64> = BTreeMap::new();
+        for task in self.tasks(instance_id)? {
+            *counts.entry((task.task_type, task.status)).or_default() += 1;
+        }
+        Ok(counts
+            .into_iter()
+            .map(|((task_type, status), count)| TaskStatusCount {
+                task_type,
+                status,
+                count,
+            })
+            .collect())
+    }
+
+    fn find_cron_workflows(&self, ids: &[u64], time_threshold: i64) -> Result<Vec<WorkflowInfo>> {
+        self.inject("find_cron_workflows")?;
+        let status: usize = JobStatus::Running.into();
+        let typ: usize = JobTimeExpressionType::CRON.into();
+        Ok(self
+            .rows::<WorkflowInfo>(&WorkflowInfo::table_name())?
+            .into_iter()
+            .filter(|workflow| {
+                workflow.app_id.map_or(false, |id| ids.contains(&id))
+                    && workflow.status == Some(status)
+                    && workflow.time_expression_type == Some(typ)
+                    && workflow.next_trigger_time.map_or(false, |t| t <= time_threshold)
+            })
+            .collect())
+    }
+
+    fn find_workflow_nodes(&self, workflow_id: u64) -> Result<Vec<WorkflowNodeInfo>> {
+        self.inject("find_workflow_nodes")?;
+        Ok(self
+            .rows::<WorkflowNodeInfo>(&WorkflowNodeInfo::table_name())?
+            .into_iter()
+            .filter(|node| node.workflow_id == Some(workflow_id))
+            .collect())
+    }
+
+    fn find_workflow_instance(&self, wf_instance_id: u64) -> Result<Option<WorkflowInstanceInfo>> {
+        self.inject("find_workflow_instance")?;
+        Ok(self
+            .rows::<WorkflowInstanceInfo>(&WorkflowInstanceInfo::table_name())?
+            .into_iter()
+            .find(|instance| instance.wf_instance_id == Some(wf_instance_id)))
+    }
+
+    fn find_workflow_instances_by_status(
+        &self,
+        ids: &[u64],
+        status: Vec<u32>,
+    ) -> Result<Vec<WorkflowInstanceInfo>> {
+        self.inject("find_workflow_instances_by_status")?;
+        Ok(self
+            .rows::<WorkflowInstanceInfo>(&WorkflowInstanceInfo::table_name())?
+            .into_iter()
+            .filter(|instance| {
+                instance.app_id.map_or(false, |id| ids.contains(&id))
+                    && instance.status.map_or(false, |s| status.contains(&s))
+            })
+            .collect())
+    }
+
+    fn update_workflow_instance(&self, wf_instance: &WorkflowInstanceInfo) -> Result<bool> {
+        self.inject("update_workflow_instance")?;
+        let version = wf_instance.version.unwrap_or(0);
+        self.modify_row(
+            |row: &WorkflowInstanceInfo| {
+                row.id.is_some() && row.id == wf_instance.id && row.version.unwrap_or(0) == version
+            },
+            |row| {
+                row.map(|row| {
+                    row.dag = wf_instance.dag.clone();
+                    row.wf_context = wf_instance.wf_context.clone();
+                    row.status = wf_instance.status;
+                    row.result = wf_instance.result.clone();
+                    row.finished_time = wf_instance.finished_time;
+                    row.version = Some(version + 1);
+                })
+                .is_some()
+            },
+        )
+    }
+
+    fn release_app_current_server(&self, current_server: &str) -> Result<u64> {
+        self.inject("release_app_current_server")?;
+        let mut tables = self.inner.tables.write().unwrap();
+        let table = tables.entry(AppInfo::table_name()).or_default();
+        let mut released = 0;
+        for row in table.values_mut() {
+            if row["current_server"].as_str() == Some(current_server) {
+                row["current_server"] = Value::Null;
+                released += 1;
+            }
+        }
+        Ok(released)
+    }
+
+    fn upsert_server_heartbeat(&self, ip: &str, now: i64) -> Result<()> {
+        self.inject("upsert_server_heartbeat")?;
+        let updated = self.modify_row(
+            |server: &ServerInfo| server.ip.as_deref() == Some(ip),
+            |server| server.map(|server| server.last_heartbeat = Some(now)).is_some(),
+        )?;
+        if !updated {
+            self.insert(&ServerInfo {
+                ip: Some(ip.to_string()),
+                last_heartbeat: Some(now),
+                ..Default::default()
+            })?;
+        }
+        Ok(())
+    }
+
+    fn find_alive_servers(&self, since: i64) -> Result<Vec<ServerInfo>> {
+        self.inject("find_alive_servers")?;
+        let mut servers: Vec<ServerInfo> = self
+            .rows::<ServerInfo>(&ServerInfo::table_name())?
+            .into_iter()
+            .filter(|server| server.last_heartbeat.map_or(false, |t| t >= since))
+            .collect();
+        servers.sort_by(|a, b| a.ip.cmp(&b.ip));
+        Ok(servers)
+    }
+
+    fn delete_expired_servers(&self, before: i64) -> Result<u64> {
+        self.inject("delete_expired_servers")?;
+        let mut tables = self.inner.tables.write().unwrap();
+        let table = tables.entry(ServerInfo::table_name()).or_default();
+        let count = table.len();
+        table.retain(|_, row| row["last_heartbeat"].as_i64().map_or(false, |t| t >= before));
+        Ok((count - table.len()) as u64)
+    }
+
+    fn delete_server(&self, ip: &str) -> Result<u64> {
+        self.inject("delete_server")?;
+        let mut tables = self.inner.tables.write().unwrap();
+        let table = tables.entry(ServerInfo::table_name()).or_default();
+        let count = table.len();
+        table.retain(|_, row| row["ip"].as_str() != Some(ip));
+        Ok((count - table.len()) as u64)
+    }
+
+    fn find_tasks_by_instance_id(&self, instance_id: u64) -> Result<Vec<TaskInfo>> {
+        self.inject("find_tasks_by_instance_id")?;
+        let mut tasks: Vec<TaskInfo> = self
+            .rows::<TaskInfo>(&TaskInfo::table_name())?
+            .into_iter()
+            .filter(|task| task.instance_id == Some(instance_id))
+            .collect();
+        tasks.sort_by(|a, b| a.worker_address.cmp(&b.worker_address));
+        Ok(tasks)
+    }
+
+    fn delete_tasks_by_instance_id(&self, instance_id: u64) -> Result<u64> {
+        self.inject("delete_tasks_by_instance_id")?;
+        let mut tables = self.inner.tables.write().unwrap();
+        let table = tables.entry(TaskInfo::table_name()).or_default();
+        let count = table.len();
+        table.retain(|_, row| row["instance_id"].as_u64() != Some(instance_id));
+        Ok((count - table.len()) as u64)
+    }
+
+    fn update_task_status(
+        &self,
+        instance_id: u64,
+        worker_address: &str,
+        status: u32,
+        result: &str,
+        now: i64,
+    ) -> Result<bool> {
+        self.inject("update_task_status")?;
+        self.modify_row(
+            |task: &TaskInfo| {
+                task.instance_id == Some(instance_id)
+                    && task.worker_address.as_deref() == Some(worker_address)
+            },
+            |task| {
+                task.map(|task| {
+                    task.status = Some(status);
+                    task.result = Some(result.to_string());
+                    task.gmt_modified = Some(now);
+                })
+                .is_some()
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::instance_info::InstanceStatus;
+
+    fn cron_job(id: u64, app_id: u64, next_trigger_time: i64) -> JobInfo {
+        JobInfo {
+            id: Some(id),
+            app_id: Some(app_id),
+            status: Some(JobStatus::Running.into()),
+            time_expression_type: Some(JobTimeExpressionType::CRON.into()),
+            time_expression: Some("0 * * * * *".to_string()),
+            next_trigger_time: Some(next_trigger_time),
+            ..Default::default()
+        }
+    }
+
+    fn instance(instance_id: u64, job_id: u64, status: InstanceStatus) -> InstanceInfo {
+        InstanceInfo {
+            instance_id: Some(instance_id),
+            job_id: Some(job_id),
+            status: Some(status.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn t_find_cron_jobs() {
+        let storage = MemoryStorage::new();
+        storage
+            .save_batch(&[cron_job(1, 1, 100), cron_job(2, 1, 200), cron_job(3, 2, 100)])
+            .unwrap();
+
+        let ids: Vec<_> = storage
+            .find_cron_jobs(&[1], 100)
+            .unwrap()
+            .iter()
+            .filter_map(|job| job.id)
+            .collect();
+        assert_eq!(ids, vec![1]);
+        assert_eq!(storage.find_cron_jobs(&[1, 2], 200).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn t_find_one_shot_jobs() {
+        let storage = MemoryStorage::new();
+        let one_shot = JobInfo {
+            time_expression_type: Some(JobTimeExpressionType::OneShot.into()),
+            time_expression: Some("+30000".to_string()),
+            ..cron_job(1, 1, 100)
+        };
+        storage.save_batch(&[one_shot, cron_job(2, 1, 100)]).unwrap();
+
+        assert!(storage.find_one_shot_jobs(&[1], 99).unwrap().is_empty());
+        let ids: Vec<_> = storage
+            .find_one_shot_jobs(&[1], 100)
+            .unwrap()
+            .iter()
+            .filter_map(|job| job.id)
+            .collect();
+        assert_eq!(ids, vec![1]);
+    }
+
+    #[test]
+    fn t_save_scheduled_instances() {
+        let storage = MemoryStorage::new();
+        storage.save(cron_job(1, 1, 100)).unwrap();
+        let slot = |trigger_time: i64| InstanceInfo {
+            job_id: Some(1),
+            trigger_slot: Some(trigger_time),
+            ..Default::default()
+        };
+        let advanced = cron_job(1, 1, 200);
+        assert!(storage
+            .save_scheduled_instances(Some(100), &advanced, &[slot(100)])
+            .unwrap());
+        assert_eq!(storage.find_job_info_by_id(1).unwrap().unwrap().next_trigger_time, Some(200));
+
+        // a second server scheduling the same round saves nothing.
+        assert!(!storage
+            .save_scheduled_instances(Some(100), &advanced, &[slot(100)])
+            .unwrap());
+        assert!(storage
+            .save_scheduled_instances(Some(200), &cron_job(1, 1, 300), &[slot(100)])
+            .is_err());
+        assert_eq!(storage.instances().unwrap().len(), 1);
+        assert_eq!(storage.find_job_info_by_id(1).unwrap().unwrap().next_trigger_time, Some(200));
+
+        // an on-demand run doesn't advance the job, but its request key is unique per job.
+        let run = |request_key: &str| InstanceInfo {
+            job_id: Some(1),
+            request_key: Some(request_key.to_string()),
+            ..Default::default()
+        };
+        let job = cron_job(1, 1, 200);
+        assert!(storage
+            .save_scheduled_instances(Some(200), &job, &[run("key")])
+            .unwrap());
+        assert!(storage
+            .save_scheduled_instances(Some(200), &job, &[run("key")])
+            .is_err());
+        assert!(storage
+            .save_scheduled_instances(Some(200), &job, &[run("other")])
+            .unwrap());
+        assert_eq!(storage.instances().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn t_update_and_count_instances() {
+        let storage = MemoryStorage::new();
+        storage
+            .save_batch(&[
+                instance(10, 1, InstanceStatus::WaitingDispatch),
+                instance(11, 1, InstanceStatus::Running),
+                instance(12, 2, InstanceStatus::Success),
+            ])
+            .unwrap();
+        assert_eq!(storage.find_frequent_instance_by_job_id(&[1, 2]).unwrap(), vec![1]);
+
+        let mut instance = storage.find_instance_by_id(10).unwrap().unwrap();
+        instance.status = Some(InstanceStatus::Running.into());
+        instance.job_params = None;
+        storage.update(&mut [instance]).unwrap();
+
+        let running = vec![InstanceStatus::Running.into()];
+        assert_eq!(storage.count_instance_by_status(1, running).unwrap(), 2);
+    }
+
+    #[test]
+    fn t_broadcast_tasks() {
+        let storage = MemoryStorage::new();
+        let task = |address: &str| TaskInfo {
+            instance_id: Some(10),
+            worker_address: Some(address.to_string()),
+            status: Some(InstanceStatus::WaitingWorkerReceive.into()),
+            ..Default::default()
+        };
+        storage.save_batch(&[task("b"), task("a")]).unwrap();
+
+        let success = InstanceStatus::Success.into();
+        assert!(storage.update_task_status(10, "a", success, "ok", 1).unwrap());
+        assert!(!storage.update_task_status(10, "c", success, "ok", 1).unwrap());
+        let tasks = storage.find_tasks_by_instance_id(10).unwrap();
+        assert_eq!(tasks[0].worker_address.as_deref(), Some("a"));
+        assert_eq!(tasks[0].status, Some(success));
+        assert_eq!(storage.delete_tasks_by_instance_id(10).unwrap(), 2);
+    }
+
+    #[test]
+    fn t_fail_nth_call() {
+        let storage = MemoryStorage::new();
+        storage.save(cron_job(1, 1, 100)).unwrap();
+        storage.fail_nth_call(2);
+        assert!(storage.find_job_info_by_id(1).unwrap().is_some());
+        assert!(storage.find_job_info_by_id(1).is_err());
+        assert!(storage.find_job_info_by_id(1).unwrap().is_some());
+        assert!(storage.save(cron_job(1, 1, 100)).is_err());
+    }
+}
@@ -0,0 +1,376 @@
+use crate::error::{MigrationError, MigrationLocked, SchemaVersionTooNew, StorageError};
+use crate::sqlite_storage::translate_statement;
+use rbatis::rbatis::Rbatis;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+
+/// A numbered schema change, `up` upgrades the schema to `version` and `down` reverts it.
+#[derive(Clone, Copy, Debug)]
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+macro_rules! migration {
+    ($version:expr, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            up: include_str!(concat!("../migrations/", $name, ".up.sql")),
+            down: include_str!(concat!("../migrations/", $name, ".down.sql")),
+        }
+    };
+}
+
+/// Every migration known to this binary, ordered by version.
+///
+/// New schema changes are appended here as a new pair of files under `migrations/`,
+/// never by editing an already released migration. They are written for MySQL,
+/// `SqliteStorage` runs them translated, see `Dialect`.
+pub const MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_baseline"),
+    migration!(2, "0002_lock_lease"),
+    migration!(3, "0003_server_heartbeat"),
+    migration!(4, "0004_broadcast"),
+    migration!(5, "0005_mapreduce"),
+    migration!(6, "0006_workflow"),
+    migration!(7, "0007_job_time_zone"),
+    migration!(8, "0008_misfire"),
+    migration!(9, "0009_cron_jitter"),
+    migration!(10, "0010_calendar"),
+    migration!(11, "0011_instance_request_key"),
+    migration!(12, "0012_instance_trigger_slot"),
+    migration!(13, "0013_workflow_instance_version"),
+];
+
+const CREATE_SCHEMA_VERSION: &str = "CREATE TABLE IF NOT EXISTS `schema_version`
+(
+    `version`    bigint(20) NOT NULL,
+    `name`       varchar(255) DEFAULT NULL,
+    `applied_at` bigint(20) DEFAULT NULL,
+    PRIMARY KEY (`version`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4";
+
+/// The MySQL named lock a server holds while it migrates the schema.
+const MIGRATION_LOCK: &str = "fastjob_schema_migration";
+/// How long a server starting waits for another one to finish migrating.
+const MIGRATION_LOCK_TIMEOUT_SECS: u64 = 300;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SchemaVersion {
+    version: i64,
+    name: Option<String>,
+    applied_at: Option<i64>,
+}
+
+/// The database a `Migrator` runs `MIGRATIONS` against.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Dialect {
+    Mysql,
+    /// Every statement is translated from MySQL first.
+    Sqlite,
+}
+
+impl Dialect {
+    fn translate(self, sql: &str) -> Vec<String> {
+        match self {
+            Dialect::Mysql => vec![sql.to_string()],
+            Dialect::Sqlite => translate_statement(sql),
+        }
+    }
+}
+
+/// The schema version this binary expects.
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// Applies and reverts `MIGRATIONS` against a linked database.
+pub struct Migrator<'a> {
+    rb: &'a Rbatis,
+    migrations: &'a [Migration],
+    dialect: Dialect,
+}
+
+impl<'a> Migrator<'a> {
+    pub fn new(rb: &'a Rbatis) -> Self {
+        Self {
+            rb,
+            migrations: MIGRATIONS,
+            dialect: Dialect::Mysql,
+        }
+    }
+
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// The highest applied version, `0` for a database that was never migrated.
+    pub async fn current_version(&self) -> Result<i64, StorageError> {
+        self.current_version_in("").await
+    }
+
+    /// Apply every pending migration in order and return the resulting version.
+    ///
+    /// Servers starting together migrate one after the other, see `lock`. Fails with
+    /// `SchemaVersionTooNew` when the database was migrated by a newer binary.
+    pub async fn migrate(&self) -> Result<i64, StorageError> {
+        let tx_id = self.lock().await?;
+        let rs = self.migrate_locked(&tx_id).await;
+        self.unlock(&tx_id, rs).await
+    }
+
+    /// Revert applied migrations, newest first, until the schema is at `target` version.
+    pub async fn rollback(&self, target: i64) -> Result<i64, StorageError> {
+        let tx_id = self.lock().await?;
+        let rs = self.rollback_locked(&tx_id, target).await;
+        self.unlock(&tx_id, rs).await
+    }
+
+    async fn current_version_in(&self, tx_id: &str) -> Result<i64, StorageError> {
+        for sql in self.dialect.translate(CREATE_SCHEMA_VERSION) {
+            self.rb
+                .exec(tx_id, &sql)
+                .await
+                .context(MigrationError { version: 0i64 })?;
+        }
+        let applied: Vec<SchemaVersion> = self
+            .rb
+            .fetch(tx_id, "SELECT `version`, `name`, `applied_at` FROM `schema_version`")
+            .await
+            .context(MigrationError { version: 0i64 })?;
+        Ok(applied.iter().map(|v| v.version).max().unwrap_or(0))
+    }
+
+    async fn migrate_locked(&self, tx_id: &str) -> Result<i64, StorageError> {
+        // read under the lock, the server that held it before may have migrated already.
+        let current = self.current_version_in(tx_id).await?;
+        for migration in pending(self.migrations, current)? {
+            log::info!(
+                "applying schema migration {} ({})",
+                migration.version,
+                migration.name
+            );
+            self.apply(tx_id, migration).await?;
+        }
+        self.current_version_in(tx_id).await
+    }
+
+    async fn rollback_locked(&self, tx_id: &str, target: i64) -> Result<i64, StorageError> {
+        let current = self.current_version_in(tx_id).await?;
+        for migration in self
+            .migrations
+            .iter()
+            .rev()
+            .filter(|m| m.version > target && m.version <= current)
+        {
+            log::info!(
+                "reverting schema migration {} ({})",
+                migration.version,
+                migration.name
+            );
+            for sql in self.statements(migration.down) {
+                self.rb
+                    .exec(tx_id, &sql)
+                    .await
+                    .context(MigrationError {
+                        version: migration.version,
+                    })?;
+            }
+            self.rb
+                .exec_prepare(
+                    tx_id,
+                    "DELETE FROM `schema_version` WHERE `version` = ?",
+                    &vec![serde_json::json!(migration.version)],
+                )
+                .await
+                .context(MigrationError {
+                    version: migration.version,
+                })?;
+        }
+        self.current_version_in(tx_id).await
+    }
+
+    /// Run the statements of the migration and record its version right after them.
+    ///
+    /// MySQL commits every DDL statement on its own, a migration failing half way stays
+    /// half applied and unrecorded, the error names its version so it can be finished by
+    /// hand. Sqlite rolls the whole run back instead, see `lock`.
+    async fn apply(&self, tx_id: &str, migration: &Migration) -> Result<(), StorageError> {
+        for sql in self.statements(migration.up) {
+            self.rb
+                .exec(tx_id, &sql)
+                .await
+                .context(MigrationError {
+                    version: migration.version,
+                })?;
+        }
+        self.rb
+            .exec_prepare(
+                tx_id,
+                "INSERT INTO `schema_version` (`version`, `name`, `applied_at`) VALUES (?, ?, ?)",
+                &vec![
+                    serde_json::json!(migration.version),
+                    serde_json::json!(migration.name),
+                    serde_json::json!(chrono::Local::now().timestamp_millis()),
+                ],
+            )
+            .await
+            .context(MigrationError {
+                version: migration.version,
+            })?;
+        Ok(())
+    }
+
+    /// Hold the database for a run on a connection of its own, every statement of the run
+    /// goes through the returned transaction id.
+    ///
+    /// MySQL commits DDL implicitly, so the run is guarded by a named lock of the connection
+    /// instead. Sqlite runs DDL inside the transaction, which locks the database file from
+    /// its first write on.
+    async fn lock(&self) -> Result<String, StorageError> {
+        let tx_id = self
+            .rb
+            .begin_tx()
+            .await
+            .context(MigrationError { version: 0i64 })?;
+        if self.dialect == Dialect::Mysql {
+            let locked: Result<Option<i64>, StorageError> = self
+                .rb
+                .fetch_prepare(
+                    &tx_id,
+                    "SELECT GET_LOCK(?, ?)",
+                    &vec![
+                        serde_json::json!(MIGRATION_LOCK),
+                        serde_json::json!(MIGRATION_LOCK_TIMEOUT_SECS),
+                    ],
+                )
+                .await
+                .context(MigrationError { version: 0i64 });
+            // `GET_LOCK` returns 0 on timeout and NULL on error.
+            if !matches!(locked, Ok(Some(1))) {
+                let _ = self.rb.rollback(&tx_id).await;
+                locked?;
+                return MigrationLocked {
+                    name: MIGRATION_LOCK,
+                    timeout_secs: MIGRATION_LOCK_TIMEOUT_SECS,
+                }
+                .fail();
+            }
+        }
+        Ok(tx_id)
+    }
+
+    /// Release the database after a run, the run is committed if it succeeded.
+    async fn unlock(
+        &self,
+        tx_id: &str,
+        rs: Result<i64, StorageError>,
+    ) -> Result<i64, StorageError> {
+        if self.dialect == Dialect::Mysql {
+            let released: Result<Option<i64>, _> = self
+                .rb
+                .fetch_prepare(
+                    tx_id,
+                    "SELECT RELEASE_LOCK(?)",
+                    &vec![serde_json::json!(MIGRATION_LOCK)],
+                )
+                .await;
+            if let Err(e) = released {
+                log::warn!("release schema migration lock failed: {}", e);
+            }
+        }
+        match rs {
+            Ok(version) => {
+                self.rb
+                    .commit(tx_id)
+                    .await
+                    .context(MigrationError { version })?;
+                Ok(version)
+            }
+            Err(e) => {
+                // the error of the run matters more than the one of the rollback.
+                let _ = self.rb.rollback(tx_id).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// The statements of a migration file in the dialect of the database.
+    fn statements(&self, sql: &str) -> Vec<String> {
+        split_statements(sql)
+            .iter()
+            .flat_map(|stmt| self.dialect.translate(stmt))
+            .collect()
+    }
+}
+
+/// The migrations above `current`, or an error if the database is ahead of `migrations`.
+fn pending(migrations: &[Migration], current: i64) -> Result<&[Migration], StorageError> {
+    let latest = migrations.last().map_or(0, |m| m.version);
+    if current > latest {
+        return SchemaVersionTooNew {
+            db_version: current,
+            binary_version: latest,
+        }
+        .fail();
+    }
+    let applied = migrations.iter().take_while(|m| m.version <= current).count();
+    Ok(&migrations[applied..])
+}
+
+/// Split a migration file into single statements, the driver executes one at a time.
+pub(crate) fn split_statements(sql: &str) -> Vec<String> {
+    sql.lines()
+        .filter(|line| !line.trim_start().starts_with("--"))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .split(';')
+        .map(str::trim)
+        .filter(|stmt| !stmt.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_migrations_ordered() {
+        for (i, m) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(m.version, i as i64 + 1, "migration {} is out of order", m.name);
+            assert!(!split_statements(m.up).is_empty());
+            assert!(!split_statements(m.down).is_empty());
+            assert!(!m.up.contains("DROP TABLE"), "{} drops tables on upgrade", m.name);
+        }
+    }
+
+    #[test]
+    fn t_pending() {
+        let latest = latest_version();
+        assert_eq!(pending(MIGRATIONS, 0).unwrap().len(), MIGRATIONS.len());
+        assert!(pending(MIGRATIONS, latest).unwrap().is_empty());
+        match pending(MIGRATIONS, latest + 1) {
+            Err(StorageError::SchemaVersionTooNew {
+                db_version,
+                binary_version,
+            }) => {
+                assert_eq!(db_version, latest + 1);
+                assert_eq!(binary_version, latest);
+            }
+            _ => panic!("expected SchemaVersionTooNew"),
+        }
+    }
+
+    #[test]
+    fn t_split_statements() {
+        let stmts = split_statements(
+            "-- comment; with semicolon\nCREATE TABLE a (id int);\n\nALTER TABLE a ADD b int;\n",
+        );
+        assert_eq!(stmts, vec!["CREATE TABLE a (id int)", "ALTER TABLE a ADD b int"]);
+    }
+}
@@ -2,12 +2,17 @@ use rbatis::crud::CRUDTable;
 use serde::Deserialize;
 use serde::Serialize;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct AppInfo {
     pub id: Option<u64>,
-    pub app_name: Option<&'static str>,
-    pub password: Option<&'static str>,
-    pub current_server: Option<&'static str>,
+    pub app_name: Option<String>,
+    pub password: Option<String>,
+    pub current_server: Option<String>,
+    /// The window in ms the cron runs of the app's jobs without their own `jitter` are
+    /// spread over, unset disables spreading.
+    pub cron_spread: Option<u64>,
+    /// The fencing token of the election lock that set `current_server`.
+    pub fencing_token: Option<u64>,
     pub gmt_create: Option<i64>,
     pub gmt_modified: Option<i64>,
 }
@@ -0,0 +1,31 @@
+use rbatis::crud::CRUDTable;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// A named calendar of an app, the cron runs of a job referencing it are adjusted to the
+/// allowed days according to the job's `CalendarPolicy`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CalendarInfo {
+    pub id: Option<u64>,
+    pub app_id: Option<u64>,
+    pub calendar_name: Option<String>,
+    pub gmt_create: Option<u64>,
+    pub gmt_modified: Option<u64>,
+    /// The excluded dates, comma-separated `yyyy-MM-dd`.
+    pub holidays: Option<String>,
+    /// The allowed days of the week, comma-separated from 1 Monday to 7 Sunday, unset
+    /// means Monday to Friday.
+    pub working_days: Option<String>,
+}
+
+impl CRUDTable for CalendarInfo {
+    type IdType = u64;
+
+    fn get_id(&self) -> Option<&Self::IdType> {
+        self.id.as_ref()
+    }
+
+    fn table_name() -> String {
+        "calendar_info".to_string()
+    }
+}
@@ -1,3 +1,4 @@
+use fastjob_components_utils::id_generator::{self, GeneratorTyp, IdGeneratorError};
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use rbatis::crud::{CRUDTable, CRUD};
 use serde::Deserialize;
@@ -5,7 +6,7 @@ use serde::Serialize;
 use std::borrow::{Borrow, BorrowMut};
 use std::fmt::{Debug, Display, Formatter};
 
-#[derive(TryFromPrimitive, IntoPrimitive, Eq, PartialEq, Hash)]
+#[derive(Clone, Copy, TryFromPrimitive, IntoPrimitive, Eq, PartialEq, Hash)]
 #[repr(u32)]
 pub enum InstanceStatus {
     /// Waiting for dispatch.
@@ -67,24 +68,32 @@ impl Debug for InstanceType {
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct InstanceInfo {
     pub id: Option<u64>,
     pub job_id: Option<u64>,
     pub app_id: Option<u64>,
     pub instance_id: Option<u64>,
-    pub job_params: Option<&'static str>,
-    pub instance_params: Option<&'static str>,
+    pub job_params: Option<String>,
+    pub instance_params: Option<String>,
     pub instance_type: Option<u32>,
     pub wf_instance_id: Option<u64>,
     pub status: Option<u32>,
-    pub result: Option<&'static str>,
+    pub result: Option<String>,
     pub expected_trigger_time: Option<i64>,
     pub actual_trigger_time: Option<i64>,
     pub finished_time: Option<i64>,
     pub last_report_time: Option<i64>,
-    pub task_tracker_address: Option<&'static str>,
+    pub task_tracker_address: Option<String>,
     pub running_times: Option<usize>,
+    /// The `MisfirePolicy` that fired a missed trigger time, unset for a run on time.
+    pub misfire_action: Option<u32>,
+    /// The key the caller of an on-demand run supplied, unique per job so a retried request
+    /// gets the instance back instead of a second one.
+    pub request_key: Option<String>,
+    /// The expected trigger time of an instance the schedule created, unique per job so a
+    /// trigger time is instantiated once, unset for on-demand and workflow instances.
+    pub trigger_slot: Option<i64>,
     pub gmt_create: Option<i64>,
     pub gmt_modified: Option<i64>,
 }
@@ -102,21 +111,23 @@ impl CRUDTable for InstanceInfo {
 }
 
 impl InstanceInfo {
+    /// A new instance waiting for dispatch, fails while this server has no worker id to
+    /// generate its instance id with.
     pub fn create(
         job_id: Option<u64>,
         app_id: Option<u64>,
-        job_params: Option<&'static str>,
-        instance_params: Option<&'static str>,
+        job_params: Option<String>,
+        instance_params: Option<String>,
         wf_instance_id: Option<u64>,
         expected_trigger_time: Option<i64>,
-    ) -> InstanceInfo {
-        let instance_id = 1;
+    ) -> Result<InstanceInfo, IdGeneratorError> {
+        let instance_id = id_generator::generator_id(GeneratorTyp::Instance)?;
         let instance_type = if wf_instance_id.is_none() {
             Some(InstanceType::Normal.into())
         } else {
             Some(InstanceType::WorkFlow.into())
         };
-        InstanceInfo {
+        Ok(InstanceInfo {
             id: None,
             job_id,
             app_id,
@@ -133,9 +144,12 @@ impl InstanceInfo {
             last_report_time: Some(-1),
             task_tracker_address: None,
             running_times: Some(0),
+            misfire_action: None,
+            request_key: None,
+            trigger_slot: None,
             gmt_create: None,
             gmt_modified: None,
-        }
+        })
     }
 
     pub fn get_id(&self) -> u64 {
@@ -3,6 +3,7 @@ use num_enum::{IntoPrimitive, TryFromPrimitive};
 use rbatis::crud::{CRUDTable, CRUD};
 use serde::Deserialize;
 use serde::Serialize;
+use std::convert::TryFrom;
 use std::fmt::Display;
 use crate::model::task::TimeExpressionType;
 
@@ -21,6 +22,82 @@ pub enum JobTimeExpressionType {
     FixRate = 3,
     FixDelay = 4,
     WORKFLOW = 5,
+    /// Fires once at an absolute time or after a delay, then the job is `Finished`.
+    OneShot = 6,
+}
+
+/// What a cron job fires for the trigger times missed while no server was scheduling it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u32)]
+pub enum MisfirePolicy {
+    /// A single run now for all the missed ones.
+    FireOnce = 1,
+    /// A run now for each missed one, up to the job's `max_catch_up`.
+    CatchUp = 2,
+    /// No run, the job waits for its next trigger time.
+    Skip = 3,
+}
+
+impl Default for MisfirePolicy {
+    fn default() -> Self {
+        MisfirePolicy::FireOnce
+    }
+}
+
+/// How the cron runs of a job falling on a day its calendar excludes are adjusted.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u32)]
+pub enum CalendarPolicy {
+    /// The runs on excluded days don't happen.
+    Skip = 1,
+    /// The runs on excluded days happen on the next allowed day at the same time.
+    NextAllowedDay = 2,
+    /// Only the runs on the last allowed day of each month happen, e.g. a daily expression
+    /// runs on the last business day of the month.
+    LastAllowedDayOfMonth = 3,
+}
+
+impl Default for CalendarPolicy {
+    fn default() -> Self {
+        CalendarPolicy::Skip
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u32)]
+pub enum ExecuteType {
+    /// Runs on a single worker.
+    Standalone = 1,
+    /// Every available worker runs a sub-task of the instance.
+    Broadcast = 2,
+    MapReduce = 3,
+}
+
+impl Default for ExecuteType {
+    fn default() -> Self {
+        ExecuteType::Standalone
+    }
+}
+
+/// How the task tracker is chosen among the available workers, stored in `dispatch_strategy`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, TryFromPrimitive, IntoPrimitive)]
+#[repr(u32)]
+pub enum DispatchStrategyType {
+    /// The worker with the most free memory and cpu.
+    HealthFirst = 1,
+    Random = 2,
+    /// Rotate over the workers per job.
+    RoundRobin = 3,
+    /// The worker tracking the fewest unfinished instances of the app.
+    LeastRunning = 4,
+    /// The same instance params keep landing on the same worker.
+    ConsistentHash = 5,
+}
+
+impl Default for DispatchStrategyType {
+    fn default() -> Self {
+        DispatchStrategyType::HealthFirst
+    }
 }
 
 #[derive(TryFromPrimitive, IntoPrimitive)]
@@ -29,32 +106,43 @@ pub enum JobStatus {
     Running = 1,
     Stop = 2,
     DISABLED = 3,
+    /// A one-shot job that fired, its instance runs on as usual.
+    Finished = 4,
     DELETED = 10,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct JobInfo {
     pub id: Option<u64>,
     pub app_id: Option<u64>,
+    /// The calendar adjusting the cron runs, see `CalendarPolicy`.
+    pub calendar_id: Option<u64>,
+    /// See `CalendarPolicy`, unset means `Skip`.
+    pub calendar_policy: Option<u32>,
     pub concurrency: Option<u32>,
     /// Specifies the machine to run, empty represents unlimited,
     /// non-empty will only use one of the machines to run (multi-value comma split)
-    pub designated_workers: Option<&'static str>,
+    pub designated_workers: Option<String>,
     pub dispatch_strategy: Option<u32>,
     /// The execute type Standalone/Broadcast/MapReduce
     pub execute_type: Option<u32>,
-    /// Extension parameters. This data is not used by FastJob itself and is reserved for developers to use when extending
-    ///  For example, the custom Worker filtering logic of WorkerFilter can be passed in the filter metric GPUUsage < 10
+    /// Extension parameters, used as the filter a worker has to match to run the job,
+    /// e.g. `cpuLoad < 0.8 && tag startsWith "gpu-"`, see the worker crate's `worker_filter`.
     pub extra: Option<String>,
     pub gmt_create: Option<u64>,
     pub gmt_modified: Option<u64>,
     pub instance_retry_num: Option<usize>,
     /// The overall timeout for the task
     pub instance_time_limit: Option<u64>,
-    pub job_description: Option<&'static str>,
-    pub job_name: Option<&'static str>,
-    pub job_params: Option<&'static str>,
-    pub lifecycle: Option<&'static str>,
+    pub job_description: Option<String>,
+    pub job_name: Option<String>,
+    pub job_params: Option<String>,
+    /// The window in ms the cron runs are delayed within, by an offset fixed per job so jobs
+    /// sharing a fire time don't all dispatch at once. Unset uses the app's `cron_spread`.
+    pub jitter: Option<u64>,
+    pub lifecycle: Option<String>,
+    /// The most missed runs a `CatchUp` job fires, unset means 10.
+    pub max_catch_up: Option<u32>,
     pub max_instance_num: Option<usize>,
     /// The maximum worker numbers, just scope for the execution of mapreduce.
     pub max_worker_count: Option<usize>,
@@ -64,19 +152,28 @@ pub struct JobInfo {
     pub min_disk_space: Option<f64>,
     /// Minimum memory space, unit GB, 0 represents unlimited
     pub min_memory_space: Option<f64>,
+    /// See `MisfirePolicy`, unset means `FireOnce`.
+    pub misfire_policy: Option<u32>,
     pub next_trigger_time: Option<i64>,
     /// Alarm list of user ids, multi-valued comma-separated.
-    pub notify_user_ids: Option<&'static str>,
-    pub processor_info: Option<&'static str>,
+    pub notify_user_ids: Option<String>,
+    pub processor_info: Option<String>,
     /// The process type, Java/Shell.
     pub processor_type: Option<usize>,
-    /// 1 normal running，2 stop
+    /// 1 normal running，2 stop, 4 finished one-shot
     pub status: Option<usize>,
+    /// The fraction of broadcast sub-tasks that must succeed for the instance to succeed,
+    /// unset means all of them.
+    pub success_ratio: Option<f64>,
     pub task_retry_num: Option<usize>,
-    /// Time expression CRON/NULL/LONG/LONG
-    pub time_expression: Option<&'static str>,
-    /// Time expression type（CRON/API/FIX_RATE/FIX_DELAY）
+    /// Time expression CRON/NULL/LONG/LONG/NULL/fire time, see the scheduler's `one_shot`
+    /// for the fire times.
+    pub time_expression: Option<String>,
+    /// Time expression type（CRON/API/FIX_RATE/FIX_DELAY/WORKFLOW/ONE_SHOT）
     pub time_expression_type: Option<usize>,
+    /// IANA time zone the cron expression is evaluated in, e.g. `Asia/Shanghai`, unset
+    /// means the time zone of the server.
+    pub time_zone: Option<String>,
 }
 
 impl JobInfo {
@@ -91,7 +188,7 @@ impl JobInfo {
     }
 
     #[inline]
-    pub fn get_job_params(&self) -> Option<&str> {
+    pub fn get_job_params(&self) -> Option<String> {
         self.job_params.clone()
     }
 
@@ -99,12 +196,34 @@ impl JobInfo {
     pub fn get_next_trigger_time(&self) -> Option<i64> {
         self.next_trigger_time.clone()
     }
+
+    /// The execute type of the job, unset or unknown values fall back to standalone.
+    pub fn get_execute_type(&self) -> ExecuteType {
+        self.execute_type
+            .and_then(|t| ExecuteType::try_from(t).ok())
+            .unwrap_or_default()
+    }
+
+    /// The success ratio clamped to `(0, 1]`, defaults to 1.
+    pub fn get_success_ratio(&self) -> f64 {
+        match self.success_ratio {
+            Some(ratio) if ratio > 0.0 && ratio < 1.0 => ratio,
+            _ => 1.0,
+        }
+    }
+
+    /// The dispatch strategy of the job, unset or unknown values fall back to health first.
+    pub fn dispatch_strategy_type(&self) -> DispatchStrategyType {
+        self.dispatch_strategy
+            .and_then(|s| DispatchStrategyType::try_from(s).ok())
+            .unwrap_or_default()
+    }
 }
 
 impl JobTimeExpressionType {
     #[inline]
     pub fn is_frequent(&self) -> bool {
-        self == JobTimeExpressionType::FixRate || self == JobTimeExpressionType::FixDelay
+        matches!(self, JobTimeExpressionType::FixRate | JobTimeExpressionType::FixDelay)
     }
 }
 
@@ -2,12 +2,14 @@ use rbatis::crud::CRUDTable;
 use serde::Deserialize;
 use serde::Serialize;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Lock {
     pub id: Option<u64>,
-    pub lock_name: Option<&'static str>,
+    pub lock_name: Option<String>,
     pub max_lock_time: Option<u64>,
-    pub owner_ip: Option<&'static str>,
+    pub owner_ip: Option<String>,
+    /// Incremented on every acquisition, never reset, see `LockService`.
+    pub fencing_token: Option<u64>,
     pub gmt_create: Option<i64>,
     pub gmt_modified: Option<i64>,
 }
@@ -28,11 +30,22 @@ impl Lock {
     pub fn new(lock_name: &str, max_lock_time: u64, owner_ip: &str) -> Self {
         Self {
             id: None,
-            lock_name: Some(lock_name),
+            lock_name: Some(lock_name.to_string()),
             max_lock_time: Some(max_lock_time),
-            owner_ip: Some(owner_ip),
+            owner_ip: Some(owner_ip.to_string()),
+            fencing_token: None,
             gmt_create: None,
             gmt_modified: None,
         }
     }
+
+    /// Whether the lock can be taken at `now`: it was released or its lease expired.
+    pub fn is_free(&self, now: i64) -> bool {
+        match (&self.owner_ip, self.gmt_modified, self.max_lock_time) {
+            (Some(_), Some(modified), Some(max_lock_time)) => {
+                modified + max_lock_time as i64 <= now
+            }
+            _ => true,
+        }
+    }
 }
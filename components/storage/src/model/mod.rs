@@ -1,9 +1,13 @@
 pub mod app_info;
+pub mod calendar_info;
 pub mod container_info;
 pub mod instance_info;
 pub mod job_info;
 pub mod lock;
 pub mod server_info;
 pub mod task;
+pub mod task_info;
 pub mod user_info;
 pub mod workflow_info;
+pub mod workflow_instance_info;
+pub mod workflow_node_info;
@@ -2,10 +2,12 @@ use rbatis::crud::CRUDTable;
 use serde::Deserialize;
 use serde::Serialize;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct ServerInfo {
     pub id: Option<u64>,
-    pub ip: Option<&'static str>,
+    pub ip: Option<String>,
+    /// Millisecond timestamp of the server's last heartbeat.
+    pub last_heartbeat: Option<i64>,
     pub gmt_create: Option<u64>,
     pub gmt_modified: Option<u64>,
 }
@@ -0,0 +1,59 @@
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use rbatis::crud::CRUDTable;
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u32)]
+pub enum TaskType {
+    /// The sub-task of a broadcast instance on one worker.
+    Broadcast = 1,
+    /// The task of a MapReduce instance that runs on the task tracker and emits the maps.
+    Root = 2,
+    /// A task emitted by the root or by another map task.
+    Map = 3,
+    /// Receives the results of every map task once they finished.
+    Reduce = 4,
+}
+
+/// A task of a broadcast or MapReduce instance.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TaskInfo {
+    pub id: Option<u64>,
+    pub app_id: Option<u64>,
+    pub job_id: Option<u64>,
+    pub instance_id: Option<u64>,
+    /// Unique within the instance, broadcast sub-tasks use the worker address.
+    pub task_id: Option<String>,
+    pub task_name: Option<String>,
+    /// A `TaskType`.
+    pub task_type: Option<u32>,
+    pub task_params: Option<String>,
+    pub worker_address: Option<String>,
+    /// An `InstanceStatus`.
+    pub status: Option<u32>,
+    pub result: Option<String>,
+    pub retry_times: Option<u32>,
+    pub gmt_create: Option<i64>,
+    pub gmt_modified: Option<i64>,
+}
+
+impl CRUDTable for TaskInfo {
+    type IdType = u64;
+
+    fn get_id(&self) -> Option<&Self::IdType> {
+        self.id.as_ref()
+    }
+
+    fn table_name() -> String {
+        "task_info".to_string()
+    }
+}
+
+/// The number of tasks of an instance per type and status.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TaskStatusCount {
+    pub task_type: Option<u32>,
+    pub status: Option<u32>,
+    pub count: u64,
+}
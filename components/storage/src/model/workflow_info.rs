@@ -2,9 +2,30 @@ use rbatis::crud::CRUDTable;
 use serde::Deserialize;
 use serde::Serialize;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct WorkflowInfo {}
-
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct WorkflowInfo {
+    pub id: Option<u64>,
+    pub app_id: Option<u64>,
+    pub extra: Option<String>,
+    pub gmt_create: Option<u64>,
+    pub gmt_modified: Option<u64>,
+    pub lifecycle: Option<String>,
+    /// The maximum unfinished instances of the workflow, 0 represents unlimited.
+    pub max_wf_instance_num: Option<usize>,
+    pub next_trigger_time: Option<i64>,
+    /// Alarm list of user ids, multi-valued comma-separated.
+    pub notify_user_ids: Option<String>,
+    /// The DAG of the workflow as json, its nodes refer to `workflow_node_info` by id,
+    /// see the scheduler crate's `workflow` module.
+    pub pedag: Option<String>,
+    /// A `JobStatus`, 1 normal running，2 stop.
+    pub status: Option<usize>,
+    pub time_expression: Option<String>,
+    /// Time expression type（CRON/API）
+    pub time_expression_type: Option<usize>,
+    pub wf_description: Option<String>,
+    pub wf_name: Option<String>,
+}
 
 impl CRUDTable for WorkflowInfo {
     type IdType = u64;
@@ -17,4 +38,3 @@ impl CRUDTable for WorkflowInfo {
         "workflow_info".to_string()
     }
 }
-
@@ -0,0 +1,58 @@
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use rbatis::crud::CRUDTable;
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u32)]
+pub enum WorkflowInstanceStatus {
+    Waiting = 1,
+    Running = 2,
+    Failed = 3,
+    Success = 4,
+    Stopped = 10,
+}
+
+impl WorkflowInstanceStatus {
+    #[inline]
+    pub fn is_finished(&self) -> bool {
+        !matches!(
+            self,
+            WorkflowInstanceStatus::Waiting | WorkflowInstanceStatus::Running
+        )
+    }
+}
+
+/// A run of a workflow, `dag` keeps the instance and status of every node.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct WorkflowInstanceInfo {
+    pub id: Option<u64>,
+    pub actual_trigger_time: Option<i64>,
+    pub app_id: Option<u64>,
+    pub dag: Option<String>,
+    pub expected_trigger_time: Option<i64>,
+    pub finished_time: Option<i64>,
+    pub gmt_create: Option<u64>,
+    pub gmt_modified: Option<u64>,
+    pub result: Option<String>,
+    /// A `WorkflowInstanceStatus`.
+    pub status: Option<u32>,
+    pub wf_context: Option<String>,
+    pub wf_init_params: Option<String>,
+    pub wf_instance_id: Option<u64>,
+    pub workflow_id: Option<u64>,
+    /// Bumped by every write, see `Storage::update_workflow_instance`.
+    pub version: Option<u64>,
+}
+
+impl CRUDTable for WorkflowInstanceInfo {
+    type IdType = u64;
+
+    fn get_id(&self) -> Option<&Self::IdType> {
+        self.id.as_ref()
+    }
+
+    fn table_name() -> String {
+        "workflow_instance_info".to_string()
+    }
+}
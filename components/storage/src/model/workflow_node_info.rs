@@ -0,0 +1,36 @@
+use rbatis::crud::CRUDTable;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// A node of a workflow, runs the job with its own params.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct WorkflowNodeInfo {
+    pub id: Option<u64>,
+    pub app_id: Option<u64>,
+    /// Disabled nodes are skipped, their downstream nodes run as if they succeeded.
+    pub enable: Option<bool>,
+    pub extra: Option<String>,
+    pub gmt_create: Option<u64>,
+    pub gmt_modified: Option<u64>,
+    pub job_id: Option<u64>,
+    pub node_name: Option<String>,
+    /// Overrides the job params when set.
+    pub node_params: Option<String>,
+    /// A failure of this node doesn't fail the workflow, its downstream nodes still run.
+    pub skip_when_failed: Option<bool>,
+    #[serde(rename = "type")]
+    pub node_type: Option<u32>,
+    pub workflow_id: Option<u64>,
+}
+
+impl CRUDTable for WorkflowNodeInfo {
+    type IdType = u64;
+
+    fn get_id(&self) -> Option<&Self::IdType> {
+        self.id.as_ref()
+    }
+
+    fn table_name() -> String {
+        "workflow_node_info".to_string()
+    }
+}
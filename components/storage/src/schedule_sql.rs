@@ -0,0 +1,66 @@
+//! Schedule statements shared by `MysqlStorage` and `SqliteStorage`.
+//!
+//! The instances of a schedule round are saved in the transaction advancing the job, which
+//! is a compare-and-set on its `next_trigger_time`, so a round of two servers briefly owning
+//! the same app or of a server crashing half way never creates an instance twice.
+use crate::error::Result;
+use crate::model::instance_info::InstanceInfo;
+use crate::model::job_info::{JobInfo, JobStatus};
+use rbatis::crud::CRUD;
+use rbatis::rbatis::Rbatis;
+use serde_json::json;
+
+pub(crate) async fn save_scheduled_instances(
+    rb: &Rbatis,
+    previous_trigger_time: Option<i64>,
+    job: &JobInfo,
+    instances: &[InstanceInfo],
+) -> Result<bool> {
+    let tx_id = rb.begin_tx().await?;
+    match advance_job(rb, &tx_id, previous_trigger_time, job, instances).await {
+        Ok(true) => {
+            rb.commit(&tx_id).await?;
+            Ok(true)
+        }
+        Ok(false) => {
+            rb.rollback(&tx_id).await?;
+            Ok(false)
+        }
+        Err(e) => {
+            // the error of the statement matters more than the one of the rollback.
+            let _ = rb.rollback(&tx_id).await;
+            Err(e)
+        }
+    }
+}
+
+async fn advance_job(
+    rb: &Rbatis,
+    tx_id: &str,
+    previous_trigger_time: Option<i64>,
+    job: &JobInfo,
+    instances: &[InstanceInfo],
+) -> Result<bool> {
+    let running: usize = JobStatus::Running.into();
+    let rs = rb
+        .exec_prepare(
+            tx_id,
+            "UPDATE `job_info` SET `next_trigger_time` = ?, `status` = ? WHERE `id` = ? AND `status` = ? AND COALESCE(`next_trigger_time`, -1) = ?",
+            &vec![
+                json!(job.next_trigger_time),
+                json!(job.status.unwrap_or(running)),
+                json!(job.id),
+                json!(running),
+                json!(previous_trigger_time.unwrap_or(-1)),
+            ],
+        )
+        .await?;
+    if rs.rows_affected != 1 {
+        return Ok(false);
+    }
+    for instance in instances {
+        // `UK_instance_info_trigger_slot` rejects a slot created by another path.
+        rb.save(tx_id, instance).await?;
+    }
+    Ok(true)
+}
@@ -0,0 +1,54 @@
+//! Server registry statements shared by `MysqlStorage` and `SqliteStorage`.
+use crate::error::Result;
+use crate::model::server_info::ServerInfo;
+use rbatis::rbatis::Rbatis;
+use serde_json::json;
+
+pub(crate) async fn upsert_server_heartbeat(rb: &Rbatis, ip: &str, now: i64) -> Result<()> {
+    let update = "UPDATE `server_info` SET `last_heartbeat` = ? WHERE `ip` = ?";
+    let args = vec![json!(now), json!(ip)];
+    if rb.exec_prepare("", update, &args).await?.rows_affected > 0 {
+        return Ok(());
+    }
+    let rs = rb
+        .exec_prepare(
+            "",
+            "INSERT INTO `server_info` (`ip`, `last_heartbeat`) VALUES (?, ?)",
+            &vec![json!(ip), json!(now)],
+        )
+        .await;
+    if rs.is_err() {
+        // registered concurrently, the unique key on `ip` rejected the insert.
+        rb.exec_prepare("", update, &args).await?;
+    }
+    Ok(())
+}
+
+/// Only the heartbeat columns are read, `gmt_create` and `gmt_modified` are datetimes
+/// filled in by the database.
+pub(crate) async fn find_alive_servers(rb: &Rbatis, since: i64) -> Result<Vec<ServerInfo>> {
+    rb.fetch_prepare(
+        "",
+        "SELECT `id`, `ip`, `last_heartbeat` FROM `server_info` WHERE `last_heartbeat` >= ? ORDER BY `ip`",
+        &vec![json!(since)],
+    )
+    .await
+}
+
+pub(crate) async fn delete_expired_servers(rb: &Rbatis, before: i64) -> Result<u64> {
+    let rs = rb
+        .exec_prepare(
+            "",
+            "DELETE FROM `server_info` WHERE `last_heartbeat` IS NULL OR `last_heartbeat` < ?",
+            &vec![json!(before)],
+        )
+        .await?;
+    Ok(rs.rows_affected)
+}
+
+pub(crate) async fn delete_server(rb: &Rbatis, ip: &str) -> Result<u64> {
+    let rs = rb
+        .exec_prepare("", "DELETE FROM `server_info` WHERE `ip` = ?", &vec![json!(ip)])
+        .await?;
+    Ok(rs.rows_affected)
+}
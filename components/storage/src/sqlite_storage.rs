@@ -0,0 +1,753 @@
+use crate::error::{ConnectError, Result, StorageError};
+use crate::migration::{Dialect, Migrator};
+use crate::model::app_info::AppInfo;
+use crate::model::calendar_info::CalendarInfo;
+use crate::model::instance_info::InstanceInfo;
+use crate::model::job_info::{JobInfo, JobStatus, JobTimeExpressionType};
+use crate::model::lock::Lock;
+use crate::model::server_info::ServerInfo;
+use crate::model::task_info::{TaskInfo, TaskStatusCount};
+use crate::model::workflow_info::WorkflowInfo;
+use crate::model::workflow_instance_info::WorkflowInstanceInfo;
+use crate::model::workflow_node_info::WorkflowNodeInfo;
+use crate::{lock_sql, schedule_sql, server_sql, task_sql, workflow_sql, Storage, StorageConfig};
+use rbatis::core::runtime::task::block_on;
+use rbatis::crud::{CRUDTable, CRUD};
+use rbatis::rbatis::{Rbatis, RbatisOption};
+use rbatis::wrapper::Wrapper;
+use snafu::ResultExt;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+/// Embedded storage, used by single-node deployments and integration tests that
+/// don't want to depend on an outside MySQL server.
+#[derive(Clone)]
+pub struct SqliteStorage {
+    config: StorageConfig,
+    // shared by clones, an unlinked `Rbatis` would lose an in-memory database.
+    rb: Arc<Rbatis>,
+}
+
+impl Debug for SqliteStorage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("sqlite-storage")
+            .field("storageConfig", &self.config)
+            .finish()
+    }
+}
+
+impl SqliteStorage {
+    pub fn new(config: StorageConfig) -> Self {
+        let opt = RbatisOption::default();
+        let rb = Arc::new(Rbatis::new_with_opt(opt));
+        Self { config, rb }
+    }
+
+    /// Link the database file then bring the schema up to `latest_version` with the
+    /// migrations of `MysqlStorage`, translated to sqlite.
+    pub(crate) fn init(&self) -> std::result::Result<(), StorageError> {
+        block_on(async {
+            let derive_url = format!("sqlite://{}", self.config.address);
+            self.rb.link(&derive_url).await.context(ConnectError {
+                address: self.config.address,
+            })?;
+
+            let version = Migrator::new(&self.rb)
+                .dialect(Dialect::Sqlite)
+                .migrate()
+                .await?;
+            log::info!("sqlite storage schema at version {}", version);
+            Ok(())
+        })
+    }
+
+    fn get_wrapper(&self) -> Wrapper {
+        Wrapper::new(&self.rb.driver_type().unwrap())
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn save<T>(&self, model: T) -> Result<()>
+        where
+            T: CRUDTable,
+    {
+        block_on(async { self.rb.save("", &model).await })?;
+        Ok(())
+    }
+
+    fn save_batch<T>(&self, models: &[T]) -> Result<()>
+        where
+            T: CRUDTable,
+    {
+        block_on(async { self.rb.save_batch("", models).await })?;
+        Ok(())
+    }
+
+    fn delete<T>(&self, id: &T::IdType) -> Result<u64>
+        where
+            T: CRUDTable,
+    {
+        block_on(async { self.rb.remove_by_id::<T>("", id).await })
+    }
+
+    fn delete_batch<T>(&self, ids: &[T::IdType]) -> Result<()>
+        where
+            T: CRUDTable,
+    {
+        block_on(async { self.rb.remove_batch_by_id::<T>("", ids).await })?;
+        Ok(())
+    }
+
+    fn update<T>(&self, models: &mut [T]) -> Result<()>
+        where
+            T: CRUDTable,
+    {
+        block_on(async { self.rb.update_batch_by_id("", models).await })?;
+        Ok(())
+    }
+
+    fn find_job_info_by_instance_id(&self, instance_id: u64) -> Result<Option<JobInfo>> {
+        match self.find_instance_by_id(instance_id)? {
+            Some(InstanceInfo {
+                     job_id: Some(job_id),
+                     ..
+                 }) => self.find_job_info_by_id(job_id),
+            _ => Ok(None),
+        }
+    }
+
+    fn find_job_info_by_id(&self, id: u64) -> Result<Option<JobInfo>> {
+        let wrapper = self.get_wrapper().eq("id", id);
+        block_on(async { self.rb.fetch_by_wrapper("", &wrapper).await })
+    }
+
+    fn find_instance_by_id(&self, instance_id: u64) -> Result<Option<InstanceInfo>> {
+        let wrapper = self.get_wrapper().eq("instance_id", instance_id);
+        block_on(async { self.rb.fetch_by_wrapper("", &wrapper).await })
+    }
+
+    fn find_instance_by_ids(&self, instance_id: &[u64]) -> Result<Option<Vec<InstanceInfo>>> {
+        let wrapper = self.get_wrapper().r#in("instance_id", instance_id);
+        let instances: Vec<InstanceInfo> =
+            block_on(async { self.rb.fetch_list_by_wrapper("", &wrapper).await })?;
+        if instances.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(instances))
+    }
+
+    fn find_instance_by_request_key(
+        &self,
+        job_id: u64,
+        request_key: &str,
+    ) -> Result<Option<InstanceInfo>> {
+        let wrapper = self
+            .get_wrapper()
+            .eq("job_id", job_id)
+            .and()
+            .eq("request_key", request_key);
+        block_on(async { self.rb.fetch_by_wrapper("", &wrapper).await })
+    }
+
+    fn find_all_app_id_by_current_server(&self, current_server: &str) -> Result<Option<Vec<u64>>> {
+        let wrapper = self.get_wrapper().eq("current_server", current_server);
+        let apps: Vec<AppInfo> =
+            block_on(async { self.rb.fetch_list_by_wrapper("", &wrapper).await })?;
+        if apps.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(apps.iter().filter_map(|app| app.id).collect()))
+    }
+
+    fn find_all_app_infos(&self) -> Result<Vec<AppInfo>> {
+        let wrapper = self.get_wrapper();
+        block_on(async { self.rb.fetch_list_by_wrapper("", &wrapper).await })
+    }
+
+    fn find_cron_jobs(&self, ids: &[u64], time_threshold: i64) -> Result<Vec<JobInfo>> {
+        let status: usize = JobStatus::Running.into();
+        let typ: usize = JobTimeExpressionType::CRON.into();
+        let wrapper = self
+            .get_wrapper()
+            .r#in("app_id", ids)
+            .and()
+            .eq("status", status)
+            .and()
+            .eq("time_expression_type", typ)
+            .and()
+            .le("next_trigger_time", time_threshold);
+        block_on(async { self.rb.fetch_list_by_wrapper("", &wrapper).await })
+    }
+
+    fn find_frequent_jobs(&self, ids: &[u64]) -> Result<Vec<JobInfo>> {
+        let status: usize = JobStatus::Running.into();
+        let types: [usize; 2] = [
+            JobTimeExpressionType::FixRate.into(),
+            JobTimeExpressionType::FixDelay.into(),
+        ];
+        let wrapper = self
+            .get_wrapper()
+            .r#in("app_id", ids)
+            .and()
+            .eq("status", status)
+            .and()
+            .r#in("time_expression_type", &types);
+        block_on(async { self.rb.fetch_list_by_wrapper("", &wrapper).await })
+    }
+
+    fn find_one_shot_jobs(&self, ids: &[u64], time_threshold: i64) -> Result<Vec<JobInfo>> {
+        let status: usize = JobStatus::Running.into();
+        let typ: usize = JobTimeExpressionType::OneShot.into();
+        let wrapper = self
+            .get_wrapper()
+            .r#in("app_id", ids)
+            .and()
+            .eq("status", status)
+            .and()
+            .eq("time_expression_type", typ)
+            .and()
+            .le("next_trigger_time", time_threshold);
+        block_on(async { self.rb.fetch_list_by_wrapper("", &wrapper).await })
+    }
+
+    fn find_frequent_instance_by_job_id(&self, ids: &[u64]) -> Result<Vec<u64>> {
+        let wrapper = self
+            .get_wrapper()
+            .r#in("job_id", ids)
+            .and()
+            .r#in("status", &InstanceInfo::generalized_running_status());
+        let instances: Vec<InstanceInfo> =
+            block_on(async { self.rb.fetch_list_by_wrapper("", &wrapper).await })?;
+        let mut job_ids: Vec<u64> = instances.iter().filter_map(|i| i.job_id).collect();
+        job_ids.sort_unstable();
+        job_ids.dedup();
+        Ok(job_ids)
+    }
+
+    fn save_scheduled_instances(
+        &self,
+        previous_trigger_time: Option<i64>,
+        job: &JobInfo,
+        instances: &[InstanceInfo],
+    ) -> Result<bool> {
+        block_on(schedule_sql::save_scheduled_instances(
+            &self.rb,
+            previous_trigger_time,
+            job,
+            instances,
+        ))
+    }
+
+    fn find_last_finished_time(&self, job_id: u64) -> Result<Option<i64>> {
+        let wrapper = self
+            .get_wrapper()
+            .eq("job_id", job_id)
+            .and()
+            .gt("finished_time", 0)
+            .order_by(false, &["finished_time"])
+            .limit(1);
+        let instance: Option<InstanceInfo> =
+            block_on(async { self.rb.fetch_by_wrapper("", &wrapper).await })?;
+        Ok(instance.and_then(|i| i.finished_time))
+    }
+
+    fn count_instance_by_status(&self, id: u64, status: Vec<u32>) -> Result<u64> {
+        let wrapper = self.get_wrapper().eq("job_id", id).and().r#in("status", &status);
+        block_on(async { self.rb.fetch_count_by_wrapper::<InstanceInfo>("", &wrapper).await })
+    }
+
+    fn find_instances_by_app_status(
+        &self,
+        app_id: u64,
+        status: Vec<u32>,
+    ) -> Result<Vec<InstanceInfo>> {
+        let wrapper = self.get_wrapper().eq("app_id", app_id).and().r#in("status", &status);
+        block_on(async { self.rb.fetch_list_by_wrapper("", &wrapper).await })
+    }
+
+    fn find_app_info_by_id(&self, id: u64) -> Result<Option<AppInfo>> {
+        let wrapper = self.get_wrapper().eq("id", id);
+        block_on(async { self.rb.fetch_by_wrapper("", &wrapper).await })
+    }
+
+    fn find_calendar(&self, id: u64) -> Result<Option<CalendarInfo>> {
+        let wrapper = self.get_wrapper().eq("id", id);
+        block_on(async { self.rb.fetch_by_wrapper("", &wrapper).await })
+    }
+
+    fn find_calendar_by_name(
+        &self,
+        app_id: u64,
+        calendar_name: &str,
+    ) -> Result<Option<CalendarInfo>> {
+        let wrapper = self
+            .get_wrapper()
+            .eq("app_id", app_id)
+            .and()
+            .eq("calendar_name", calendar_name);
+        block_on(async { self.rb.fetch_by_wrapper("", &wrapper).await })
+    }
+
+    fn find_lock_by_name(&self, lock_name: &str) -> Result<Option<Lock>> {
+        block_on(lock_sql::find_lock(&self.rb, lock_name))
+    }
+
+    fn acquire_lock(
+        &self,
+        lock_name: &str,
+        owner: &str,
+        max_lock_time: u64,
+        now: i64,
+    ) -> Result<Option<u64>> {
+        block_on(lock_sql::acquire_lock(&self.rb, lock_name, owner, max_lock_time, now))
+    }
+
+    fn renew_lock(
+        &self,
+        lock_name: &str,
+        owner: &str,
+        fencing_token: u64,
+        now: i64,
+    ) -> Result<bool> {
+        block_on(lock_sql::renew_lock(&self.rb, lock_name, owner, fencing_token, now))
+    }
+
+    fn release_lock(&self, lock_name: &str, owner: &str, fencing_token: u64) -> Result<bool> {
+        block_on(lock_sql::release_lock(&self.rb, lock_name, owner, fencing_token))
+    }
+
+    fn update_app_current_server(
+        &self,
+        app_id: u64,
+        current_server: &str,
+        fencing_token: u64,
+    ) -> Result<bool> {
+        block_on(lock_sql::update_app_current_server(
+            &self.rb,
+            app_id,
+            current_server,
+            fencing_token,
+        ))
+    }
+
+    fn release_app_current_server(&self, current_server: &str) -> Result<u64> {
+        block_on(lock_sql::release_app_current_server(&self.rb, current_server))
+    }
+
+    fn upsert_server_heartbeat(&self, ip: &str, now: i64) -> Result<()> {
+        block_on(server_sql::upsert_server_heartbeat(&self.rb, ip, now))
+    }
+
+    fn find_alive_servers(&self, since: i64) -> Result<Vec<ServerInfo>> {
+        block_on(server_sql::find_alive_servers(&self.rb, since))
+    }
+
+    fn delete_expired_servers(&self, before: i64) -> Result<u64> {
+        block_on(server_sql::delete_expired_servers(&self.rb, before))
+    }
+
+    fn delete_server(&self, ip: &str) -> Result<u64> {
+        block_on(server_sql::delete_server(&self.rb, ip))
+    }
+
+    fn find_tasks_by_instance_id(&self, instance_id: u64) -> Result<Vec<TaskInfo>> {
+        block_on(task_sql::find_tasks_by_instance_id(&self.rb, instance_id))
+    }
+
+    fn delete_tasks_by_instance_id(&self, instance_id: u64) -> Result<u64> {
+        block_on(task_sql::delete_tasks_by_instance_id(&self.rb, instance_id))
+    }
+
+    fn update_task_status(
+        &self,
+        instance_id: u64,
+        worker_address: &str,
+        status: u32,
+        result: &str,
+        now: i64,
+    ) -> Result<bool> {
+        block_on(task_sql::update_task_status(
+            &self.rb,
+            instance_id,
+            worker_address,
+            status,
+            result,
+            now,
+        ))
+    }
+
+    fn find_task(&self, instance_id: u64, task_id: &str) -> Result<Option<TaskInfo>> {
+        block_on(task_sql::find_task(&self.rb, instance_id, task_id))
+    }
+
+    fn find_tasks_by_status(
+        &self,
+        instance_id: u64,
+        status: u32,
+        limit: u64,
+    ) -> Result<Vec<TaskInfo>> {
+        block_on(task_sql::find_tasks_by_status(&self.rb, instance_id, status, limit))
+    }
+
+    fn count_tasks_by_status(&self, instance_id: u64) -> Result<Vec<TaskStatusCount>> {
+        block_on(task_sql::count_tasks_by_status(&self.rb, instance_id))
+    }
+
+    fn find_cron_workflows(&self, ids: &[u64], time_threshold: i64) -> Result<Vec<WorkflowInfo>> {
+        let status: usize = JobStatus::Running.into();
+        let typ: usize = JobTimeExpressionType::CRON.into();
+        let wrapper = self
+            .get_wrapper()
+            .r#in("app_id", ids)
+            .and()
+            .eq("status", status)
+            .and()
+            .eq("time_expression_type", typ)
+            .and()
+            .le("next_trigger_time", time_threshold);
+        block_on(async { self.rb.fetch_list_by_wrapper("", &wrapper).await })
+    }
+
+    fn find_workflow_nodes(&self, workflow_id: u64) -> Result<Vec<WorkflowNodeInfo>> {
+        let wrapper = self.get_wrapper().eq("workflow_id", workflow_id);
+        block_on(async { self.rb.fetch_list_by_wrapper("", &wrapper).await })
+    }
+
+    fn find_workflow_instance(&self, wf_instance_id: u64) -> Result<Option<WorkflowInstanceInfo>> {
+        let wrapper = self.get_wrapper().eq("wf_instance_id", wf_instance_id);
+        block_on(async { self.rb.fetch_by_wrapper("", &wrapper).await })
+    }
+
+    fn find_workflow_instances_by_status(
+        &self,
+        ids: &[u64],
+        status: Vec<u32>,
+    ) -> Result<Vec<WorkflowInstanceInfo>> {
+        let wrapper = self.get_wrapper().r#in("app_id", ids).and().r#in("status", &status);
+        block_on(async { self.rb.fetch_list_by_wrapper("", &wrapper).await })
+    }
+
+    fn update_workflow_instance(&self, wf_instance: &WorkflowInstanceInfo) -> Result<bool> {
+        block_on(workflow_sql::update_workflow_instance(&self.rb, wf_instance))
+    }
+}
+
+/// Translate a statement of `MIGRATIONS` from MySQL to sqlite.
+///
+/// Tables are created as before, unique and secondary keys become standalone named
+/// `CREATE INDEX` statements. An `ALTER TABLE` is split into one statement per clause, sqlite alters a
+/// single column at a time, keys are added and dropped as indexes and `MODIFY` is dropped,
+/// sqlite columns don't enforce their declared type. Other statements run unchanged.
+pub(crate) fn translate_statement(stmt: &str) -> Vec<String> {
+    let stmt = stmt.trim();
+    if stmt.starts_with("CREATE TABLE") {
+        translate_create_table(stmt)
+    } else if stmt.starts_with("ALTER TABLE") {
+        translate_alter_table(stmt)
+    } else {
+        vec![stmt.to_string()]
+    }
+}
+
+fn translate_create_table(stmt: &str) -> Vec<String> {
+    let (head, body) = match (stmt.find('('), stmt.rfind(')')) {
+        (Some(begin), Some(end)) => (&stmt[..begin], &stmt[begin + 1..end]),
+        _ => return vec![stmt.to_string()],
+    };
+    let table = head
+        .trim_start_matches("CREATE TABLE")
+        .trim()
+        .trim_start_matches("IF NOT EXISTS")
+        .trim();
+
+    let mut columns = vec![];
+    let mut indexes = vec![];
+    let mut auto_increment = false;
+    for line in split_clauses(body) {
+        if line.starts_with("PRIMARY KEY") {
+            if !auto_increment {
+                columns.push(line.to_string());
+            }
+        } else if line.starts_with("UNIQUE KEY") {
+            // a named index, so a later migration can drop it by name.
+            indexes.push(create_index(table, &line["UNIQUE".len()..], true));
+        } else if line.starts_with("KEY") {
+            indexes.push(create_index(table, &line, false));
+        } else if line.contains("AUTO_INCREMENT") {
+            auto_increment = true;
+            let name = line.split_whitespace().next().unwrap_or_default();
+            columns.push(format!("{} INTEGER PRIMARY KEY AUTOINCREMENT", name));
+        } else {
+            columns.push(translate_column(&line));
+        }
+    }
+
+    let mut statements = vec![format!(
+        "CREATE TABLE IF NOT EXISTS {} ({})",
+        table,
+        columns.join(", ")
+    )];
+    statements.extend(indexes);
+    statements
+}
+
+fn translate_alter_table(stmt: &str) -> Vec<String> {
+    let rest = stmt.trim_start_matches("ALTER TABLE").trim_start();
+    let (table, clauses) = match rest.find(char::is_whitespace) {
+        Some(end) => (&rest[..end], &rest[end..]),
+        None => return vec![stmt.to_string()],
+    };
+    let mut statements = vec![];
+    for clause in split_clauses(clauses) {
+        let tokens: Vec<&str> = clause.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["ADD", "COLUMN", ..] => {
+                let column = clause["ADD COLUMN".len()..].trim();
+                statements.push(format!(
+                    "ALTER TABLE {} ADD COLUMN {}",
+                    table,
+                    translate_column(column)
+                ));
+            }
+            ["ADD", "UNIQUE", "KEY", ..] | ["ADD", "UNIQUE", "INDEX", ..] => {
+                statements.push(create_index(table, &clause["ADD UNIQUE".len()..], true));
+            }
+            ["ADD", "KEY", ..] | ["ADD", "INDEX", ..] => {
+                statements.push(create_index(table, &clause["ADD".len()..], false));
+            }
+            ["DROP", "KEY", name, ..] | ["DROP", "INDEX", name, ..] => {
+                statements.push(format!("DROP INDEX IF EXISTS {}", name));
+            }
+            ["DROP", "COLUMN", name, ..] => {
+                statements.push(format!("ALTER TABLE {} DROP COLUMN {}", table, name));
+            }
+            ["MODIFY", ..] => {}
+            _ => statements.push(format!("ALTER TABLE {} {}", table, clause)),
+        }
+    }
+    statements
+}
+
+/// `KEY name (columns)` as a `CREATE INDEX` statement, index names are global in sqlite.
+fn create_index(table: &str, key: &str, unique: bool) -> String {
+    let key = key.trim();
+    let name = key.split_whitespace().nth(1).unwrap_or_default();
+    let columns = &key[key.find('(').unwrap_or(0)..];
+    format!(
+        "CREATE {}INDEX IF NOT EXISTS {} ON {} {}",
+        if unique { "UNIQUE " } else { "" },
+        name,
+        table,
+        columns
+    )
+}
+
+/// Split the column definitions of a table or the clauses of an `ALTER TABLE` on the
+/// commas outside of parentheses.
+fn split_clauses(body: &str) -> Vec<String> {
+    let mut clauses = vec![];
+    let mut depth = 0;
+    let mut begin = 0;
+    for (i, c) in body.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                clauses.push(body[begin..i].to_string());
+                begin = i + 1;
+            }
+            _ => {}
+        }
+    }
+    clauses.push(body[begin..].to_string());
+    clauses
+        .into_iter()
+        .map(|clause| clause.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|clause| !clause.is_empty())
+        .collect()
+}
+
+/// Drop the MySQL specific column attributes.
+fn translate_column(column: &str) -> String {
+    let mut tokens = vec![];
+    let mut iter = column.split_whitespace().peekable();
+    while let Some(token) = iter.next() {
+        match token {
+            "unsigned" => {}
+            "CHARACTER" | "COLLATE" => {
+                // `CHARACTER SET xxx` or `COLLATE xxx`.
+                if token == "CHARACTER" {
+                    iter.next();
+                }
+                iter.next();
+            }
+            "ON" if iter.peek() == Some(&"UPDATE") => {
+                // `ON UPDATE CURRENT_TIMESTAMP (6)`, sqlite has no such clause.
+                iter.next();
+                iter.next();
+                if iter.peek().map_or(false, |t| t.starts_with('(')) {
+                    iter.next();
+                }
+            }
+            "CURRENT_TIMESTAMP" => {
+                if iter.peek().map_or(false, |t| t.starts_with('(')) {
+                    iter.next();
+                }
+                tokens.push(token);
+            }
+            _ => tokens.push(token),
+        }
+    }
+    tokens.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migration::split_statements;
+    use crate::{latest_version, StorageType, MIGRATIONS};
+
+    /// A storage on a fresh database file named after the test.
+    fn storage(name: &str) -> SqliteStorage {
+        let path = std::env::temp_dir().join(format!("fastjob-{}-{}.db", name, std::process::id()));
+        std::fs::File::create(&path).unwrap();
+        let address: &'static str = Box::leak(path.to_string_lossy().into_owned().into_boxed_str());
+        let storage = SqliteStorage::new(StorageConfig {
+            storage_type: StorageType::Sqlite,
+            address,
+            ..Default::default()
+        });
+        storage.init().unwrap();
+        storage
+    }
+
+    #[test]
+    fn t_find_one_shot_jobs() {
+        let storage = storage("one-shot");
+        let job = |id: u64, typ: JobTimeExpressionType| JobInfo {
+            id: Some(id),
+            app_id: Some(1),
+            status: Some(JobStatus::Running.into()),
+            time_expression_type: Some(typ.into()),
+            next_trigger_time: Some(100),
+            ..Default::default()
+        };
+        storage
+            .save_batch(&[
+                job(1, JobTimeExpressionType::OneShot),
+                job(2, JobTimeExpressionType::CRON),
+            ])
+            .unwrap();
+
+        assert!(storage.find_one_shot_jobs(&[1], 99).unwrap().is_empty());
+        let jobs = storage.find_one_shot_jobs(&[1], 100).unwrap();
+        assert_eq!(jobs.iter().filter_map(|job| job.id).collect::<Vec<_>>(), vec![1]);
+        assert!(storage.find_one_shot_jobs(&[2], 100).unwrap().is_empty());
+    }
+
+    #[test]
+    fn t_find_alive_servers() {
+        let storage = storage("heartbeat");
+        storage.upsert_server_heartbeat("10.0.0.1:3000", 100).unwrap();
+        storage.upsert_server_heartbeat("10.0.0.2:3000", 50).unwrap();
+        storage.upsert_server_heartbeat("10.0.0.1:3000", 200).unwrap();
+
+        let servers = storage.find_alive_servers(100).unwrap();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].ip.as_deref(), Some("10.0.0.1:3000"));
+        assert_eq!(servers[0].last_heartbeat, Some(200));
+    }
+
+    #[test]
+    fn t_init_migrates() {
+        let storage = storage("migrate");
+        // a restart finds the schema up to date.
+        storage.init().unwrap();
+        let version = block_on(async {
+            Migrator::new(&storage.rb)
+                .dialect(Dialect::Sqlite)
+                .current_version()
+                .await
+        })
+        .unwrap();
+        assert_eq!(version, latest_version());
+    }
+
+    #[test]
+    fn t_migrated_task_keys() {
+        // every migration ran, 0005 dropped the per-worker key for a per-task one.
+        let storage = storage("task-keys");
+        let task = |task_id: &str| TaskInfo {
+            instance_id: Some(1),
+            task_id: Some(task_id.to_string()),
+            worker_address: Some("127.0.0.1:3000".to_string()),
+            ..Default::default()
+        };
+        storage.save_batch(&[task("0.1"), task("0.2")]).unwrap();
+        assert_eq!(storage.find_tasks_by_instance_id(1).unwrap().len(), 2);
+        assert!(storage.save(task("0.1")).is_err());
+    }
+
+    #[test]
+    fn t_translate_statement() {
+        let statements: Vec<String> = split_statements(MIGRATIONS[0].up)
+            .iter()
+            .flat_map(|stmt| translate_statement(stmt))
+            .collect();
+        assert!(statements.iter().all(|s| !s.contains("ENGINE")
+            && !s.contains("AUTO_INCREMENT")
+            && !s.contains("ON UPDATE")
+            && !s.contains("COLLATE")));
+
+        let lock = statements
+            .iter()
+            .find(|s| s.starts_with("CREATE TABLE IF NOT EXISTS `lock`"))
+            .unwrap();
+        assert!(lock.contains("`id` INTEGER PRIMARY KEY AUTOINCREMENT"));
+        assert!(!lock.contains("UNIQUE"));
+        assert!(!lock.contains("PRIMARY KEY (`id`)"));
+        assert!(statements.contains(
+            &"CREATE UNIQUE INDEX IF NOT EXISTS `lockNameUK` ON `lock` (`lock_name`)".to_string()
+        ));
+
+        assert!(statements.contains(
+            &"CREATE INDEX IF NOT EXISTS `IDX5b1nhpe5je7gc5s1ur200njr7` ON `instance_info` (`job_id`)"
+                .to_string()
+        ));
+
+        assert_eq!(
+            translate_statement(
+                "ALTER TABLE `task_info` DROP KEY `UK_a`, ADD COLUMN `task_type` int(11) unsigned DEFAULT NULL, \
+                 ADD UNIQUE KEY `UK_b` (`instance_id`, `task_id`), MODIFY `gmt_create` bigint(20), DROP COLUMN `result`"
+            ),
+            vec![
+                "DROP INDEX IF EXISTS `UK_a`",
+                "ALTER TABLE `task_info` ADD COLUMN `task_type` int(11) DEFAULT NULL",
+                "CREATE UNIQUE INDEX IF NOT EXISTS `UK_b` ON `task_info` (`instance_id`, `task_id`)",
+                "ALTER TABLE `task_info` DROP COLUMN `result`",
+            ]
+        );
+        assert_eq!(
+            translate_statement("DELETE FROM `lock`"),
+            vec!["DELETE FROM `lock`"]
+        );
+    }
+
+    #[test]
+    fn t_translate_column() {
+        assert_eq!(
+            translate_column(
+                "`gmt_modified` datetime(6) DEFAULT CURRENT_TIMESTAMP (6) ON UPDATE CURRENT_TIMESTAMP (6)"
+            ),
+            "`gmt_modified` datetime(6) DEFAULT CURRENT_TIMESTAMP"
+        );
+        assert_eq!(
+            translate_column("`max_instance_num` int(11) unsigned DEFAULT NULL"),
+            "`max_instance_num` int(11) DEFAULT NULL"
+        );
+        assert_eq!(
+            translate_column("`owner_ip` varchar(255) CHARACTER SET utf8mb4 COLLATE utf8mb4_0900_ai_ci DEFAULT NULL"),
+            "`owner_ip` varchar(255) DEFAULT NULL"
+        );
+    }
+}
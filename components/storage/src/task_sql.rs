@@ -0,0 +1,95 @@
+//! Broadcast sub-task statements shared by `MysqlStorage` and `SqliteStorage`.
+use crate::error::Result;
+use crate::model::task_info::{TaskInfo, TaskStatusCount};
+use rbatis::rbatis::Rbatis;
+use serde_json::json;
+
+pub(crate) async fn find_tasks_by_instance_id(
+    rb: &Rbatis,
+    instance_id: u64,
+) -> Result<Vec<TaskInfo>> {
+    rb.fetch_prepare(
+        "",
+        "SELECT * FROM `task_info` WHERE `instance_id` = ? ORDER BY `worker_address`",
+        &vec![json!(instance_id)],
+    )
+    .await
+}
+
+pub(crate) async fn delete_tasks_by_instance_id(rb: &Rbatis, instance_id: u64) -> Result<u64> {
+    let rs = rb
+        .exec_prepare(
+            "",
+            "DELETE FROM `task_info` WHERE `instance_id` = ?",
+            &vec![json!(instance_id)],
+        )
+        .await?;
+    Ok(rs.rows_affected)
+}
+
+pub(crate) async fn update_task_status(
+    rb: &Rbatis,
+    instance_id: u64,
+    worker_address: &str,
+    status: u32,
+    result: &str,
+    now: i64,
+) -> Result<bool> {
+    let rs = rb
+        .exec_prepare(
+            "",
+            "UPDATE `task_info` SET `status` = ?, `result` = ?, `gmt_modified` = ? \
+             WHERE `instance_id` = ? AND `worker_address` = ?",
+            &vec![
+                json!(status),
+                json!(result),
+                json!(now),
+                json!(instance_id),
+                json!(worker_address),
+            ],
+        )
+        .await?;
+    Ok(rs.rows_affected > 0)
+}
+
+pub(crate) async fn find_task(
+    rb: &Rbatis,
+    instance_id: u64,
+    task_id: &str,
+) -> Result<Option<TaskInfo>> {
+    let tasks: Vec<TaskInfo> = rb
+        .fetch_prepare(
+            "",
+            "SELECT * FROM `task_info` WHERE `instance_id` = ? AND `task_id` = ?",
+            &vec![json!(instance_id), json!(task_id)],
+        )
+        .await?;
+    Ok(tasks.into_iter().next())
+}
+
+pub(crate) async fn find_tasks_by_status(
+    rb: &Rbatis,
+    instance_id: u64,
+    status: u32,
+    limit: u64,
+) -> Result<Vec<TaskInfo>> {
+    rb.fetch_prepare(
+        "",
+        "SELECT * FROM `task_info` WHERE `instance_id` = ? AND `status` = ? ORDER BY `id` LIMIT ?",
+        &vec![json!(instance_id), json!(status), json!(limit)],
+    )
+    .await
+}
+
+pub(crate) async fn count_tasks_by_status(
+    rb: &Rbatis,
+    instance_id: u64,
+) -> Result<Vec<TaskStatusCount>> {
+    rb.fetch_prepare(
+        "",
+        "SELECT `task_type`, `status`, COUNT(*) AS `count` FROM `task_info` \
+         WHERE `instance_id` = ? GROUP BY `task_type`, `status`",
+        &vec![json!(instance_id)],
+    )
+    .await
+}
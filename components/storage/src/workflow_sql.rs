@@ -0,0 +1,27 @@
+//! Workflow statements shared by `MysqlStorage` and `SqliteStorage`.
+use crate::error::Result;
+use crate::model::workflow_instance_info::WorkflowInstanceInfo;
+use rbatis::rbatis::Rbatis;
+use serde_json::json;
+
+pub(crate) async fn update_workflow_instance(
+    rb: &Rbatis,
+    wf_instance: &WorkflowInstanceInfo,
+) -> Result<bool> {
+    let rs = rb
+        .exec_prepare(
+            "",
+            "UPDATE `workflow_instance_info` SET `dag` = ?, `wf_context` = ?, `status` = ?, `result` = ?, `finished_time` = ?, `version` = `version` + 1 WHERE `id` = ? AND `version` = ?",
+            &vec![
+                json!(wf_instance.dag),
+                json!(wf_instance.wf_context),
+                json!(wf_instance.status),
+                json!(wf_instance.result),
+                json!(wf_instance.finished_time),
+                json!(wf_instance.id),
+                json!(wf_instance.version.unwrap_or(0)),
+            ],
+        )
+        .await?;
+    Ok(rs.rows_affected == 1)
+}
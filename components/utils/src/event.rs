@@ -7,5 +7,5 @@ pub struct CompletedInstance {
     pub instance_id: u64,
     pub wf_instance_id: u64,
     pub status: usize,
-    pub result: &'static str,
+    pub result: String,
 }
\ No newline at end of file
@@ -1,21 +1,201 @@
-use snowflake::{SnowflakeIdBucket, SnowflakeIdGenerator};
+//! Snowflake ids unique across the servers of a cluster.
+//!
+//! An id is 41 bits of milliseconds since `EPOCH_MILLIS`, 10 bits of the server's worker id
+//! and 12 bits of sequence, so ids of a type increase and stay positive as `i64`. The worker
+//! id is a slot in `0..=MAX_WORKER_ID` the server leases while it is alive, no two live
+//! servers hold the same slot.
+//!
+//! Each `GeneratorTyp` keeps its own sequence in an atomic, generating never blocks. When the
+//! sequence of a millisecond is exhausted or the clock moves back, the generator keeps
+//! counting from its last timestamp instead, which borrows the milliseconds ahead until the
+//! clock catches up, so no id is ever handed out twice.
+//!
+//! No id is generated until the server leased its worker id and installed the generator.
+use crate::time::{Clock, SystemClock};
+use parking_lot::RwLock;
+use snafu::Snafu;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
+/// 2021-01-01T00:00:00Z, the timestamps last 69 years from it.
+pub const EPOCH_MILLIS: i64 = 1_609_459_200_000;
+
+const WORKER_ID_BITS: u32 = 10;
+const SEQUENCE_BITS: u32 = 12;
+pub const MAX_WORKER_ID: u64 = (1 << WORKER_ID_BITS) - 1;
+const MAX_SEQUENCE: u64 = (1 << SEQUENCE_BITS) - 1;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
 pub enum GeneratorTyp {
     Server,
     WorkerManager,
+    Instance,
+    Workflow,
+    Task,
+}
+
+const GENERATOR_TYP_NUM: usize = 5;
+
+pub type Result<T, E = IdGeneratorError> = std::result::Result<T, E>;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub")]
+pub enum IdGeneratorError {
+    #[snafu(display("Worker id {} is out of range, at most {}.", worker_id, MAX_WORKER_ID))]
+    InvalidWorkerId { worker_id: u64 },
+
+    #[snafu(display("No worker id is leased yet, ids can't be generated."))]
+    NoWorkerId,
 }
 
 pub struct IdGenerator {
-    server_generator: SnowflakeIdBucket,
-    task_generator: SnowflakeIdBucket,
+    worker_id: u64,
+    clock: Arc<dyn Clock>,
+    /// Per `GeneratorTyp`, the timestamp and sequence of the last id.
+    last: [AtomicU64; GENERATOR_TYP_NUM],
 }
 
-/// Generator unique id use snowflake.
-pub fn generator_id(typ: GeneratorTyp) -> u64 {
-    let mut id_generator_generator = SnowflakeIdGenerator::new(1, 1);
-    let id = id_generator_generator.real_time_generate();
+impl IdGenerator {
+    /// Fails when `worker_id` is above `MAX_WORKER_ID`.
+    pub fn new(worker_id: u64) -> Result<Self> {
+        Self::with_clock(worker_id, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(worker_id: u64, clock: Arc<dyn Clock>) -> Result<Self> {
+        if worker_id > MAX_WORKER_ID {
+            return InvalidWorkerId { worker_id }.fail();
+        }
+        Ok(Self {
+            worker_id,
+            clock,
+            last: Default::default(),
+        })
+    }
+
+    pub fn worker_id(&self) -> u64 {
+        self.worker_id
+    }
+
+    pub fn next_id(&self, typ: GeneratorTyp) -> u64 {
+        let now = (self.clock.now_millis() - EPOCH_MILLIS).max(0) as u64;
+        let last = &self.last[typ as usize];
+        let mut current = last.load(Ordering::Relaxed);
+        loop {
+            // the sequence overflowing into the timestamp borrows the next millisecond.
+            let next = (current + 1).max(now << SEQUENCE_BITS);
+            match last.compare_exchange_weak(current, next, Ordering::AcqRel, Ordering::Relaxed) {
+                Ok(_) => {
+                    let timestamp = next >> SEQUENCE_BITS;
+                    return (timestamp << (WORKER_ID_BITS + SEQUENCE_BITS))
+                        | (self.worker_id << SEQUENCE_BITS)
+                        | (next & MAX_SEQUENCE);
+                }
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
+
+static GENERATOR: RwLock<Option<Arc<IdGenerator>>> = parking_lot::const_rwlock(None);
+
+/// Install the generator of this server once it leased `worker_id`.
+pub fn init(worker_id: u64) -> Result<Arc<IdGenerator>> {
+    let generator = Arc::new(IdGenerator::new(worker_id)?);
+    *GENERATOR.write() = Some(generator.clone());
+    Ok(generator)
+}
+
+/// Uninstall the generator once the server lost the lease of `worker_id`, another server
+/// may hold the slot now. A generator installed for another slot, e.g. by another registry
+/// of this process, is kept.
+pub fn reset(worker_id: u64) {
+    let mut generator = GENERATOR.write();
+    if generator.as_ref().map_or(false, |g| g.worker_id() == worker_id) {
+        *generator = None;
+    }
+}
+
+/// The generator of this server, fails while no worker id is leased.
+pub fn generator() -> Result<Arc<IdGenerator>> {
+    match GENERATOR.read().as_ref() {
+        Some(generator) => Ok(generator.clone()),
+        None => NoWorkerId.fail(),
+    }
+}
+
+/// Generator unique id use snowflake, fails while no worker id is leased.
+pub fn generator_id(typ: GeneratorTyp) -> Result<u64> {
+    Ok(generator()?.next_id(typ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::ManualClock;
+    use std::collections::HashSet;
+
+    #[test]
+    fn t_next_id() {
+        let clock = ManualClock::new(EPOCH_MILLIS + 1000);
+        let generator = IdGenerator::with_clock(3, Arc::new(clock.clone())).unwrap();
+        let first = generator.next_id(GeneratorTyp::Instance);
+        assert_eq!(first, (1000 << 22) | (3 << 12));
+        assert_eq!(generator.next_id(GeneratorTyp::Instance), first + 1);
+        // every type has its own sequence.
+        assert_eq!(generator.next_id(GeneratorTyp::Server), first);
+
+        // an exhausted sequence borrows the next millisecond.
+        let ids: Vec<u64> = (0..5000)
+            .map(|_| generator.next_id(GeneratorTyp::Workflow))
+            .collect();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(ids[4999] >> 22, 1001);
+
+        // a clock moving back keeps counting from the last timestamp.
+        let last = generator.next_id(GeneratorTyp::Instance);
+        clock.set(EPOCH_MILLIS + 10);
+        assert_eq!(generator.next_id(GeneratorTyp::Instance), last + 1);
+    }
+
+    #[test]
+    fn t_next_id_concurrently() {
+        // a worker id out of range isn't masked into another server's slot.
+        assert!(matches!(
+            IdGenerator::new(MAX_WORKER_ID + 2),
+            Err(IdGeneratorError::InvalidWorkerId { worker_id: 1025 })
+        ));
+        let generator = Arc::new(IdGenerator::new(MAX_WORKER_ID).unwrap());
+        assert_eq!(generator.worker_id(), MAX_WORKER_ID);
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let generator = generator.clone();
+                std::thread::spawn(move || {
+                    (0..10_000)
+                        .map(|_| generator.next_id(GeneratorTyp::Instance))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let ids: HashSet<u64> = handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect();
+        assert_eq!(ids.len(), 80_000);
+    }
+
+    #[test]
+    fn t_generator() {
+        assert!(matches!(generator_id(GeneratorTyp::Instance), Err(IdGeneratorError::NoWorkerId)));
+        assert!(init(MAX_WORKER_ID + 1).is_err());
+
+        init(5).unwrap();
+        let id = generator_id(GeneratorTyp::Instance).unwrap();
+        assert_eq!((id >> SEQUENCE_BITS) & MAX_WORKER_ID, 5);
 
-    let mut id_generator_bucket = SnowflakeIdBucket::new(1, 1);
-    let id = id_generator_bucket.get_id();
-    1
+        // giving up a slot that isn't installed keeps the generator.
+        reset(6);
+        assert_eq!(generator().unwrap().worker_id(), 5);
+        reset(5);
+        assert!(generator().is_err());
+    }
 }
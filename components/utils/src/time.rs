@@ -1,5 +1,7 @@
 use std::cmp::Ordering;
 use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::sync::atomic::{AtomicI64, Ordering as AtomicOrdering};
+use std::sync::Arc;
 pub use std::time::Duration;
 use time::{Duration as TimeDuration, Timespec};
 use tracing::field::debug;
@@ -262,6 +264,52 @@ impl Sub<Instant> for Instant {
     }
 }
 
+/// The wall clock in milliseconds since the epoch, schedulers take it as a trait object so
+/// tests can move time by hand.
+pub trait Clock: Send + Sync {
+    fn now_millis(&self) -> i64;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> i64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as i64)
+    }
+}
+
+/// A clock that only moves when told to, clones share the same time.
+#[derive(Clone, Debug, Default)]
+pub struct ManualClock {
+    now: Arc<AtomicI64>,
+}
+
+impl ManualClock {
+    pub fn new(now_millis: i64) -> Self {
+        Self {
+            now: Arc::new(AtomicI64::new(now_millis)),
+        }
+    }
+
+    pub fn set(&self, now_millis: i64) {
+        self.now.store(now_millis, AtomicOrdering::SeqCst);
+    }
+
+    pub fn advance(&self, d: Duration) {
+        self.now
+            .fetch_add(duration_to_ms(d) as i64, AtomicOrdering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> i64 {
+        self.now.load(AtomicOrdering::SeqCst)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::ops::Sub;
@@ -279,4 +327,15 @@ mod tests {
 
         println!("{},{},{}", cost1, cost2, cost3)
     }
+
+    #[test]
+    fn t_manual_clock() {
+        use super::{Clock, ManualClock};
+        let clock = ManualClock::new(1000);
+        let shared = clock.clone();
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(shared.now_millis(), 1500);
+        shared.set(42);
+        assert_eq!(clock.now_millis(), 42);
+    }
 }
@@ -0,0 +1,152 @@
+//! The election leases of the apps this server schedules.
+//!
+//! A server holds the lease of an app for as long as it schedules the app and renews it
+//! every schedule round, an app whose lease was lost or is held by another server is
+//! skipped. An app only moves to another server once its lease is given up, by its owner
+//! handing it over or by the lease expiring when the owner died, so no two servers ever
+//! schedule the same app.
+use fastjob_components_storage::{BatisError, Lease, LockService, Storage};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Long enough to outlive a few schedule rounds, each of which renews the lease.
+pub const APP_LEASE_TTL: Duration = Duration::from_millis(30000);
+
+/// The lock guarding the `current_server` of an app.
+pub fn app_lock_name(app_id: u64) -> String {
+    format!("app-{}", app_id)
+}
+
+pub struct AppLeases<S: Storage> {
+    address: String,
+    lock_service: LockService<S>,
+    // app id -> the lease held for it.
+    leases: Arc<Mutex<HashMap<u64, Lease>>>,
+}
+
+impl<S: Storage> Clone for AppLeases<S> {
+    fn clone(&self) -> Self {
+        Self {
+            address: self.address.clone(),
+            lock_service: self.lock_service.clone(),
+            leases: self.leases.clone(),
+        }
+    }
+}
+
+impl<S: Storage> AppLeases<S> {
+    pub fn new(address: impl Into<String>, storage: Arc<S>) -> Self {
+        Self {
+            address: address.into(),
+            lock_service: LockService::new(storage),
+            leases: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Take the lease of an app not held yet, `None` if another server holds it.
+    pub fn try_acquire(&self, app_id: u64) -> Result<Option<Lease>, BatisError> {
+        self.lock_service
+            .try_acquire(&app_lock_name(app_id), &self.address, APP_LEASE_TTL)
+    }
+
+    /// Keep the lease of an app this server became the server of.
+    pub fn hold(&self, app_id: u64, lease: Lease) {
+        if let Some(stale) = self.leases.lock().unwrap().insert(app_id, lease) {
+            self.release(app_id, stale);
+        }
+    }
+
+    /// The lease held for the app, removed from the held leases.
+    pub fn take(&self, app_id: u64) -> Option<Lease> {
+        self.leases.lock().unwrap().remove(&app_id)
+    }
+
+    /// Renew the leases of `app_ids`, the apps this server is the server of, and take the
+    /// leases of those not held yet. Leases of apps that moved away are given up.
+    ///
+    /// Returns the apps whose lease is held, the only ones this server may schedule.
+    pub fn refresh(&self, app_ids: &[u64]) -> Result<Vec<u64>, BatisError> {
+        let mut leases = self.leases.lock().unwrap();
+        let moved: Vec<u64> = leases
+            .keys()
+            .filter(|app_id| !app_ids.contains(app_id))
+            .cloned()
+            .collect();
+        for app_id in moved {
+            if let Some(lease) = leases.remove(&app_id) {
+                self.release(app_id, lease);
+            }
+        }
+
+        let mut held = vec![];
+        for &app_id in app_ids {
+            match leases.get_mut(&app_id) {
+                Some(lease) => {
+                    if self.lock_service.renew(lease)? {
+                        held.push(app_id);
+                    } else {
+                        warn!(
+                            "[AppLeases] server {} lost the lease of app {}.",
+                            self.address, app_id
+                        );
+                        leases.remove(&app_id);
+                    }
+                }
+                None => match self.try_acquire(app_id)? {
+                    Some(lease) => {
+                        leases.insert(app_id, lease);
+                        held.push(app_id);
+                    }
+                    None => {
+                        info!(
+                            "[AppLeases] the lease of app {} is held by another server, skip it.",
+                            app_id
+                        );
+                    }
+                },
+            }
+        }
+        Ok(held)
+    }
+
+    /// Give every held lease up, used once the server stops scheduling.
+    pub fn release_all(&self) {
+        let leases: Vec<(u64, Lease)> = self.leases.lock().unwrap().drain().collect();
+        for (app_id, lease) in leases {
+            self.release(app_id, lease);
+        }
+    }
+
+    /// Give the lease of an app up, returned by `take` or `try_acquire`.
+    pub fn release(&self, app_id: u64, lease: Lease) {
+        if let Err(e) = self.lock_service.release(lease) {
+            warn!("[AppLeases] release lease of app {} failed: {}", app_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fastjob_components_storage::MemoryStorage;
+
+    #[test]
+    fn t_refresh() {
+        let storage = Arc::new(MemoryStorage::new());
+        let a = AppLeases::new("10.0.0.1:3000", storage.clone());
+        let b = AppLeases::new("10.0.0.2:3000", storage);
+
+        assert_eq!(a.refresh(&[1, 2]).unwrap(), vec![1, 2]);
+        // a server that thinks it owns an app doesn't schedule it while another one holds it.
+        assert_eq!(b.refresh(&[2, 3]).unwrap(), vec![3]);
+        assert_eq!(a.refresh(&[1, 2]).unwrap(), vec![1, 2]);
+
+        // app 2 moved away from `a`, which gives its lease up on the next round.
+        assert_eq!(a.refresh(&[1]).unwrap(), vec![1]);
+        assert_eq!(b.refresh(&[2, 3]).unwrap(), vec![2, 3]);
+
+        a.release_all();
+        assert!(b.try_acquire(1).unwrap().is_some());
+    }
+}
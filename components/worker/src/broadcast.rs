@@ -0,0 +1,58 @@
+//! Broadcast instances run a sub-task on every available worker of the app.
+//!
+//! Each worker reports the status of its own sub-task into `task_info`, the instance
+//! succeeds once the job's success ratio of sub-tasks succeeded and fails as soon as
+//! that ratio can't be reached anymore.
+use fastjob_components_storage::model::instance_info::InstanceStatus;
+
+/// The final status of a broadcast instance from the statuses of its sub-tasks,
+/// `None` while it is still undecided.
+pub fn broadcast_outcome(statuses: &[InstanceStatus], success_ratio: f64) -> Option<InstanceStatus> {
+    if statuses.is_empty() {
+        return Some(InstanceStatus::Failed);
+    }
+    let total = statuses.len();
+    // the epsilon keeps e.g. 0.3 * 10 from rounding up to 4.
+    let required = ((success_ratio * total as f64 - 1e-9).ceil() as usize).clamp(1, total);
+    let succeeded = statuses
+        .iter()
+        .filter(|s| **s == InstanceStatus::Success)
+        .count();
+    let failed = statuses.iter().filter(|s| is_unsuccessful(s)).count();
+    if succeeded >= required {
+        Some(InstanceStatus::Success)
+    } else if total - failed < required {
+        Some(InstanceStatus::Failed)
+    } else {
+        None
+    }
+}
+
+/// Finished without success.
+fn is_unsuccessful(status: &InstanceStatus) -> bool {
+    matches!(
+        status,
+        InstanceStatus::Failed | InstanceStatus::Canceled | InstanceStatus::Stopped
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use InstanceStatus::*;
+
+    #[test]
+    fn t_broadcast_outcome() {
+        assert_eq!(broadcast_outcome(&[Success, Running], 1.0), None);
+        assert_eq!(broadcast_outcome(&[Success, Success], 1.0), Some(Success));
+        assert_eq!(broadcast_outcome(&[Running, Failed], 1.0), Some(Failed));
+        assert_eq!(broadcast_outcome(&[], 1.0), Some(Failed));
+
+        let statuses = [Success, Success, Success, Failed, Running, Running, Running, Running, Running, Running];
+        assert_eq!(broadcast_outcome(&statuses, 0.3), Some(Success));
+        assert_eq!(broadcast_outcome(&statuses, 0.5), None);
+        let statuses = [Success, Failed, Failed, Running];
+        assert_eq!(broadcast_outcome(&statuses, 0.5), None);
+        assert_eq!(broadcast_outcome(&statuses, 0.75), Some(Failed));
+    }
+}
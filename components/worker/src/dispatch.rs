@@ -1,31 +1,48 @@
-use crate::error::Result;
-use crate::WorkerClusterHolder;
+use crate::broadcast::broadcast_outcome;
+use crate::drain::DrainState;
+use crate::dispatch_strategy::{DispatchContext, DispatchStrategies};
+use crate::error::{self, Result};
+use crate::{Worker, WorkerClusterHolder};
 use dashmap::DashMap;
 use fastjob_components_storage::model::instance_info::{InstanceInfo, InstanceStatus};
-use fastjob_components_storage::model::job_info::{JobInfo, JobStatus, JobTimeExpressionType};
+use fastjob_components_storage::model::job_info::{
+    ExecuteType, JobInfo, JobStatus, JobTimeExpressionType,
+};
+use fastjob_components_storage::model::task_info::{TaskInfo, TaskType};
 use fastjob_components_storage::model::task::TimeExpressionType;
+use fastjob_components_scheduler::mapreduce::MapReduceEngine;
 use fastjob_components_storage::Storage;
+use fastjob_proto::fastjob::ServerScheduleJobReq;
 use snafu::ResultExt;
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::sync::Arc;
 use tokio::sync::mpsc::Receiver;
 
 pub struct Dispatch<S: Storage> {
     task_receiver: Receiver<(JobInfo, u64)>,
-    storage: S,
+    storage: Arc<S>,
     workers: RefCell<DashMap<u64, WorkerClusterHolder>>,
+    strategies: DispatchStrategies,
+    mapreduce: MapReduceEngine<S>,
+    drain: DrainState,
 }
 
 impl<S: Storage> Dispatch<S> {
     pub fn new(
         task_receiver: Receiver<(JobInfo, u64)>,
-        storage: S,
+        storage: Arc<S>,
         workers: RefCell<DashMap<u64, WorkerClusterHolder>>,
+        drain: DrainState,
     ) -> Self {
         Self {
             task_receiver,
+            mapreduce: MapReduceEngine::new(storage.clone()),
             storage,
             workers,
+            strategies: DispatchStrategies::default(),
+            drain,
         }
     }
 
@@ -35,20 +52,31 @@ impl<S: Storage> Dispatch<S> {
                 "[Dispatch Event-Loop] receive task id: {}",
                 task.0.id.unwrap()
             );
-            self.dispatch(task).await;
+            // a draining server waits for it before handing its apps over.
+            let _in_flight = self.drain.dispatching();
+            let instance_id = task.1;
+            if let Err(e) = self.dispatch(task).await {
+                error!(
+                    "[Dispatch Event-Loop] dispatch instance {} failed: {}",
+                    instance_id, e
+                );
+            }
         }
     }
     pub async fn dispatch(&self, task: (JobInfo, u64)) -> Result<()> {
         debug!("[Dispatch] start dispatch: {}", task.0.id.unwrap());
         // 1. check the current instance whether canceled.
-        if let Some(instance_info) = self.storage.find_instance_by_id(task.1).context("")? {
+        if let Some(instance_info) = self
+            .storage
+            .find_instance_by_id(task.1)
+            .context(error::WorkerStorageError)? {
             match InstanceStatus::try_from(instance_info.status.unwrap()) {
-                Some(InstanceStatus::Canceled) => {
+                Ok(InstanceStatus::Canceled) => {
                     warn!("[Dispatcher] The instance: {} is canceled.", task.1);
                     return Ok(());
                 }
                 // if it has been dispatched, it will not be dispatched.
-                Some(status) if status != InstanceStatus::WaitingDispatch => {
+                Ok(status) if status != InstanceStatus::WaitingDispatch => {
                     warn!("[Dispatcher] The instance: {} has been dispatched.", task.1);
                     return Ok(());
                 }
@@ -89,15 +117,16 @@ impl<S: Storage> Dispatch<S> {
                                 InstanceStatus::Running.into(),
                             ],
                         )
-                        .context()?;
+                        .context(error::WorkerStorageError)?;
                     if running_count >= n as u64 {
-                        self.update_instance_trigger_failed(instance_info.clone());
+                        let result = format!("Too many instances, exceed max instance num: {}", n);
+                        self.update_instance_trigger_failed(instance_info.clone(), &result)?;
 
                         // process this finished instance.
                         self.process_completed_instance(
                             task.1,
                             InstanceStatus::Failed,
-                            format!("Too many instances, exceed max instance num: {}", n).as_str(),
+                            &result,
                             instance_info.wf_instance_id,
                         );
                         return Ok(());
@@ -106,25 +135,133 @@ impl<S: Storage> Dispatch<S> {
                 _ => {}
             }
             // 3. Choose the most suitable worker.
-            self.choose_suitable_worker(&task.0)?;
+            let workers = self.choose_suitable_worker(&task.0, &instance_info)?;
+            if workers.is_empty() {
+                warn!(
+                    "[Dispatcher] no worker available for job: {}, instance: {}.",
+                    instance_info.job_id.unwrap(),
+                    task.1
+                );
+                let result = "no worker available";
+                self.update_instance_trigger_failed(instance_info.clone(), result)?;
+                self.process_completed_instance(
+                    task.1,
+                    InstanceStatus::Failed,
+                    result,
+                    instance_info.wf_instance_id,
+                );
+                return Ok(());
+            }
 
             // 4. Construct the schedule task request.
-            self.construct_schedule_job()?;
+            let req = self.construct_schedule_job(&task.0, &instance_info, &workers);
+            if task.0.get_execute_type() == ExecuteType::Broadcast {
+                return self.broadcast(&task.0, instance_info, &workers, &req);
+            }
 
             // 5. Send request(unreliable，so need background thread to poll the status periodically).
-            self.send()?;
-            info!("[Dispatcher] Send schedule request( job id: {}, instance id: {} ) to worker address: {} successfully.");
-            self.update_instance_trigger_success(instance_info.clone(), "");
+            // the first worker tracks the task and distributes it to the others.
+            let task_tracker = &workers[0];
+            self.send(task_tracker, &req)?;
+            info!(
+                "[Dispatcher] Send schedule request( job id: {}, instance id: {} ) to worker address: {} successfully.",
+                instance_info.job_id.unwrap(),
+                task.1,
+                task_tracker.address
+            );
+            if task.0.get_execute_type() == ExecuteType::MapReduce {
+                // the root task runs on the task tracker and emits the map tasks.
+                self.mapreduce
+                    .start(&instance_info, task_tracker.address)
+                    .context(error::SchedulerFailed)?;
+            }
+            self.update_instance_trigger_success(instance_info.clone(), task_tracker.address)?;
         }
         Ok(())
     }
 
+    /// Send the instance to every worker, each worker's result is tracked as a sub-task.
+    fn broadcast(
+        &self,
+        job_info: &JobInfo,
+        instance: InstanceInfo,
+        workers: &[Worker],
+        req: &ServerScheduleJobReq,
+    ) -> Result<()> {
+        let instance_id = instance.instance_id.unwrap_or_default();
+        let now = chrono::Local::now().timestamp_millis();
+        // a redispatched instance starts over.
+        self.storage
+            .delete_tasks_by_instance_id(instance_id)
+            .context(error::WorkerStorageError)?;
+        let tasks: Vec<TaskInfo> = workers
+            .iter()
+            .map(|worker| TaskInfo {
+                app_id: instance.app_id,
+                job_id: instance.job_id,
+                instance_id: Some(instance_id),
+                task_id: Some(worker.address.to_string()),
+                task_type: Some(TaskType::Broadcast.into()),
+                worker_address: Some(worker.address.to_string()),
+                status: Some(InstanceStatus::WaitingWorkerReceive.into()),
+                gmt_create: Some(now),
+                gmt_modified: Some(now),
+                ..Default::default()
+            })
+            .collect();
+        self.storage
+            .save_batch(&tasks)
+            .context(error::WorkerStorageError)?;
+
+        let mut statuses = vec![];
+        for worker in workers {
+            match self.send(worker, req) {
+                Ok(_) => statuses.push(InstanceStatus::WaitingWorkerReceive),
+                Err(e) => {
+                    warn!(
+                        "[Dispatcher] broadcast instance {} to worker {} failed: {}",
+                        instance_id, worker.address, e
+                    );
+                    self.storage
+                        .update_task_status(
+                            instance_id,
+                            worker.address,
+                            InstanceStatus::Failed.into(),
+                            &e.to_string(),
+                            now,
+                        )
+                        .context(error::WorkerStorageError)?;
+                    statuses.push(InstanceStatus::Failed);
+                }
+            }
+        }
+
+        if broadcast_outcome(&statuses, job_info.get_success_ratio()) == Some(InstanceStatus::Failed) {
+            let result = "broadcast failed, too many workers can't be reached";
+            self.update_instance_trigger_failed(instance.clone(), result)?;
+            self.process_completed_instance(
+                instance_id,
+                InstanceStatus::Failed,
+                result,
+                instance.wf_instance_id,
+            );
+            return Ok(());
+        }
+        info!(
+            "[Dispatcher] Broadcast instance {} of job {} to {} workers.",
+            instance_id,
+            job_info.id.unwrap_or_default(),
+            workers.len()
+        );
+        self.update_instance_trigger_success(instance, workers[0].address)
+    }
+
     /// Process the completed instance.
     fn process_completed_instance(
         &self,
         instance_id: u64,
         status: InstanceStatus,
-        result: &'static str,
+        result: &str,
         wf_instance_id: Option<u64>,
     ) {
         info!(
@@ -136,14 +273,15 @@ impl<S: Storage> Dispatch<S> {
         if status == InstanceStatus::Failed {}
     }
 
-    fn update_instance_trigger_failed(&self, mut instance: InstanceInfo) -> Result<()> {
+    fn update_instance_trigger_failed(&self, mut instance: InstanceInfo, result: &str) -> Result<()> {
         let now = chrono::Local::now().timestamp_millis();
-        instance.result =
-            Some(format!("Too many instances, exceed max instance num: {}", n).as_str());
+        instance.result = Some(result.to_string());
         instance.actual_trigger_time = Some(now);
         instance.finished_time = Some(now);
         instance.status = Some(InstanceStatus::Failed.into());
-        self.storage.update(instance.as_mut_ref())?;
+        self.storage
+            .update(&mut [instance])
+            .context(error::WorkerStorageError)?;
         Ok(())
     }
 
@@ -154,22 +292,126 @@ impl<S: Storage> Dispatch<S> {
     ) -> Result<()> {
         let now = chrono::Local::now().timestamp_millis();
         instance.actual_trigger_time = Some(now);
-        instance.task_tracker_address = Some(worker_address);
+        instance.task_tracker_address = Some(worker_address.to_string());
         instance.status = Some(InstanceStatus::WaitingWorkerReceive.into());
-        self.storage.update(instance.as_mut_ref())?;
+        self.storage
+            .update(&mut [instance])
+            .context(error::WorkerStorageError)?;
         Ok(())
     }
 
-    /// Choose the most suitable worker.
-    fn choose_suitable_worker(&self, job_info: &JobInfo) -> Result<()> {
-        Ok(())
+    /// Choose the suitable workers of the job's app, the task tracker chosen by the job's
+    /// dispatch strategy first.
+    fn choose_suitable_worker(
+        &self,
+        job_info: &JobInfo,
+        instance: &InstanceInfo,
+    ) -> Result<Vec<Worker>> {
+        let app_id = match job_info.app_id {
+            Some(app_id) => app_id,
+            None => return Ok(vec![]),
+        };
+        let workers = match self.workers.borrow().get_mut(&app_id) {
+            Some(mut holder) => holder.get_suitable_worker(job_info),
+            None => return Ok(vec![]),
+        };
+        if workers.is_empty() {
+            return Ok(workers);
+        }
+
+        let strategy = self.strategies.get(job_info.dispatch_strategy_type());
+        let running = if strategy.needs_running_counts() {
+            self.running_counts(app_id)?
+        } else {
+            HashMap::new()
+        };
+        let ctx = DispatchContext {
+            job_info,
+            instance,
+            running,
+        };
+        let mut workers = strategy.select(&ctx, workers);
+        // a broadcast runs on every available worker.
+        if job_info.get_execute_type() != ExecuteType::Broadcast {
+            if let Some(count) = job_info.max_worker_count.filter(|c| *c > 0) {
+                workers.truncate(count);
+            }
+        }
+        Ok(workers)
     }
 
-    fn construct_schedule_job(&self) -> Result<()> {
-        Ok(())
+    /// Unfinished instances of the app per task tracker address.
+    fn running_counts(&self, app_id: u64) -> Result<HashMap<String, u64>> {
+        let instances = self
+            .storage
+            .find_instances_by_app_status(
+                app_id,
+                vec![
+                    InstanceStatus::WaitingWorkerReceive.into(),
+                    InstanceStatus::Running.into(),
+                ],
+            )
+            .context(error::WorkerStorageError)?;
+        let mut running = HashMap::new();
+        for address in instances.into_iter().filter_map(|i| i.task_tracker_address) {
+            *running.entry(address).or_insert(0) += 1;
+        }
+        Ok(running)
+    }
+
+    fn construct_schedule_job(
+        &self,
+        job_info: &JobInfo,
+        instance: &InstanceInfo,
+        workers: &[Worker],
+    ) -> ServerScheduleJobReq {
+        let mut req = ServerScheduleJobReq::default();
+        req.set_allWorkerAddress(
+            workers
+                .iter()
+                .map(|worker| worker.address.to_string())
+                .collect::<Vec<_>>()
+                .into(),
+        );
+        req.set_jobId(job_info.id.unwrap_or_default());
+        req.set_instanceId(instance.instance_id.unwrap_or_default());
+        req.set_wfInstanceId(instance.wf_instance_id.unwrap_or_default());
+        req.set_processorType(job_info.processor_type.unwrap_or_default() as u32);
+        req.set_processorInfo(job_info.processor_info.clone().unwrap_or_default());
+        req.set_executeType(job_info.execute_type.unwrap_or_default());
+        req.set_jobParams(job_info.job_params.clone().unwrap_or_default());
+        req.set_instanceParams(instance.instance_params.clone().unwrap_or_default());
+        req.set_timeExpressionType(job_info.time_expression_type.unwrap_or_default() as u32);
+        req.set_timeExpression(job_info.time_expression.clone().unwrap_or_default());
+        req.set_instanceTimeoutMS(job_info.instance_time_limit.unwrap_or_default());
+        req.set_threadConcurrency(job_info.concurrency.unwrap_or_default());
+        req.set_taskRetryNum(job_info.task_retry_num.unwrap_or_default() as u32);
+        req
     }
 
-    fn send(&self) -> Result<()> {
+    fn send(&self, worker: &Worker, req: &ServerScheduleJobReq) -> Result<()> {
+        let client = match &worker.client {
+            Some(client) => client,
+            None => {
+                return error::WorkerRejected {
+                    address: worker.address,
+                    msg: "worker is not connected",
+                }
+                .fail()
+            }
+        };
+        let reply = client
+            .schedule_job(req)
+            .context(error::WorkerRpcFailed {
+                address: worker.address,
+            })?;
+        if reply.get_code() != 200 {
+            return error::WorkerRejected {
+                address: worker.address,
+                msg: reply.get_message(),
+            }
+            .fail();
+        }
         Ok(())
     }
 
@@ -179,14 +421,18 @@ impl<S: Storage> Dispatch<S> {
             .find_job_info_by_instance_id(instance.instance_id.unwrap())
             .context(error::WorkerStorageError)?
         {
-            if !job.status.unwrap() == JobStatus::Running
+            // the instance of a fired one-shot job runs on.
+            if !matches!(
+                job.status.and_then(|s| JobStatus::try_from(s).ok()),
+                Some(JobStatus::Running) | Some(JobStatus::Finished)
+            )
                 || JobTimeExpressionType::try_from(job.time_expression_type)?.is_frequent()
                 || instance.running_times.unwrap() >= job.instance_retry_num.unwrap()
             {
                 instance.status = Some(InstanceStatus::Failed.into());
                 instance.finished_time = Some(chrono::Local::now().timestamp_millis());
                 // only this case will happen.
-                instance.result = Some("worker report timeout, maybe Worker down");
+                instance.result = Some("worker report timeout, maybe Worker down".to_string());
                 instance.running_times = Some(instance.running_times.unwrap().wrapping_add(1));
                 self.storage.save(instance);
                 return Ok(());
@@ -199,4 +445,134 @@ impl<S: Storage> Dispatch<S> {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use fastjob_components_storage::MemoryStorage;
+    use tokio::sync::mpsc::channel;
+
+    /// A dispatch over app 1 whose workers heartbeated just now, none is connected.
+    fn dispatch(storage: &MemoryStorage, addresses: &[&'static str]) -> Dispatch<MemoryStorage> {
+        let now = chrono::Local::now().timestamp_millis();
+        let mut holder = WorkerClusterHolder::new("app");
+        for &address in addresses {
+            let mut worker = Worker::new();
+            worker.address = address;
+            worker.last_active_time = now;
+            holder.workers.insert(address, worker);
+        }
+        let workers = DashMap::default();
+        workers.insert(1, holder);
+        let (_, rx) = channel(1);
+        Dispatch::new(
+            rx,
+            Arc::new(storage.clone()),
+            RefCell::new(workers),
+            DrainState::default(),
+        )
+    }
+
+    fn job() -> JobInfo {
+        JobInfo {
+            id: Some(1),
+            app_id: Some(1),
+            ..Default::default()
+        }
+    }
+
+    fn instance(storage: &MemoryStorage, instance_id: u64, status: InstanceStatus) {
+        storage
+            .save(InstanceInfo {
+                id: Some(instance_id),
+                instance_id: Some(instance_id),
+                app_id: Some(1),
+                job_id: Some(1),
+                status: Some(status.into()),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    fn run(dispatch: &Dispatch<MemoryStorage>, job: JobInfo, instance_id: u64) -> Result<()> {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(dispatch.dispatch((job, instance_id)))
+    }
+
+    fn chosen(dispatch: &Dispatch<MemoryStorage>, job: &JobInfo) -> Vec<&'static str> {
+        let mut addresses: Vec<_> = dispatch
+            .choose_suitable_worker(job, &InstanceInfo::default())
+            .unwrap()
+            .iter()
+            .map(|worker| worker.address)
+            .collect();
+        addresses.sort_unstable();
+        addresses
+    }
+
+    #[test]
+    fn t_choose_designated_workers() {
+        let storage = MemoryStorage::new();
+        let dispatch = dispatch(&storage, &["a", "b", "c"]);
+        assert_eq!(chosen(&dispatch, &job()), vec!["a", "b", "c"]);
+
+        let job = JobInfo {
+            designated_workers: Some("b, c".to_string()),
+            ..job()
+        };
+        assert_eq!(chosen(&dispatch, &job), vec!["b", "c"]);
+        let other_app = JobInfo {
+            app_id: Some(2),
+            ..job
+        };
+        assert!(chosen(&dispatch, &other_app).is_empty());
+    }
+
+    #[test]
+    fn t_choose_at_most_max_worker_count() {
+        let storage = MemoryStorage::new();
+        let dispatch = dispatch(&storage, &["a", "b", "c"]);
+        let job = JobInfo {
+            max_worker_count: Some(2),
+            ..job()
+        };
+        assert_eq!(chosen(&dispatch, &job).len(), 2);
+
+        // a broadcast runs on every worker.
+        let broadcast = JobInfo {
+            execute_type: Some(ExecuteType::Broadcast.into()),
+            ..job
+        };
+        assert_eq!(chosen(&dispatch, &broadcast).len(), 3);
+    }
+
+    #[test]
+    fn t_dispatch_exceeding_max_instance_num() {
+        let storage = MemoryStorage::new();
+        let dispatch = dispatch(&storage, &["a"]);
+        instance(&storage, 1, InstanceStatus::Running);
+        instance(&storage, 2, InstanceStatus::WaitingDispatch);
+        let job = JobInfo {
+            max_instance_num: Some(1),
+            ..job()
+        };
+
+        run(&dispatch, job, 2).unwrap();
+        let failed = storage.find_instance_by_id(2).unwrap().unwrap();
+        assert_eq!(failed.status, Some(InstanceStatus::Failed.into()));
+        assert!(failed.result.unwrap().contains("exceed max instance num"));
+    }
+
+    #[test]
+    fn t_dispatch_send_failed() {
+        let storage = MemoryStorage::new();
+        let dispatch = dispatch(&storage, &["a"]);
+        instance(&storage, 1, InstanceStatus::WaitingDispatch);
+
+        let err = run(&dispatch, job(), 1).unwrap_err();
+        assert!(matches!(err, error::WorkerManagerError::WorkerRejected { .. }));
+        // left waiting, the status checker dispatches it again.
+        let instance = storage.find_instance_by_id(1).unwrap().unwrap();
+        assert_eq!(instance.status, Some(InstanceStatus::WaitingDispatch.into()));
+    }
+}
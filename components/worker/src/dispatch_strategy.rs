@@ -0,0 +1,216 @@
+//! Choose the task tracker of an instance among the available workers.
+//!
+//! The job's `dispatch_strategy` column picks the strategy, a strategy only reorders the
+//! workers: the first one tracks the instance and the rest join it (e.g. for MapReduce).
+use crate::Worker;
+use dashmap::DashMap;
+use fastjob_components_storage::model::instance_info::InstanceInfo;
+use fastjob_components_storage::model::job_info::{DispatchStrategyType, JobInfo};
+use rand::seq::SliceRandom;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Virtual nodes per worker on the consistent hash ring.
+const VIRTUAL_NODES: usize = 16;
+
+pub(crate) struct DispatchContext<'a> {
+    pub job_info: &'a JobInfo,
+    pub instance: &'a InstanceInfo,
+    /// Unfinished instances tracked per worker address, only loaded for the strategies
+    /// that return true from `needs_running_counts`.
+    pub running: HashMap<String, u64>,
+}
+
+pub(crate) trait DispatchStrategy: Send + Sync {
+    /// Reorder `workers`, which come sorted by health, so the chosen task tracker is first.
+    fn select(&self, ctx: &DispatchContext<'_>, workers: Vec<Worker>) -> Vec<Worker>;
+
+    fn needs_running_counts(&self) -> bool {
+        false
+    }
+}
+
+/// Keep the health ordering.
+pub(crate) struct HealthFirst;
+
+impl DispatchStrategy for HealthFirst {
+    fn select(&self, _ctx: &DispatchContext<'_>, workers: Vec<Worker>) -> Vec<Worker> {
+        workers
+    }
+}
+
+pub(crate) struct Random;
+
+impl DispatchStrategy for Random {
+    fn select(&self, _ctx: &DispatchContext<'_>, mut workers: Vec<Worker>) -> Vec<Worker> {
+        workers.shuffle(&mut rand::thread_rng());
+        workers
+    }
+}
+
+/// Rotate the workers, ordered by address so the rotation is stable, with a cursor per job.
+#[derive(Default)]
+pub(crate) struct RoundRobin {
+    cursors: DashMap<u64, AtomicUsize>,
+}
+
+impl DispatchStrategy for RoundRobin {
+    fn select(&self, ctx: &DispatchContext<'_>, mut workers: Vec<Worker>) -> Vec<Worker> {
+        if workers.is_empty() {
+            return workers;
+        }
+        workers.sort_by(|a, b| a.address.cmp(b.address));
+        let job_id = ctx.job_info.id.unwrap_or_default();
+        let cursor = self
+            .cursors
+            .entry(job_id)
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+        let len = workers.len();
+        workers.rotate_left(cursor % len);
+        workers
+    }
+}
+
+pub(crate) struct LeastRunning;
+
+impl DispatchStrategy for LeastRunning {
+    fn select(&self, ctx: &DispatchContext<'_>, mut workers: Vec<Worker>) -> Vec<Worker> {
+        // stable, so workers with the same count keep the health ordering.
+        workers.sort_by_key(|w| ctx.running.get(w.address).copied().unwrap_or_default());
+        workers
+    }
+
+    fn needs_running_counts(&self) -> bool {
+        true
+    }
+}
+
+/// Hash the instance params onto a ring of the workers, so a worker joining or leaving
+/// only moves the params that hashed next to it.
+pub(crate) struct ConsistentHash;
+
+impl DispatchStrategy for ConsistentHash {
+    fn select(&self, ctx: &DispatchContext<'_>, mut workers: Vec<Worker>) -> Vec<Worker> {
+        if workers.is_empty() {
+            return workers;
+        }
+        let mut ring = BTreeMap::new();
+        for (idx, worker) in workers.iter().enumerate() {
+            for vnode in 0..VIRTUAL_NODES {
+                ring.insert(hash(&(worker.address, vnode)), idx);
+            }
+        }
+        let key = hash(&ctx.instance.instance_params.as_deref().unwrap_or_default());
+        let idx = ring
+            .range(key..)
+            .next()
+            .or_else(|| ring.iter().next())
+            .map(|(_, idx)| *idx)
+            .unwrap();
+        let chosen = workers.remove(idx);
+        workers.insert(0, chosen);
+        workers
+    }
+}
+
+fn hash<T: Hash>(t: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    t.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The built-in strategies keyed by `DispatchStrategyType`.
+pub(crate) struct DispatchStrategies {
+    strategies: HashMap<DispatchStrategyType, Box<dyn DispatchStrategy>>,
+}
+
+impl Default for DispatchStrategies {
+    fn default() -> Self {
+        let mut strategies: HashMap<DispatchStrategyType, Box<dyn DispatchStrategy>> =
+            HashMap::new();
+        strategies.insert(DispatchStrategyType::HealthFirst, Box::new(HealthFirst));
+        strategies.insert(DispatchStrategyType::Random, Box::new(Random));
+        strategies.insert(DispatchStrategyType::RoundRobin, Box::new(RoundRobin::default()));
+        strategies.insert(DispatchStrategyType::LeastRunning, Box::new(LeastRunning));
+        strategies.insert(DispatchStrategyType::ConsistentHash, Box::new(ConsistentHash));
+        Self { strategies }
+    }
+}
+
+impl DispatchStrategies {
+    pub fn get(&self, typ: DispatchStrategyType) -> &dyn DispatchStrategy {
+        self.strategies
+            .get(&typ)
+            .or_else(|| self.strategies.get(&DispatchStrategyType::HealthFirst))
+            .map(|s| s.as_ref())
+            .unwrap_or(&HealthFirst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workers(addresses: &[&'static str]) -> Vec<Worker> {
+        addresses
+            .iter()
+            .map(|address| {
+                let mut worker = Worker::new();
+                worker.address = address;
+                worker
+            })
+            .collect()
+    }
+
+    fn addresses(workers: &[Worker]) -> Vec<&str> {
+        workers.iter().map(|w| w.address).collect()
+    }
+
+    #[test]
+    fn t_round_robin_rotates_per_job() {
+        let strategy = RoundRobin::default();
+        let job = JobInfo { id: Some(1), ..Default::default() };
+        let instance = InstanceInfo::default();
+        let ctx = DispatchContext { job_info: &job, instance: &instance, running: HashMap::new() };
+        let first: Vec<_> = (0..3)
+            .map(|_| strategy.select(&ctx, workers(&["b", "a", "c"]))[0].address)
+            .collect();
+        assert_eq!(first, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn t_least_running_prefers_idle_workers() {
+        let job = JobInfo::default();
+        let instance = InstanceInfo::default();
+        let mut running = HashMap::new();
+        running.insert("a".to_string(), 3);
+        running.insert("b".to_string(), 1);
+        let ctx = DispatchContext { job_info: &job, instance: &instance, running };
+        let selected = LeastRunning.select(&ctx, workers(&["a", "b", "c"]));
+        assert_eq!(addresses(&selected), vec!["c", "b", "a"]);
+    }
+
+    #[test]
+    fn t_consistent_hash_is_sticky() {
+        let job = JobInfo::default();
+        let instance = InstanceInfo {
+            instance_params: Some("user-42".to_string()),
+            ..Default::default()
+        };
+        let ctx = DispatchContext { job_info: &job, instance: &instance, running: HashMap::new() };
+        let chosen = ConsistentHash.select(&ctx, workers(&["a", "b", "c", "d"]))[0].address;
+        // the order the workers come in doesn't matter.
+        assert_eq!(ConsistentHash.select(&ctx, workers(&["d", "c", "b", "a"]))[0].address, chosen);
+        // removing another worker doesn't move the params.
+        let removed = if chosen == "a" { "b" } else { "a" };
+        let remaining: Vec<_> = ["a", "b", "c", "d"]
+            .iter()
+            .copied()
+            .filter(|a| *a != removed)
+            .collect();
+        assert_eq!(ConsistentHash.select(&ctx, workers(&remaining))[0].address, chosen);
+    }
+}
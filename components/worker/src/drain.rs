@@ -0,0 +1,166 @@
+//! Graceful drain on shutdown.
+//!
+//! A draining server stops creating new instances, lets the dispatches already in flight
+//! finish up to a deadline, then hands the apps it owns over to the live peers and leaves
+//! the cluster, so the next tick of every job is scheduled by a peer instead of being lost.
+use crate::app_leases::AppLeases;
+use crate::rebalancer::Rebalancer;
+use crate::server_registry::ServerRegistry;
+use fastjob_components_storage::Storage;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Shutdown hook, registered on `Server` and run after the shutdown signal.
+pub trait Drain {
+    fn drain(&self, deadline: Duration);
+}
+
+/// The draining flag and the number of dispatches in flight, shared by clones.
+#[derive(Clone, Default)]
+pub struct DrainState {
+    inner: Arc<DrainInner>,
+}
+
+#[derive(Default)]
+struct DrainInner {
+    draining: AtomicBool,
+    in_flight: Mutex<usize>,
+    idle: Condvar,
+}
+
+/// Counts as in flight until dropped.
+pub struct InFlight {
+    state: DrainState,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut in_flight = self.state.inner.in_flight.lock().unwrap();
+        *in_flight -= 1;
+        if *in_flight == 0 {
+            self.state.inner.idle.notify_all();
+        }
+    }
+}
+
+impl DrainState {
+    pub fn start_drain(&self) {
+        self.inner.draining.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.inner.draining.load(Ordering::SeqCst)
+    }
+
+    pub fn dispatching(&self) -> InFlight {
+        *self.inner.in_flight.lock().unwrap() += 1;
+        InFlight {
+            state: self.clone(),
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        *self.inner.in_flight.lock().unwrap()
+    }
+
+    /// Block until nothing is in flight or `timeout` elapses, returns whether it became idle.
+    pub fn wait_idle(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut in_flight = self.inner.in_flight.lock().unwrap();
+        while *in_flight > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            in_flight = self.inner.idle.wait_timeout(in_flight, deadline - now).unwrap().0;
+        }
+        true
+    }
+}
+
+pub struct Drainer<S: Storage> {
+    address: String,
+    storage: Arc<S>,
+    registry: ServerRegistry<S>,
+    rebalancer: Rebalancer<S>,
+    app_leases: AppLeases<S>,
+    state: DrainState,
+}
+
+impl<S: Storage> Drainer<S> {
+    pub fn new(
+        address: impl Into<String>,
+        storage: Arc<S>,
+        registry: ServerRegistry<S>,
+        rebalancer: Rebalancer<S>,
+        app_leases: AppLeases<S>,
+        state: DrainState,
+    ) -> Self {
+        Self {
+            address: address.into(),
+            storage,
+            registry,
+            rebalancer,
+            app_leases,
+            state,
+        }
+    }
+}
+
+impl<S: Storage> Drain for Drainer<S> {
+    fn drain(&self, deadline: Duration) {
+        info!("[Drain] server {} start draining.", self.address);
+        self.state.start_drain();
+
+        if !self.state.wait_idle(deadline) {
+            warn!(
+                "[Drain] {} dispatches still in flight after {:?}, stop waiting.",
+                self.state.in_flight(),
+                deadline
+            );
+        }
+
+        match self.rebalancer.hand_over() {
+            Ok(moves) => info!("[Drain] handed {} apps over to the live servers.", moves.len()),
+            Err(e) => warn!("[Drain] hand apps over failed: {}", e),
+        }
+        // apps left without a live peer are released, the first server to look them up takes them.
+        match self.storage.release_app_current_server(&self.address) {
+            Ok(released) if released > 0 => {
+                info!("[Drain] released {} apps without a live peer.", released)
+            }
+            Ok(_) => {}
+            Err(e) => warn!("[Drain] release owned apps failed: {}", e),
+        }
+        self.app_leases.release_all();
+
+        if let Err(e) = self.registry.deregister() {
+            warn!("[Drain] deregister server {} failed: {}", self.address, e);
+        }
+        info!("[Drain] server {} drained.", self.address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_wait_idle() {
+        let state = DrainState::default();
+        assert!(state.wait_idle(Duration::from_millis(0)));
+
+        let in_flight = state.dispatching();
+        assert!(!state.wait_idle(Duration::from_millis(10)));
+
+        let waiter = {
+            let state = state.clone();
+            std::thread::spawn(move || state.wait_idle(Duration::from_secs(5)))
+        };
+        std::thread::sleep(Duration::from_millis(10));
+        drop(in_flight);
+        assert!(waiter.join().unwrap());
+        assert_eq!(state.in_flight(), 0);
+    }
+}
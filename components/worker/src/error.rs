@@ -1,6 +1,7 @@
 use fastjob_components_scheduler::error::SchedError;
 use crate::event::error::EventHandlerError;
-use fastjob_components_storage::error::StorageError;
+use crate::worker_filter::FilterError;
+use fastjob_components_storage::BatisError;
 use snafu::{ResultExt, Snafu};
 
 pub type Result<T, E = WorkerManagerError> = std::result::Result<T, E>;
@@ -9,16 +10,16 @@ pub type Result<T, E = WorkerManagerError> = std::result::Result<T, E>;
 #[snafu(visibility = "pub")]
 pub enum WorkerManagerError {
     #[snafu(display("WorkerManager storage encounter error: {}.", source))]
-    WorkerStorageError { source: StorageError },
+    WorkerStorageError { source: BatisError },
 
     #[snafu(display(
     "App name or id {} is not registered, please register the app first.",
     app_name_or_id
     ))]
-    WorkerNotRegistered { app_name_or_id: &'static str },
+    WorkerNotRegistered { app_name_or_id: String },
 
     #[snafu(display("server {} lookup failed", server_ip))]
-    LookupFail { server_ip: &'static str },
+    LookupFail { server_ip: String },
 
     #[snafu(display("WorkerManager scheduler encounter error: {}.", source))]
     SchedulerFailed { source: SchedError },
@@ -26,6 +27,21 @@ pub enum WorkerManagerError {
     #[snafu(display("WorkerManager event handle encounter error: {}.", source))]
     EventHandlerFailed { source: EventHandlerError },
 
+    #[snafu(display("Send request to worker {} failed: {}.", address, source))]
+    WorkerRpcFailed { source: grpcio::Error, address: String },
+
+    #[snafu(display("Worker {} rejected the request: {}.", address, msg))]
+    WorkerRejected { address: String, msg: String },
+
+    #[snafu(display("Job {} doesn't exist.", job_id))]
+    JobNotFound { job_id: u64 },
+
+    #[snafu(display("Invalid worker filter '{}': {}", expr, source))]
+    InvalidWorkerFilter { source: FilterError, expr: String },
+
     #[snafu(display("Permission Denied"))]
     PermissionDenied,
+
+    #[snafu(display("WorkerManager has no address, set the address this server is reachable at."))]
+    MissingAddress,
 }
@@ -1,17 +1,36 @@
 use std::sync::Arc;
 
-use grpcio::{ChannelBuilder, EnvBuilder};
+use grpcio::{ChannelBuilder, EnvBuilder, Environment};
+use parking_lot::Mutex;
 
 pub use error::Result;
 use fastjob_proto::fastjob::*;
+use fastjob_proto::fastjob_grpc::FastJobClient;
 use std::collections::HashMap;
 use std::cmp::Ordering;
+use fastjob_components_proto::task::{MapReduceTask, RunTaskRequest, StopInstanceRequest};
+use fastjob_components_proto::task_grpc::FastJobTaskWorkerClient;
+use fastjob_components_storage::model::instance_info::InstanceInfo;
 use fastjob_components_storage::model::job_info::JobInfo;
+use fastjob_components_storage::model::task_info::TaskInfo;
+use fastjob_components_utils::grpc_returns::SUCCESS;
+use worker_filter::{FilterTarget, Value, Var, WorkerFilter};
+
+/// A worker that hasn't sent a heartbeat for this long is removed.
+const WORKER_HEARTBEAT_TIMEOUT_MS: i64 = 60000;
+const GRPC_CLIENT: &str = "GRPC-CLIENT";
 
 mod alarm_controller;
+pub mod app_leases;
+pub mod broadcast;
 mod dispatch;
-mod error;
+mod dispatch_strategy;
+pub mod drain;
+pub mod error;
 mod instance_status_checker;
+pub mod rebalancer;
+pub mod server_registry;
+pub mod worker_filter;
 pub mod worker_manager;
 mod event;
 
@@ -25,10 +44,11 @@ struct WorkerClusterHolder {
     containers: HashMap<u64, HashMap<&'static str, DeployContainerInfo>>,
 }
 
+#[derive(Clone)]
 struct Worker {
     address: &'static str,
     last_active_time: i64,
-    client: Option<grpcio::Client>,
+    client: Option<FastJobClient>,
     tag: &'static str,
     indicators: WorkerIndicators,
 }
@@ -41,22 +61,6 @@ impl PartialEq for Worker {
     }
 }
 
-impl Ord for Worker {
-    fn cmp(&self, other: &Self) -> Ordering {
-        let available_memory = self.indicators.get_jvmMaxMemory() - self.indicators.get_jvmUsedMemory() as f32;
-        let available_cpu = self.indicators.get_cpuProcessors() - self.indicators.get_cpuLoad();
-        let o_available_memory = other.indicators.get_jvmMaxMemory() - other.indicators.get_jvmUsedMemory() as f32;
-        let o_available_cpu = other.indicators.get_cpuProcessors() - other.indicators.get_cpuLoad();
-        (available_memory + available_cpu).cmp(&(o_available_memory - o_available_cpu))
-    }
-}
-
-impl PartialOrd for Worker {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
-}
-
 impl Worker {
     pub fn new() -> Self {
         Self {
@@ -69,36 +73,58 @@ impl Worker {
     }
 
     fn refresh(&mut self, heartbeat: &HeartBeatRequest) {
+        if self.client.is_none() || self.address != heartbeat.get_workerAddress() {
+            self.client = Some(init_grpc_client(heartbeat.get_workerAddress()));
+        }
         self.address = heartbeat.get_workerAddress();
         self.last_active_time = heartbeat.get_heartbeatTime();
         self.tag = heartbeat.get_tag();
         self.indicators = heartbeat.get_indicators();
     }
 
-    fn is_available(&self, job_info: &JobInfo) -> bool {
+    fn is_available(&self, job_info: &JobInfo, filter: Option<&WorkerFilter>, now: i64) -> bool {
         // 1. determine job whether specified the worker.
-        if let Some(d_workers) = &job_info.designated_workers {
-            let workers: Vec<_> = d_workers.split(',').collect();
-            if workers.contains(&self.address) || workers.contains(&self.tag) {
-                return true;
+        if let Some(d_workers) = job_info.designated_workers.as_deref().filter(|w| !w.is_empty()) {
+            let workers: Vec<_> = d_workers.split(',').map(str::trim).collect();
+            if !workers.contains(&self.address) && !workers.contains(&self.tag) {
+                return false;
             }
         }
 
         // 2. determine the worker whether expired.
-        if chrono::Local::now().timestamp_millis() < self.last_active_time {
+        if self.is_expired(now) {
             warn!("[Worker - {}] unreported heartbeat for a long time.", self.address);
-            return true;
+            return false;
         }
-        // 3. determine the worker indicators whether is satisfied.
-        let available_memory = self.indicators.get_jvmMaxMemory() - self.indicators.get_jvmUsedMemory();
-        let available_disk = self.indicators.get_diskTotal() - self.indicators.get_diskUsed();
-        let available_core = self.indicators.get_cpuProcessors() - self.indicators.get_cpuLoad();
-        if available_memory < job_info.min_memory_space.unwrap()
-            || available_disk < job_info.min_disk_space.unwrap()
-            || available_core < job_info.min_cpu_cores.unwrap() {
-            return true;
+        // 3. determine the worker indicators whether is satisfied, 0 represents unlimited.
+        let (memory, disk, cpu) = self.available_resources();
+        if memory < job_info.min_memory_space.unwrap_or_default()
+            || disk < job_info.min_disk_space.unwrap_or_default()
+            || cpu < job_info.min_cpu_cores.unwrap_or_default()
+        {
+            return false;
         }
-        false
+        // 4. determine the worker whether matches the user-defined filter.
+        filter.map_or(true, |f| f.matches(self))
+    }
+
+    #[inline]
+    fn is_expired(&self, now: i64) -> bool {
+        now - self.last_active_time > WORKER_HEARTBEAT_TIMEOUT_MS
+    }
+
+    /// Available memory (GB), disk (GB) and cpu cores.
+    fn available_resources(&self) -> (f64, f64, f64) {
+        let memory = self.indicators.get_jvmMaxMemory() as f64 - self.indicators.get_jvmUsedMemory() as f64;
+        let disk = self.indicators.get_diskTotal() as f64 - self.indicators.get_diskUsed() as f64;
+        let cpu = self.indicators.get_cpuProcessors() as f64 - self.indicators.get_cpuLoad() as f64;
+        (memory, disk, cpu)
+    }
+
+    /// Higher is better, used to rank the suitable workers.
+    fn score(&self) -> f64 {
+        let (memory, _, cpu) = self.available_resources();
+        memory + cpu
     }
 
     fn worker_clean(&self) {
@@ -111,6 +137,26 @@ impl Worker {
     }
 }
 
+impl FilterTarget for Worker {
+    fn value(&self, var: Var) -> Value<'_> {
+        let i = &self.indicators;
+        let ratio = |used: f64, total: f64| if total > 0.0 { used / total } else { 0.0 };
+        let n = match var {
+            Var::Address => return Value::Str(self.address),
+            Var::Tag => return Value::Str(self.tag),
+            Var::CpuProcessors => i.get_cpuProcessors() as f64,
+            Var::CpuLoad => i.get_cpuLoad() as f64,
+            Var::JvmUsedMemory => i.get_jvmUsedMemory() as f64,
+            Var::JvmMaxMemory => i.get_jvmMaxMemory() as f64,
+            Var::JvmMemoryUsage => ratio(i.get_jvmUsedMemory() as f64, i.get_jvmMaxMemory() as f64),
+            Var::DiskUsed => i.get_diskUsed() as f64,
+            Var::DiskTotal => i.get_diskTotal() as f64,
+            Var::DiskUsage => ratio(i.get_diskUsed() as f64, i.get_diskTotal() as f64),
+        };
+        Value::Num(n)
+    }
+}
+
 impl WorkerClusterHolder {
     pub fn new(app_name: &'static str) -> Self {
         Self {
@@ -155,26 +201,32 @@ impl WorkerClusterHolder {
         }
     }
 
-    /// Returns the most suitable worker and if have worker unavailable that will remove it.
-    pub fn get_suitable_worker(&mut self, job_info: &JobInfo) -> Option<&[Worker]> {
-        if self.workers.is_empty() {
-            return None;
-        }
-        let mut worker: &[_] = self.workers.clone().values().collect();
-        worker.retain(|_, &v| v.is_available(job_info));
-        worker.sort();
-
-
-        if worker.is_empty() {
-            return None;
-        }
+    /// Returns the available workers, the healthiest first, and removes the expired workers.
+    pub fn get_suitable_worker(&mut self, job_info: &JobInfo) -> Vec<Worker> {
+        let now = chrono::Local::now().timestamp_millis();
+        self.workers.retain(|_, worker| !worker.is_expired(now));
 
-        if let Some(count) = job_info.max_worker_count {
-            if count > 0 && worker.len() > count {
-                worker.split_at(count);
+        // filters are validated when the job is saved, one that still fails to parse
+        // matches no worker rather than letting the job run anywhere.
+        let filter = match WorkerFilter::from_extra(job_info.extra.as_deref()) {
+            Ok(filter) => filter,
+            Err(e) => {
+                error!(
+                    "[WorkerClusterHolder] invalid worker filter of job {}: {}",
+                    job_info.id.unwrap_or_default(),
+                    e
+                );
+                return vec![];
             }
-        }
-        Some(worker)
+        };
+        let mut workers: Vec<Worker> = self
+            .workers
+            .values()
+            .filter(|worker| worker.is_available(job_info, filter.as_ref(), now))
+            .cloned()
+            .collect();
+        workers.sort_by(|a, b| b.score().partial_cmp(&a.score()).unwrap_or(Ordering::Equal));
+        workers
     }
 
     /// Returns all containers deployment status in worker.
@@ -197,13 +249,68 @@ impl WorkerClusterHolder {
     }
 }
 
-fn init_grpc_client(addr: &str) -> Result<::grpcio::Client> {
-    let env = Arc::new(EnvBuilder::new().build());
-    let ch = ChannelBuilder::new(env).connect(addr);
-    let client = FastJobClient::new(ch);
-    Ok(client)
+/// The completion queues shared by the clients of every worker.
+static CLIENT_ENV: Mutex<Option<Arc<Environment>>> = parking_lot::const_mutex(None);
+
+fn client_env() -> Arc<Environment> {
+    CLIENT_ENV
+        .lock()
+        .get_or_insert_with(|| Arc::new(EnvBuilder::new().name_prefix(GRPC_CLIENT).build()))
+        .clone()
+}
+
+fn init_grpc_client(addr: &str) -> FastJobClient {
+    let ch = ChannelBuilder::new(client_env()).connect(addr);
+    FastJobClient::new(ch)
     // let mut req = HelloRequest::default();
     // req.set_name("world".to_owned());
     // let reply = client.say_hello(&req).expect("rpc");
     // info!("Greeter received: {}", reply.get_message());
 }
+
+/// Send a task of a MapReduce instance to the worker at `address`, the reduce task along
+/// with every map task, see `MapReduceEngine::dispatch_pending`.
+pub fn send_task(
+    address: &str,
+    task: &TaskInfo,
+    map_tasks: &[TaskInfo],
+) -> std::result::Result<(), String> {
+    let ch = ChannelBuilder::new(client_env()).connect(address);
+    let client = FastJobTaskWorkerClient::new(ch);
+    let mut req = RunTaskRequest::default();
+    req.set_jobId(task.job_id.unwrap_or_default());
+    req.set_instanceId(task.instance_id.unwrap_or_default());
+    req.set_task(map_reduce_task(task));
+    req.set_mapTasks(map_tasks.iter().map(map_reduce_task).collect::<Vec<_>>().into());
+    let reply = client.run_task(&req).map_err(|e| e.to_string())?;
+    if reply.get_code() != SUCCESS {
+        return Err(reply.get_message().to_string());
+    }
+    Ok(())
+}
+
+/// Ask the task tracker at `address` to stop an instance, see
+/// `WorkerManager::stop_workflow_instance`.
+pub fn stop_instance(address: &str, instance: &InstanceInfo) -> std::result::Result<(), String> {
+    let ch = ChannelBuilder::new(client_env()).connect(address);
+    let client = FastJobTaskWorkerClient::new(ch);
+    let mut req = StopInstanceRequest::default();
+    req.set_jobId(instance.job_id.unwrap_or_default());
+    req.set_instanceId(instance.instance_id.unwrap_or_default());
+    let reply = client.stop_instance(&req).map_err(|e| e.to_string())?;
+    if reply.get_code() != SUCCESS {
+        return Err(reply.get_message().to_string());
+    }
+    Ok(())
+}
+
+fn map_reduce_task(task: &TaskInfo) -> MapReduceTask {
+    let mut t = MapReduceTask::default();
+    t.set_taskId(task.task_id.clone().unwrap_or_default());
+    t.set_taskName(task.task_name.clone().unwrap_or_default());
+    t.set_taskType(task.task_type.unwrap_or_default());
+    t.set_taskParams(task.task_params.clone().unwrap_or_default());
+    t.set_status(task.status.unwrap_or_default());
+    t.set_result(task.result.clone().unwrap_or_default());
+    t
+}
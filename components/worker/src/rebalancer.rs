@@ -0,0 +1,309 @@
+//! Spread the apps evenly over the live servers.
+//!
+//! Apps whose server is dead (or that have none) are moved first, then apps move from the
+//! most to the least loaded server until no two servers differ by more than one app.
+//! A round moves at most `max_moves` apps so a server joining or leaving doesn't reshuffle
+//! the whole cluster at once.
+//!
+//! Every server plans each round but an app only moves with its lease, see `AppLeases`. A
+//! live server hands its own apps over itself with the lease it holds, the apps of dead
+//! servers are moved by the server holding the rebalance lock once their lease expired.
+use crate::app_leases::AppLeases;
+use crate::drain::DrainState;
+use crate::server_registry::ServerRegistry;
+use fastjob_components_storage::{BatisError, Lease, LockService, Storage};
+use fastjob_components_utils::sched_pool::{JobHandle, SchedPool};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const REBALANCE_INTERVAL: Duration = Duration::from_millis(30000);
+pub const DEFAULT_MAX_MOVES_PER_ROUND: usize = 10;
+const REBALANCE_LOCK_NAME: &str = "rebalance";
+
+/// Reassign `app_id` from `from` (`None` when it had no server) to `to`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Move {
+    pub app_id: u64,
+    pub from: Option<String>,
+    pub to: String,
+}
+
+/// Plan at most `max_moves` reassignments of `assignments` (app id, current server)
+/// over the `live` servers.
+pub fn plan_rebalance(
+    assignments: &[(u64, Option<String>)],
+    live: &[String],
+    max_moves: usize,
+) -> Vec<Move> {
+    let mut moves = vec![];
+    if live.is_empty() {
+        return moves;
+    }
+    let mut load: BTreeMap<&str, Vec<u64>> = live.iter().map(|s| (s.as_str(), vec![])).collect();
+    let mut orphans = vec![];
+    for (app_id, server) in assignments {
+        match server.as_deref().and_then(|s| load.get_mut(s)) {
+            Some(apps) => apps.push(*app_id),
+            None => orphans.push((*app_id, server.clone())),
+        }
+    }
+    load.values_mut().for_each(|apps| apps.sort_unstable());
+    orphans.sort_unstable();
+
+    for (app_id, from) in orphans {
+        if moves.len() >= max_moves {
+            return moves;
+        }
+        let to = least_loaded(&load);
+        load.get_mut(to).unwrap().push(app_id);
+        moves.push(Move {
+            app_id,
+            from,
+            to: to.to_string(),
+        });
+    }
+
+    while moves.len() < max_moves {
+        let (most, least) = (most_loaded(&load), least_loaded(&load));
+        if load[most].len() <= load[least].len() + 1 {
+            break;
+        }
+        let app_id = load.get_mut(most).unwrap().pop().unwrap();
+        load.get_mut(least).unwrap().push(app_id);
+        moves.push(Move {
+            app_id,
+            from: Some(most.to_string()),
+            to: least.to_string(),
+        });
+    }
+    moves
+}
+
+fn least_loaded<'a>(load: &BTreeMap<&'a str, Vec<u64>>) -> &'a str {
+    load.iter().min_by_key(|(_, apps)| apps.len()).map(|(s, _)| *s).unwrap()
+}
+
+fn most_loaded<'a>(load: &BTreeMap<&'a str, Vec<u64>>) -> &'a str {
+    load.iter().max_by_key(|(_, apps)| apps.len()).map(|(s, _)| *s).unwrap()
+}
+
+pub struct Rebalancer<S: Storage> {
+    address: String,
+    storage: Arc<S>,
+    registry: ServerRegistry<S>,
+    app_leases: AppLeases<S>,
+    lock_service: LockService<S>,
+    max_moves: usize,
+    drain: DrainState,
+}
+
+impl<S: Storage> Clone for Rebalancer<S> {
+    fn clone(&self) -> Self {
+        Self {
+            address: self.address.clone(),
+            storage: self.storage.clone(),
+            registry: self.registry.clone(),
+            app_leases: self.app_leases.clone(),
+            lock_service: self.lock_service.clone(),
+            max_moves: self.max_moves,
+            drain: self.drain.clone(),
+        }
+    }
+}
+
+impl<S: Storage> Rebalancer<S> {
+    pub fn new(
+        address: impl Into<String>,
+        storage: Arc<S>,
+        registry: ServerRegistry<S>,
+        app_leases: AppLeases<S>,
+        max_moves: usize,
+        drain: DrainState,
+    ) -> Self {
+        Self {
+            address: address.into(),
+            lock_service: LockService::new(storage.clone()),
+            storage,
+            registry,
+            app_leases,
+            max_moves,
+            drain,
+        }
+    }
+
+    /// Run a round every `REBALANCE_INTERVAL` until the returned handle is canceled.
+    pub fn start(&self, sched_pool: &SchedPool) -> JobHandle
+        where
+            S: Send + Sync + 'static,
+    {
+        let rebalancer = self.clone();
+        sched_pool.schedule_at_fixed_rate(
+            move || {
+                if let Err(e) = rebalancer.rebalance() {
+                    error!("[Rebalance] server {} rebalance failed: {}", rebalancer.address, e);
+                }
+            },
+            REBALANCE_INTERVAL,
+            REBALANCE_INTERVAL,
+        )
+    }
+
+    /// Plan and apply one round, returns the moves that were applied.
+    pub fn rebalance(&self) -> Result<Vec<Move>, BatisError> {
+        // a draining server is leaving, it must not pick up apps.
+        if self.drain.is_draining() {
+            return Ok(vec![]);
+        }
+        let live = self.registry.live_servers();
+        let moves = plan_rebalance(&self.assignments()?, &live, self.max_moves);
+        let (own, others): (Vec<Move>, Vec<Move>) = moves
+            .into_iter()
+            .partition(|m| m.from.as_deref() == Some(self.address.as_str()));
+        let mut applied = self.apply(own)?;
+
+        // the apps of the other live servers are handed over by those servers.
+        let orphans: Vec<Move> = others
+            .into_iter()
+            .filter(|m| m.from.as_ref().map_or(true, |from| !live.contains(from)))
+            .collect();
+        if orphans.is_empty() {
+            return Ok(applied);
+        }
+        let lease = match self.lock_service.try_acquire(
+            REBALANCE_LOCK_NAME,
+            &self.address,
+            REBALANCE_INTERVAL,
+        )? {
+            Some(lease) => lease,
+            None => return Ok(applied),
+        };
+        let rs = self.apply(orphans);
+        if let Err(e) = self.lock_service.release(lease) {
+            warn!("[Rebalance] release rebalance lock failed: {}", e);
+        }
+        applied.extend(rs?);
+        Ok(applied)
+    }
+
+    /// Move every app owned by this server onto the other live servers, used when draining.
+    pub fn hand_over(&self) -> Result<Vec<Move>, BatisError> {
+        let peers: Vec<String> = self
+            .registry
+            .live_servers()
+            .into_iter()
+            .filter(|s| *s != self.address)
+            .collect();
+        let moves = plan_rebalance(&self.assignments()?, &peers, usize::MAX)
+            .into_iter()
+            .filter(|m| m.from.as_deref() == Some(self.address.as_str()))
+            .collect();
+        self.apply(moves)
+    }
+
+    fn assignments(&self) -> Result<Vec<(u64, Option<String>)>, BatisError> {
+        Ok(self
+            .storage
+            .find_all_app_infos()?
+            .into_iter()
+            .filter_map(|app| Some((app.id?, app.current_server)))
+            .collect())
+    }
+
+    fn apply(&self, moves: Vec<Move>) -> Result<Vec<Move>, BatisError> {
+        let mut applied = vec![];
+        for m in moves {
+            let lease = match self.lease_of(&m)? {
+                Some(lease) => lease,
+                None => {
+                    info!("[Rebalance] the lease of app {} is held, skip it this round.", m.app_id);
+                    continue;
+                }
+            };
+            let moved = self
+                .storage
+                .update_app_current_server(m.app_id, &m.to, lease.fencing_token());
+            // the new server takes the lease over on its next schedule round.
+            self.app_leases.release(m.app_id, lease);
+            if moved? {
+                info!(
+                    "[Rebalance] app {} moved from {} to {}.",
+                    m.app_id,
+                    m.from.as_deref().unwrap_or("none"),
+                    m.to
+                );
+                applied.push(m);
+            }
+        }
+        Ok(applied)
+    }
+
+    /// The lease of the app to move, the one this server holds for its own apps, else a
+    /// lease taken if no server holds it and the app is still where the plan found it.
+    fn lease_of(&self, m: &Move) -> Result<Option<Lease>, BatisError> {
+        if m.from.as_deref() == Some(self.address.as_str()) {
+            if let Some(lease) = self.app_leases.take(m.app_id) {
+                return Ok(Some(lease));
+            }
+        }
+        let lease = match self.app_leases.try_acquire(m.app_id)? {
+            Some(lease) => lease,
+            None => return Ok(None),
+        };
+        // another server may have moved the app since the plan was made.
+        let current = self
+            .storage
+            .find_app_info_by_id(m.app_id)?
+            .and_then(|app| app.current_server);
+        if current != m.from {
+            self.app_leases.release(m.app_id, lease);
+            return Ok(None);
+        }
+        Ok(Some(lease))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn servers(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn assign(apps: &[(u64, &str)]) -> Vec<(u64, Option<String>)> {
+        apps.iter()
+            .map(|(id, s)| (*id, Some(s.to_string()).filter(|s| !s.is_empty())))
+            .collect()
+    }
+
+    #[test]
+    fn t_plan_moves_apps_off_dead_servers() {
+        let apps = assign(&[(1, "a"), (2, "dead"), (3, "dead"), (4, "")]);
+        let moves = plan_rebalance(&apps, &servers(&["a", "b"]), 10);
+        assert_eq!(
+            moves,
+            vec![
+                Move { app_id: 2, from: Some("dead".to_string()), to: "b".to_string() },
+                Move { app_id: 3, from: Some("dead".to_string()), to: "a".to_string() },
+                Move { app_id: 4, from: None, to: "b".to_string() },
+            ]
+        );
+    }
+
+    #[test]
+    fn t_plan_spreads_onto_joined_server() {
+        let apps = assign(&[(1, "a"), (2, "a"), (3, "a"), (4, "a"), (5, "a"), (6, "b")]);
+        let moves = plan_rebalance(&apps, &servers(&["a", "b", "c"]), 10);
+        assert_eq!(moves.len(), 3);
+        assert!(moves.iter().all(|m| m.from.as_deref() == Some("a")));
+        assert!(plan_rebalance(&assign(&[(1, "a"), (2, "b")]), &servers(&["a", "b"]), 10).is_empty());
+    }
+
+    #[test]
+    fn t_plan_caps_moves_per_round() {
+        let apps: Vec<_> = (1..=20).map(|id| (id, Some("a".to_string()))).collect();
+        assert_eq!(plan_rebalance(&apps, &servers(&["a", "b"]), 4).len(), 4);
+        assert!(plan_rebalance(&apps, &[], 4).is_empty());
+    }
+}
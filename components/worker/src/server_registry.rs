@@ -0,0 +1,271 @@
+//! Server membership, every fastjob server heartbeats its address into `server_info`
+//! and considers a peer alive while that peer's heartbeat is fresh.
+//!
+//! A server also leases the worker id of its id generator, one lock per slot in
+//! `0..=MAX_WORKER_ID`, the heartbeat renews the lease along with the server's row.
+use chrono::Local;
+use fastjob_components_storage::{BatisError, Lease, LockService, Storage};
+use fastjob_components_utils::id_generator::{self, MAX_WORKER_ID};
+use fastjob_components_utils::sched_pool::{JobHandle, SchedPool};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+pub const SERVER_HEARTBEAT_INTERVAL: Duration = Duration::from_millis(5000);
+/// A peer that missed this many milliseconds of heartbeats is considered dead.
+pub const SERVER_EXPIRE_TIME_MS: i64 = 15000;
+/// The worker id of a dead server is free again once its heartbeats expired.
+pub const WORKER_ID_LEASE_TTL: Duration = Duration::from_millis(SERVER_EXPIRE_TIME_MS as u64);
+
+pub struct ServerRegistry<S: Storage> {
+    address: String,
+    storage: Arc<S>,
+    lock_service: LockService<S>,
+    // address -> last heartbeat, refreshed on every heartbeat.
+    servers: Arc<RwLock<HashMap<String, i64>>>,
+    // the leased worker id installed in the id generator, `None` until leased or once lost.
+    worker_id: Arc<Mutex<Option<(u64, Lease)>>>,
+    deregistered: Arc<AtomicBool>,
+}
+
+impl<S: Storage> Clone for ServerRegistry<S> {
+    fn clone(&self) -> Self {
+        Self {
+            address: self.address.clone(),
+            storage: self.storage.clone(),
+            lock_service: self.lock_service.clone(),
+            servers: self.servers.clone(),
+            worker_id: self.worker_id.clone(),
+            deregistered: self.deregistered.clone(),
+        }
+    }
+}
+
+impl<S: Storage> ServerRegistry<S> {
+    pub fn new(address: impl Into<String>, storage: Arc<S>) -> Self {
+        Self {
+            address: address.into(),
+            lock_service: LockService::new(storage.clone()),
+            storage,
+            servers: Arc::new(RwLock::new(HashMap::new())),
+            worker_id: Arc::new(Mutex::new(None)),
+            deregistered: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Heartbeat every `SERVER_HEARTBEAT_INTERVAL` until the returned handle is canceled.
+    pub fn start(&self, sched_pool: &SchedPool) -> JobHandle
+        where
+            S: Send + Sync + 'static,
+    {
+        let registry = self.clone();
+        sched_pool.schedule_at_fixed_rate(
+            move || {
+                if let Err(e) = registry.heartbeat() {
+                    error!("[ServerRegistry] server {} heartbeat failed: {}", registry.address, e);
+                }
+            },
+            Duration::from_millis(0),
+            SERVER_HEARTBEAT_INTERVAL,
+        )
+    }
+
+    /// Refresh our own heartbeat and worker id lease, expire stale peers and reload the
+    /// live servers.
+    pub fn heartbeat(&self) -> Result<(), BatisError> {
+        if self.deregistered.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let now = Local::now().timestamp_millis();
+        self.storage.upsert_server_heartbeat(&self.address, now)?;
+        self.renew_worker_id(now)?;
+        let expired = self
+            .storage
+            .delete_expired_servers(now - SERVER_EXPIRE_TIME_MS)?;
+        if expired > 0 {
+            info!("[ServerRegistry] expired {} stale servers.", expired);
+        }
+        self.refresh(now)
+    }
+
+    /// The id generator worker id of this server, a slot no other live server holds.
+    ///
+    /// The first call leases a free slot, starting from one derived from the address so
+    /// servers rarely contend, and installs it in the id generator. Later calls return the
+    /// held slot, or lease a new one if the lease was lost.
+    pub fn worker_id(&self) -> Result<u64, BatisError> {
+        let mut held = self.worker_id.lock().unwrap();
+        if let Some((worker_id, _)) = held.as_ref() {
+            return Ok(*worker_id);
+        }
+        let mut hasher = DefaultHasher::new();
+        self.address.hash(&mut hasher);
+        let first = hasher.finish() & MAX_WORKER_ID;
+        for offset in 0..=MAX_WORKER_ID {
+            let worker_id = (first + offset) & MAX_WORKER_ID;
+            let lease = self.lock_service.try_acquire(
+                &worker_id_lock_name(worker_id),
+                &self.address,
+                WORKER_ID_LEASE_TTL,
+            )?;
+            if let Some(lease) = lease {
+                if let Err(e) = id_generator::init(worker_id) {
+                    self.lock_service.release(lease)?;
+                    return Err(BatisError::from(e.to_string()));
+                }
+                info!(
+                    "[ServerRegistry] server {} leased worker id {}.",
+                    self.address, worker_id
+                );
+                *held = Some((worker_id, lease));
+                return Ok(worker_id);
+            }
+        }
+        Err(BatisError::from(format!(
+            "all {} worker ids are leased, server {} gets none",
+            MAX_WORKER_ID + 1,
+            self.address
+        )))
+    }
+
+    /// Whether this server holds the lease of a worker id.
+    pub fn has_worker_id(&self) -> bool {
+        self.worker_id.lock().unwrap().is_some()
+    }
+
+    /// Renew the worker id lease. A lease that was taken over, or that expired while the
+    /// storage was unreachable, is given up and the id generator uninstalled before another
+    /// slot is leased, ids are never generated with a slot another server may hold.
+    fn renew_worker_id(&self, now: i64) -> Result<(), BatisError> {
+        let mut held = self.worker_id.lock().unwrap();
+        let (worker_id, lease) = match held.as_mut() {
+            Some((worker_id, lease)) => (*worker_id, lease),
+            None => return Ok(()),
+        };
+        match self.lock_service.renew(lease) {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(e) if lease.is_valid(now) => return Err(e),
+            Err(e) => {
+                warn!("[ServerRegistry] renew worker id {} failed: {}", worker_id, e);
+            }
+        }
+        *held = None;
+        id_generator::reset(worker_id);
+        drop(held);
+        warn!(
+            "[ServerRegistry] server {} lost the lease of worker id {}.",
+            self.address, worker_id
+        );
+        self.worker_id().map(|_| ())
+    }
+
+    /// Leave the cluster, heartbeats stop and peers stop seeing this server at once.
+    pub fn deregister(&self) -> Result<(), BatisError> {
+        self.deregistered.store(true, Ordering::SeqCst);
+        self.storage.delete_server(&self.address)?;
+        if let Some((worker_id, lease)) = self.worker_id.lock().unwrap().take() {
+            id_generator::reset(worker_id);
+            self.lock_service.release(lease)?;
+        }
+        info!("[ServerRegistry] server {} deregistered.", self.address);
+        Ok(())
+    }
+
+    /// Addresses of all live servers, including this one once it has heartbeated.
+    pub fn live_servers(&self) -> Vec<String> {
+        let since = Local::now().timestamp_millis() - SERVER_EXPIRE_TIME_MS;
+        let mut servers: Vec<String> = self
+            .servers
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, heartbeat)| **heartbeat >= since)
+            .map(|(address, _)| address.clone())
+            .collect();
+        servers.sort();
+        servers
+    }
+
+    /// Whether `address` heartbeated recently, reloads the registry once on a miss
+    /// so a peer that just joined is not reported dead.
+    pub fn is_alive(&self, address: &str) -> bool {
+        if address.is_empty() {
+            return false;
+        }
+        if self.live_servers().iter().any(|s| s == address) {
+            return true;
+        }
+        if let Err(e) = self.refresh(Local::now().timestamp_millis()) {
+            warn!("[ServerRegistry] reload live servers failed: {}", e);
+            return false;
+        }
+        self.live_servers().iter().any(|s| s == address)
+    }
+
+    fn refresh(&self, now: i64) -> Result<(), BatisError> {
+        let servers = self
+            .storage
+            .find_alive_servers(now - SERVER_EXPIRE_TIME_MS)?
+            .into_iter()
+            .filter_map(|server| Some((server.ip?, server.last_heartbeat?)))
+            .collect();
+        *self.servers.write().unwrap() = servers;
+        Ok(())
+    }
+}
+
+/// The lock leasing `worker_id` to a live server.
+fn worker_id_lock_name(worker_id: u64) -> String {
+    format!("id-worker-{}", worker_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fastjob_components_storage::MemoryStorage;
+
+    #[test]
+    fn t_registry_expires_stale_servers() {
+        let storage = Arc::new(MemoryStorage::new());
+        let now = Local::now().timestamp_millis();
+        storage
+            .upsert_server_heartbeat("10.0.0.2:3000", now - SERVER_EXPIRE_TIME_MS - 1)
+            .unwrap();
+        storage.upsert_server_heartbeat("10.0.0.3:3000", now).unwrap();
+
+        let registry = ServerRegistry::new("10.0.0.1:3000", storage.clone());
+        assert!(!registry.is_alive("10.0.0.1:3000"));
+        registry.heartbeat().unwrap();
+
+        assert_eq!(registry.live_servers(), vec!["10.0.0.1:3000", "10.0.0.3:3000"]);
+        assert!(!registry.is_alive("10.0.0.2:3000"));
+        assert_eq!(storage.find_alive_servers(0).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn t_worker_id() {
+        let storage = Arc::new(MemoryStorage::new());
+        let first = ServerRegistry::new("10.0.0.1:3000", storage.clone());
+        let second = ServerRegistry::new("10.0.0.2:3000", storage.clone());
+        let worker_id = first.worker_id().unwrap();
+        assert_eq!(first.worker_id().unwrap(), worker_id);
+        assert!(first.has_worker_id());
+        let second_id = second.worker_id().unwrap();
+        assert_ne!(second_id, worker_id);
+
+        // the slot is taken while leased and free once released.
+        let lock = worker_id_lock_name(worker_id);
+        let now = Local::now().timestamp_millis();
+        assert_eq!(storage.acquire_lock(&lock, "10.0.0.3:3000", 1000, now).unwrap(), None);
+        // the second registry installed its generator last, the first giving up its slot
+        // leaves it installed.
+        first.deregister().unwrap();
+        assert!(!first.has_worker_id());
+        assert_eq!(id_generator::generator().unwrap().worker_id(), second_id);
+        assert!(storage.acquire_lock(&lock, "10.0.0.3:3000", 1000, now).unwrap().is_some());
+    }
+}
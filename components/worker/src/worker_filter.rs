@@ -0,0 +1,591 @@
+//! Worker filter expressions, written in `JobInfo.extra`.
+//!
+//! A filter is evaluated against every available worker and only the workers it matches
+//! run the job, e.g. `cpuLoad < 0.8 && tag startsWith "gpu-"`.
+//!
+//! ```text
+//! expr     := and ( ("||" | "or") and )*
+//! and      := unary ( ("&&" | "and") unary )*
+//! unary    := ("!" | "not") unary | "(" expr ")" | "true" | "false" | operand op operand
+//! op       := "==" | "!=" | "<" | "<=" | ">" | ">="
+//!           | "contains" | "startsWith" | "endsWith" | "matches"
+//! operand  := number | "string" | 'string' | variable
+//! ```
+//!
+//! `matches` takes a glob where `*` matches any run of characters and `?` a single one.
+//! Variables and operators are type checked when the filter is parsed, so a filter that
+//! parses never fails to evaluate.
+use snafu::Snafu;
+
+pub type Result<T, E = FilterError> = std::result::Result<T, E>;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub")]
+pub enum FilterError {
+    #[snafu(display("unexpected character '{}' at {}.", ch, pos))]
+    UnexpectedChar { ch: char, pos: usize },
+
+    #[snafu(display("unterminated string starting at {}.", pos))]
+    UnterminatedString { pos: usize },
+
+    #[snafu(display("expected {} at {}, found {}.", expected, pos, found))]
+    UnexpectedToken {
+        expected: &'static str,
+        found: String,
+        pos: usize,
+    },
+
+    #[snafu(display("unknown variable '{}' at {}.", name, pos))]
+    UnknownVariable { name: String, pos: usize },
+
+    #[snafu(display("operator '{}' at {} can't compare {} with {}.", op, pos, left, right))]
+    TypeMismatch {
+        op: &'static str,
+        left: &'static str,
+        right: &'static str,
+        pos: usize,
+    },
+}
+
+/// The worker attributes a filter can refer to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Var {
+    Address,
+    Tag,
+    CpuProcessors,
+    CpuLoad,
+    JvmUsedMemory,
+    JvmMaxMemory,
+    /// `jvmUsedMemory / jvmMaxMemory`.
+    JvmMemoryUsage,
+    DiskUsed,
+    DiskTotal,
+    /// `diskUsed / diskTotal`.
+    DiskUsage,
+}
+
+impl Var {
+    fn from_name(name: &str) -> Option<Var> {
+        let var = match name {
+            "address" => Var::Address,
+            "tag" => Var::Tag,
+            "cpuProcessors" => Var::CpuProcessors,
+            "cpuLoad" => Var::CpuLoad,
+            "jvmUsedMemory" => Var::JvmUsedMemory,
+            "jvmMaxMemory" => Var::JvmMaxMemory,
+            "jvmMemoryUsage" => Var::JvmMemoryUsage,
+            "diskUsed" => Var::DiskUsed,
+            "diskTotal" => Var::DiskTotal,
+            "diskUsage" => Var::DiskUsage,
+            _ => return None,
+        };
+        Some(var)
+    }
+
+    fn typ(self) -> Type {
+        match self {
+            Var::Address | Var::Tag => Type::Str,
+            _ => Type::Num,
+        }
+    }
+}
+
+/// The value of a `Var` on a worker.
+pub enum Value<'a> {
+    Num(f64),
+    Str(&'a str),
+}
+
+/// Something a filter can be evaluated against.
+pub trait FilterTarget {
+    /// Returns a `Value::Str` for `Var::Address` and `Var::Tag`, a `Value::Num` otherwise.
+    fn value(&self, var: Var) -> Value<'_>;
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Type {
+    Num,
+    Str,
+}
+
+impl Type {
+    fn name(self) -> &'static str {
+        match self {
+            Type::Num => "number",
+            Type::Str => "string",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+    StartsWith,
+    EndsWith,
+    Matches,
+}
+
+impl CmpOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            CmpOp::Eq => "==",
+            CmpOp::Ne => "!=",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+            CmpOp::Contains => "contains",
+            CmpOp::StartsWith => "startsWith",
+            CmpOp::EndsWith => "endsWith",
+            CmpOp::Matches => "matches",
+        }
+    }
+
+    fn accepts(self, typ: Type) -> bool {
+        match self {
+            CmpOp::Eq | CmpOp::Ne => true,
+            CmpOp::Lt | CmpOp::Le | CmpOp::Gt | CmpOp::Ge => typ == Type::Num,
+            _ => typ == Type::Str,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operand {
+    Var(Var),
+    Num(f64),
+    Str(String),
+}
+
+impl Operand {
+    fn typ(&self) -> Type {
+        match self {
+            Operand::Var(var) => var.typ(),
+            Operand::Num(_) => Type::Num,
+            Operand::Str(_) => Type::Str,
+        }
+    }
+
+    fn eval<'a, T: FilterTarget>(&'a self, target: &'a T) -> Value<'a> {
+        match self {
+            Operand::Var(var) => target.value(*var),
+            Operand::Num(n) => Value::Num(*n),
+            Operand::Str(s) => Value::Str(s),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Bool(bool),
+    Cmp(Operand, CmpOp, Operand),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+/// A parsed, type checked worker filter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkerFilter {
+    expr: Expr,
+}
+
+impl WorkerFilter {
+    pub fn parse(input: &str) -> Result<WorkerFilter> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end: input.len(),
+        };
+        let expr = parser.parse_or()?;
+        match parser.peek() {
+            None => Ok(WorkerFilter { expr }),
+            Some((token, pos)) => UnexpectedToken {
+                expected: "end of filter",
+                found: token.to_string(),
+                pos: *pos,
+            }
+            .fail(),
+        }
+    }
+
+    /// Parse the filter of a job, `None` when `extra` is empty.
+    pub fn from_extra(extra: Option<&str>) -> Result<Option<WorkerFilter>> {
+        match extra.map(str::trim).filter(|e| !e.is_empty()) {
+            Some(expr) => WorkerFilter::parse(expr).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn matches<T: FilterTarget>(&self, target: &T) -> bool {
+        eval(&self.expr, target)
+    }
+}
+
+fn eval<T: FilterTarget>(expr: &Expr, target: &T) -> bool {
+    match expr {
+        Expr::Bool(b) => *b,
+        Expr::Not(e) => !eval(e, target),
+        Expr::And(l, r) => eval(l, target) && eval(r, target),
+        Expr::Or(l, r) => eval(l, target) || eval(r, target),
+        Expr::Cmp(l, op, r) => match (l.eval(target), r.eval(target)) {
+            (Value::Num(l), Value::Num(r)) => match op {
+                CmpOp::Eq => (l - r).abs() < f64::EPSILON,
+                CmpOp::Ne => (l - r).abs() >= f64::EPSILON,
+                CmpOp::Lt => l < r,
+                CmpOp::Le => l <= r,
+                CmpOp::Gt => l > r,
+                CmpOp::Ge => l >= r,
+                _ => false,
+            },
+            (Value::Str(l), Value::Str(r)) => match op {
+                CmpOp::Eq => l == r,
+                CmpOp::Ne => l != r,
+                CmpOp::Contains => l.contains(r),
+                CmpOp::StartsWith => l.starts_with(r),
+                CmpOp::EndsWith => l.ends_with(r),
+                CmpOp::Matches => glob_match(r, l),
+                _ => false,
+            },
+            // ruled out by the type check of the parser.
+            _ => false,
+        },
+    }
+}
+
+/// Match `text` against `pattern` where `*` matches any run of characters and `?` one.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let (p, t): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut pi, mut ti) = (0, 0);
+    // the last `*` seen and the text position it is matched up to.
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((star_pi, star_ti)) = star {
+            pi = star_pi + 1;
+            ti = star_ti + 1;
+            star = Some((star_pi, star_ti + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(f64),
+    Str(String),
+    Ident(String),
+    Op(CmpOp),
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Num(n) => write!(f, "{}", n),
+            Token::Str(s) => write!(f, "{:?}", s),
+            Token::Ident(s) => write!(f, "{}", s),
+            Token::Op(op) => write!(f, "{}", op.symbol()),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::Not => write!(f, "!"),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<(Token, usize)>> {
+    let chars: Vec<(usize, char)> = input.char_indices().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let (pos, ch) = chars[i];
+        let next = chars.get(i + 1).map(|(_, c)| *c);
+        let (token, len) = match (ch, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('(', _) => (Token::LParen, 1),
+            (')', _) => (Token::RParen, 1),
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('=', Some('=')) => (Token::Op(CmpOp::Eq), 2),
+            ('!', Some('=')) => (Token::Op(CmpOp::Ne), 2),
+            ('<', Some('=')) => (Token::Op(CmpOp::Le), 2),
+            ('>', Some('=')) => (Token::Op(CmpOp::Ge), 2),
+            ('!', _) => (Token::Not, 1),
+            ('<', _) => (Token::Op(CmpOp::Lt), 1),
+            ('>', _) => (Token::Op(CmpOp::Gt), 1),
+            ('"', _) | ('\'', _) => {
+                let mut s = String::new();
+                let mut j = i + 1;
+                loop {
+                    match chars.get(j) {
+                        None => return UnterminatedString { pos }.fail(),
+                        Some((_, '\\')) if j + 1 < chars.len() => {
+                            s.push(chars[j + 1].1);
+                            j += 2;
+                        }
+                        Some((_, c)) if *c == ch => break,
+                        Some((_, c)) => {
+                            s.push(*c);
+                            j += 1;
+                        }
+                    }
+                }
+                (Token::Str(s), j + 1 - i)
+            }
+            (c, _) if c.is_ascii_digit() || (c == '-' && next.map_or(false, |n| n.is_ascii_digit())) => {
+                let len = chars[i + 1..]
+                    .iter()
+                    .take_while(|(_, c)| c.is_ascii_digit() || *c == '.')
+                    .count()
+                    + 1;
+                let end = chars.get(i + len).map_or(input.len(), |(p, _)| *p);
+                match input[pos..end].parse() {
+                    Ok(n) => (Token::Num(n), len),
+                    Err(_) => {
+                        return UnexpectedToken {
+                            expected: "a number",
+                            found: input[pos..end].to_string(),
+                            pos,
+                        }
+                        .fail()
+                    }
+                }
+            }
+            (c, _) if c.is_alphabetic() || c == '_' => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|(_, c)| c.is_alphanumeric() || *c == '_')
+                    .count();
+                let end = chars.get(i + len).map_or(input.len(), |(p, _)| *p);
+                let token = match &input[pos..end] {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    "contains" => Token::Op(CmpOp::Contains),
+                    "startsWith" => Token::Op(CmpOp::StartsWith),
+                    "endsWith" => Token::Op(CmpOp::EndsWith),
+                    "matches" => Token::Op(CmpOp::Matches),
+                    ident => Token::Ident(ident.to_string()),
+                };
+                (token, len)
+            }
+            (ch, _) => return UnexpectedChar { ch, pos }.fail(),
+        };
+        tokens.push((token, pos));
+        i += len;
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    /// Reported as the position of a missing token.
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&(Token, usize)> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self, expected: &'static str) -> Result<(Token, usize)> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token.clone())
+            }
+            None => UnexpectedToken {
+                expected,
+                found: "end of filter",
+                pos: self.end,
+            }
+            .fail(),
+        }
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek().map_or(false, |(t, _)| t == token) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_and()?;
+        while self.eat(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_unary()?;
+        while self.eat(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        if self.eat(&Token::Not) {
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        if self.eat(&Token::LParen) {
+            let expr = self.parse_or()?;
+            return match self.next("')'")? {
+                (Token::RParen, _) => Ok(expr),
+                (token, pos) => UnexpectedToken {
+                    expected: "')'",
+                    found: token.to_string(),
+                    pos,
+                }
+                .fail(),
+            };
+        }
+        match self.peek() {
+            Some((Token::Ident(ident), _)) if ident == "true" || ident == "false" => {
+                let b = ident == "true";
+                self.pos += 1;
+                return Ok(Expr::Bool(b));
+            }
+            _ => {}
+        }
+        self.parse_cmp()
+    }
+
+    fn parse_cmp(&mut self) -> Result<Expr> {
+        let left = self.parse_operand()?;
+        let (op, pos) = match self.next("a comparison operator")? {
+            (Token::Op(op), pos) => (op, pos),
+            (token, pos) => {
+                return UnexpectedToken {
+                    expected: "a comparison operator",
+                    found: token.to_string(),
+                    pos,
+                }
+                .fail()
+            }
+        };
+        let right = self.parse_operand()?;
+        let (lt, rt) = (left.typ(), right.typ());
+        if lt != rt || !op.accepts(lt) {
+            return TypeMismatch {
+                op: op.symbol(),
+                left: lt.name(),
+                right: rt.name(),
+                pos,
+            }
+            .fail();
+        }
+        Ok(Expr::Cmp(left, op, right))
+    }
+
+    fn parse_operand(&mut self) -> Result<Operand> {
+        match self.next("a value")? {
+            (Token::Num(n), _) => Ok(Operand::Num(n)),
+            (Token::Str(s), _) => Ok(Operand::Str(s)),
+            (Token::Ident(name), pos) => match Var::from_name(&name) {
+                Some(var) => Ok(Operand::Var(var)),
+                None => UnknownVariable { name, pos }.fail(),
+            },
+            (token, pos) => UnexpectedToken {
+                expected: "a value",
+                found: token.to_string(),
+                pos,
+            }
+            .fail(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Target {
+        tag: &'static str,
+        cpu_load: f64,
+    }
+
+    impl FilterTarget for Target {
+        fn value(&self, var: Var) -> Value<'_> {
+            match var {
+                Var::Address => Value::Str("10.0.0.1:7700"),
+                Var::Tag => Value::Str(self.tag),
+                Var::CpuLoad => Value::Num(self.cpu_load),
+                _ => Value::Num(0.0),
+            }
+        }
+    }
+
+    #[test]
+    fn t_filter_matches() {
+        let gpu = Target { tag: "gpu-a100", cpu_load: 0.5 };
+        let cpu = Target { tag: "cpu", cpu_load: 0.9 };
+        let cases = vec![
+            ("cpuLoad < 0.8", true, false),
+            ("tag startsWith \"gpu-\" and cpuLoad <= 0.5", true, false),
+            ("!(tag == 'cpu') || cpuLoad > 1", true, false),
+            ("tag matches 'g?u-*0' or address endsWith \":7700\"", true, true),
+            ("not tag contains \"gpu\" && true", false, true),
+            ("cpuLoad >= -1 && cpuLoad != 0.9", true, false),
+        ];
+        for (expr, on_gpu, on_cpu) in cases {
+            let filter = WorkerFilter::parse(expr).unwrap();
+            assert_eq!(filter.matches(&gpu), on_gpu, "{}", expr);
+            assert_eq!(filter.matches(&cpu), on_cpu, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn t_filter_rejects_invalid() {
+        for expr in vec![
+            "GPUUsage < 10",
+            "tag < 3",
+            "cpuLoad contains 'a'",
+            "cpuLoad <",
+            "(cpuLoad < 1",
+            "cpuLoad < 1 cpuLoad",
+            "tag == 'gpu",
+            "cpuLoad # 1",
+            "cpuLoad",
+        ] {
+            assert!(WorkerFilter::parse(expr).is_err(), "{}", expr);
+        }
+        assert_eq!(WorkerFilter::from_extra(Some("  ")).unwrap(), None);
+    }
+
+    #[test]
+    fn t_glob_match() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*c", "abbbc"));
+        assert!(glob_match("a?c*", "abcdef"));
+        assert!(!glob_match("a*c", "abcd"));
+        assert!(!glob_match("?", ""));
+    }
+}
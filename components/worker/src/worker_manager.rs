@@ -5,23 +5,34 @@
 //! so client will retry this request that send to another server util success unless achieved
 //! the maximum retry numbers and send has failed.
 use super::{error, Result};
+use crate::app_leases::AppLeases;
 use crate::dispatch::Dispatch;
+use crate::drain::{DrainState, Drainer};
 use crate::event::event_handler::EventHandler;
-use crate::{init_grpc_client, Worker, WorkerClusterHolder};
+use crate::rebalancer::{Rebalancer, DEFAULT_MAX_MOVES_PER_ROUND};
+use crate::broadcast::broadcast_outcome;
+use crate::server_registry::ServerRegistry;
+use crate::worker_filter::WorkerFilter;
+use crate::{Worker, WorkerClusterHolder};
 use chrono::Local;
 use dashmap::DashMap;
+use fastjob_components_scheduler::mapreduce::{MapReduceEngine, MapReduceStats, Step};
+use fastjob_components_scheduler::calendar::{self, Calendar};
 use fastjob_components_scheduler::{Scheduler, SCHEDULE_INTERVAL};
 use fastjob_components_storage::model::instance_info::{
     InstanceInfo, InstanceStatus, InstanceType,
 };
-use fastjob_components_storage::model::job_info::JobTimeExpressionType;
-use fastjob_components_storage::model::{app_info::AppInfo, job_info::JobInfo, lock::Lock};
+use fastjob_components_storage::model::job_info::{ExecuteType, JobTimeExpressionType};
+use fastjob_components_storage::model::calendar_info::CalendarInfo;
+use fastjob_components_storage::model::task_info::TaskInfo;
+use fastjob_components_storage::model::{app_info::AppInfo, job_info::JobInfo};
 use fastjob_components_storage::{BatisError, Storage};
 use fastjob_components_utils::event::{CompletedInstance, Event};
 use fastjob_components_utils::grpc_returns::GrpcReturn;
 use fastjob_components_utils::sched_pool::{JobHandle, SchedPool};
+use fastjob_components_utils::id_generator::{self, GeneratorTyp};
 use fastjob_proto::fastjob::*;
-use snafu::ResultExt;
+use snafu::{OptionExt, ResultExt};
 use std::cell::RefCell;
 use std::convert::TryFrom;
 use std::fmt::{Debug, Display, Formatter};
@@ -44,10 +55,15 @@ const WORKFLOW_WAITING_TIMEOUT_MS: u64 = 60000;
 
 pub struct WorkerManager<S: Storage> {
     id: i64,
-    address: &'static str,
+    address: String,
     sched_pool: SchedPool,
     storage: Arc<S>,
+    app_leases: AppLeases<S>,
+    registry: ServerRegistry<S>,
+    rebalancer: Rebalancer<S>,
+    drain: DrainState,
     workers: RefCell<DashMap<u64, WorkerClusterHolder>>,
+    mapreduce: MapReduceEngine<S>,
     scheduler: Scheduler<S>,
     event_handler: EventHandler,
     sender: Sender<Event>,
@@ -68,6 +84,8 @@ impl<S: Storage> Debug for WorkerManager<S> {
 
 pub struct WorkerManagerBuilder<S: Storage> {
     id: i64,
+    address: String,
+    max_rebalance_moves: usize,
     config: WorkerManagerConfig,
     storage: Arc<S>,
 }
@@ -76,6 +94,8 @@ impl<S: Storage> WorkerManagerBuilder<S> {
     pub fn builder(config: WorkerManagerConfig, storage: S) -> Self {
         Self {
             id: 0,
+            address: String::new(),
+            max_rebalance_moves: DEFAULT_MAX_MOVES_PER_ROUND,
             config,
             storage: Arc::new(storage),
         }
@@ -86,33 +106,92 @@ impl<S: Storage> WorkerManagerBuilder<S> {
         self
     }
 
-    pub fn build(self) -> WorkerManager<S> {
+    /// The address this server is reachable at, registered in `server_info`.
+    pub fn address(mut self, address: impl Into<String>) -> Self {
+        self.address = address.into();
+        self
+    }
+
+    /// The maximum number of apps the rebalancer reassigns per round.
+    pub fn max_rebalance_moves(mut self, max_rebalance_moves: usize) -> Self {
+        self.max_rebalance_moves = max_rebalance_moves;
+        self
+    }
+
+    /// Fails without an address, other servers couldn't reach this one nor tell it apart.
+    pub fn build(self) -> Result<WorkerManager<S>> {
+        if self.address.is_empty() {
+            return error::MissingAddress.fail();
+        }
         let (tx, rx) = channel(1024);
         let (sched_tx, sched_rx) = channel(1024);
         let workers = RefCell::new(DashMap::default());
-        WorkerManager {
+        let registry = ServerRegistry::new(self.address.clone(), self.storage.clone());
+        let app_leases = AppLeases::new(self.address.clone(), self.storage.clone());
+        let drain = DrainState::default();
+        Ok(WorkerManager {
+            // generated once the worker id is leased unless given, see `start`.
             id: self.id,
-            address: "",
+            address: self.address.clone(),
             sched_pool: SchedPool::new(
                 WORKER_MANAGER_SCHED_POOL_NUM_SIZE,
                 WORKER_MANAGER_SCHED_POOL_NAME,
             ),
-            storage: self.storage,
+            app_leases: app_leases.clone(),
+            registry: registry.clone(),
+            rebalancer: Rebalancer::new(
+                self.address.clone(),
+                self.storage.clone(),
+                registry,
+                app_leases,
+                self.max_rebalance_moves,
+                drain.clone(),
+            ),
             workers,
+            mapreduce: MapReduceEngine::new(self.storage.clone()),
             scheduler: Scheduler::new(self.storage.clone(), sched_tx.clone()),
             event_handler: EventHandler::new(rx, tx.clone()),
             sender: tx,
-            dispatch: Dispatch::new(sched_rx, self.storage.clone(), workers.clone()),
-        }
+            dispatch: Dispatch::new(
+                sched_rx,
+                self.storage.clone(),
+                workers.clone(),
+                drain.clone(),
+            ),
+            drain,
+            storage: self.storage,
+        })
     }
 }
 
 /// used for grpc service.
 impl<S: Storage> WorkerManager<S> {
-    async fn start(&mut self) {
+    async fn start(&mut self)
+        where
+            S: Send + Sync + 'static,
+    {
         // First Start dispatch.
         self.dispatch.event_loop().await;
 
+        // Ids are unique across the cluster once this server leased a worker id, the registry
+        // installs it in the id generator, no id is generated before.
+        match self.registry.worker_id() {
+            Ok(_) if self.id == 0 => {
+                match id_generator::generator_id(GeneratorTyp::WorkerManager) {
+                    Ok(id) => self.id = id as i64,
+                    Err(e) => error!("[WorkerManager] generate the worker manager id failed: {}", e),
+                }
+            }
+            Ok(_) => {}
+            Err(e) => error!("[WorkerManager] lease the id generator worker id failed: {}", e),
+        }
+
+        // Register itself and keep heartbeat.
+        self.registry.start(&self.sched_pool);
+
+        // Start rebalance thread, move apps off dead servers and onto joined ones.
+        self.rebalancer.start(&self.sched_pool);
+
         // Start scheduler thread.
         self.sched_pool.schedule_at_fixed_rate(
             self.scheduler(),
@@ -139,6 +218,23 @@ impl<S: Storage> WorkerManager<S> {
         self.scheduler.shutdown();
     }
 
+    /// The shutdown hook that drains this server, see `Drainer`.
+    pub fn drainer(&self) -> Drainer<S> {
+        Drainer::new(
+            self.address.clone(),
+            self.storage.clone(),
+            self.registry.clone(),
+            self.rebalancer.clone(),
+            self.app_leases.clone(),
+            self.drain.clone(),
+        )
+    }
+
+    /// Addresses of the servers in the cluster whose heartbeat is fresh.
+    pub fn live_servers(&self) -> Vec<String> {
+        self.registry.live_servers()
+    }
+
     /// Connect to worker grpc client.
     pub fn connect(&self, addr: u64) -> Result<()> {
         self.workers.entry(addr).or_insert_with(Worker::new())?;
@@ -159,60 +255,168 @@ impl<S: Storage> WorkerManager<S> {
         })
     }
 
+    /// Save a new job or update an existing one, a job whose worker filter in `extra`
+    /// doesn't parse, whose cron expression is invalid in its time zone and calendar or
+    /// whose one-shot fire time is invalid or past is rejected, see `Scheduler::prepare_job`.
+    pub fn save_job_info(&self, mut job_info: JobInfo) -> Result<()> {
+        let extra = job_info.extra.clone().unwrap_or_default();
+        WorkerFilter::from_extra(Some(&extra)).context(error::InvalidWorkerFilter { expr: extra })?;
+        self.scheduler
+            .prepare_job(&mut job_info)
+            .context(error::SchedulerFailed)?;
+
+        let now = Local::now().timestamp_millis();
+        job_info.gmt_modified = Some(now as u64);
+        let rs = if job_info.id.is_some() {
+            self.storage.update(&mut [job_info])
+        } else {
+            job_info.gmt_create = job_info.gmt_modified;
+            self.storage.save(job_info)
+        };
+        rs.context(error::WorkerStorageError)
+    }
+
+    /// Run the job now or after `delay` ms with `instance_params`, returns the id of the
+    /// instance. A retried request with the same `request_key` creates no second instance.
+    pub fn run_job(
+        &self,
+        job_id: u64,
+        instance_params: Option<String>,
+        delay: i64,
+        request_key: Option<&str>,
+    ) -> Result<u64> {
+        let job_info = self
+            .storage
+            .find_job_info_by_id(job_id)
+            .context(error::WorkerStorageError)?
+            .context(error::JobNotFound { job_id })?;
+        self.scheduler
+            .run_job(&job_info, instance_params, delay, request_key)
+            .context(error::SchedulerFailed)
+    }
+
+    /// The next `count` fire times of the cron expression of a job in its time zone and
+    /// calendar, so the next runs of a job can be shown before it is saved.
+    pub fn preview_cron(&self, job_info: &JobInfo, count: usize) -> Result<Vec<i64>> {
+        let now = Local::now().timestamp_millis();
+        let trigger = self
+            .scheduler
+            .cron_trigger(job_info)
+            .context(error::SchedulerFailed)?;
+        trigger.first_after(now).context(error::SchedulerFailed)?;
+        Ok(trigger.preview(now, count))
+    }
+
+    /// Save a new calendar or update an existing one, a calendar whose working days or
+    /// holidays don't parse is rejected.
+    pub fn save_calendar(&self, mut calendar: CalendarInfo) -> Result<()> {
+        Calendar::from_info(&calendar).context(error::SchedulerFailed)?;
+        calendar.gmt_modified = Some(Local::now().timestamp_millis() as u64);
+        let rs = if calendar.id.is_some() {
+            self.storage.update(&mut [calendar])
+        } else {
+            calendar.gmt_create = calendar.gmt_modified;
+            self.storage.save(calendar)
+        };
+        rs.context(error::WorkerStorageError)
+    }
+
+    /// Add the days covered by the events of an iCalendar file to the holidays of the app's
+    /// calendar, which is created with the default working days if it doesn't exist.
+    pub fn import_calendar(&self, app_id: u64, calendar_name: &str, ics: &str) -> Result<()> {
+        let imported = calendar::parse_ics(ics).context(error::SchedulerFailed)?;
+        let mut calendar = self
+            .storage
+            .find_calendar_by_name(app_id, calendar_name)
+            .context(error::WorkerStorageError)?
+            .unwrap_or_else(|| CalendarInfo {
+                app_id: Some(app_id),
+                calendar_name: Some(calendar_name.to_string()),
+                ..Default::default()
+            });
+        let mut holidays =
+            calendar::parse_holidays(calendar.holidays.as_deref().unwrap_or_default())
+                .context(error::SchedulerFailed)?;
+        holidays.extend(imported);
+        calendar.holidays = Some(calendar::format_holidays(&holidays));
+        self.save_calendar(calendar)
+    }
+
     /// Select the appropriate server according to the appName sent by the worker
     /// And check it whether alive,if dead the current service tries to usurp the throne.
     ///
-    /// Thread Safety: Distributed-Lock.
-    pub fn lookup(&self, current_server: &str, app_id: &str) -> Result<&str> {
+    /// Thread Safety: Distributed-Lock, the election result is written with the fencing token
+    /// of the app's lease so a server whose lease expired can't overwrite a newer election.
+    /// The winner keeps the lease for as long as it schedules the app, see `AppLeases`.
+    pub fn lookup(&self, current_server: &str, app_id: u64) -> Result<String> {
         let cache = &vec![];
         if self.address.eq(current_server) {
-            return Ok(current_server);
+            return Ok(current_server.to_string());
         }
-        let wrapper = &self.storage.get_wrapper().eq("id", app_id);
         for _ in 0..RETRY_TIMES {
-            let rs: Option<AppInfo> = self
-                .storage
-                .fetch(wrapper)
-                .context(error::WorkerStorageError)?;
-
-            if rs.is_none() {
-                return Err(error::WorkerNotRegistered {
-                    app_name_or_id: app_id,
-                });
-            }
-            let name = rs.as_ref().unwrap().app_name.unwrap();
-            let origin_server = rs.as_ref().unwrap().current_server.unwrap().as_str();
-            if self.is_active(origin_server, cache) {
+            let origin_server = self.find_current_server(app_id)?;
+            if self.is_active(&origin_server, cache) {
                 return Ok(origin_server);
             }
 
             // Server is not available, try server election again, need to lock.
-            let lock = Lock::new(app_id, 30000, current_server);
-            if !self.lock(lock).is_err() {
-                std::thread::sleep(Duration::from_millis(500));
-            }
+            let lease = match self
+                .app_leases
+                .try_acquire(app_id)
+                .context(error::WorkerStorageError)?
+            {
+                Some(lease) => lease,
+                None => {
+                    std::thread::sleep(Duration::from_millis(500));
+                    continue;
+                }
+            };
 
             // It is possible that a machine has already completed the Server election and needs to be judged again.
-            let mut rs: Option<AppInfo> = self
-                .storage
-                .fetch(wrapper)
-                .context(error::WorkerStorageError)?;
-            let cur = rs.as_ref().unwrap().current_server.unwrap().as_str();
-            if self.is_active(cur, cache) {
+            let cur = self.find_current_server(app_id)?;
+            if self.is_active(&cur, cache) {
+                self.app_leases.release(app_id, lease);
                 return Ok(cur);
             }
             // Usurpation, native as current server.
-            rs.take().unwrap().current_server = Some(current_server);
-            rs.take().unwrap().gmt_modified = Some(Local::now().timestamp_millis());
-            self.storage.save(rs.unwrap());
-            info!(
-                "[Election] server {} become the new server fo appId {}",
-                current_server, app_id
-            )
+            let elected = self
+                .storage
+                .update_app_current_server(app_id, &self.address, lease.fencing_token());
+            match elected {
+                Ok(true) => {
+                    self.app_leases.hold(app_id, lease);
+                    info!(
+                        "[Election] server {} become the new server fo appId {}",
+                        self.address, app_id
+                    );
+                    return Ok(self.address.clone());
+                }
+                // a server holding a newer lease won the election meanwhile.
+                Ok(false) => self.app_leases.release(app_id, lease),
+                Err(e) => {
+                    self.app_leases.release(app_id, lease);
+                    return Err(e).context(error::WorkerStorageError);
+                }
+            }
+        }
+        error::LookupFail {
+            server_ip: self.address.as_str(),
+        }
+        .fail()
+    }
+
+    fn find_current_server(&self, app_id: u64) -> Result<String> {
+        let app: Option<AppInfo> = self
+            .storage
+            .find_app_info_by_id(app_id)
+            .context(error::WorkerStorageError)?;
+        match app {
+            Some(app) => Ok(app.current_server.unwrap_or_default()),
+            None => error::WorkerNotRegistered {
+                app_name_or_id: app_id.to_string(),
+            }
+            .fail(),
         }
-        Err(error::LookupFail {
-            server_ip: self.address.clone(),
-        })
     }
 
     /// Immediately execute a schedule.
@@ -244,7 +448,7 @@ impl<S: Storage> WorkerManager<S> {
     ) -> Result<Option<GrpcReturn>> {
         // handle related workflow.
         if req.get_wfInstanceId() > 0 && req.get_workflowContext() {
-            // workerManager.updateWorkflowContext(&req);
+            self.update_workflow_context(req);
         }
 
         self.update_status(&req).await?;
@@ -256,6 +460,127 @@ impl<S: Storage> WorkerManager<S> {
         Ok(GrpcReturn::empty())
     }
 
+    /// Save the map tasks, `(name, params)`, a worker emitted from a task of a MapReduce
+    /// instance and send the pending tasks with `send`, see `MapReduceEngine::dispatch_pending`.
+    pub fn map_task<F>(
+        &self,
+        instance_id: u64,
+        task_id: &str,
+        children: Vec<(String, String)>,
+        send: F,
+    ) -> Result<()>
+        where
+            F: FnMut(&str, &TaskInfo, &[TaskInfo]) -> std::result::Result<(), String>,
+    {
+        let (job_info, instance_info) = match self.find_job_and_instance(instance_id)? {
+            Some(found) => found,
+            None => return Ok(()),
+        };
+        self.mapreduce
+            .map(&instance_info, task_id, children)
+            .context(error::SchedulerFailed)?;
+        self.dispatch_map_tasks(&job_info, instance_id, send)?;
+        Ok(())
+    }
+
+    /// Handle the status a worker reported for a task of a MapReduce instance, the pending
+    /// tasks are sent with `send` unless the instance finished.
+    pub async fn report_task_status<F>(
+        &self,
+        instance_id: u64,
+        task_id: &str,
+        status: InstanceStatus,
+        result: &str,
+        send: F,
+    ) -> Result<()>
+        where
+            F: FnMut(&str, &TaskInfo, &[TaskInfo]) -> std::result::Result<(), String>,
+    {
+        let (job_info, mut instance_info) = match self.find_job_and_instance(instance_id)? {
+            Some(found) => found,
+            None => return Ok(()),
+        };
+        let step = self
+            .mapreduce
+            .report(&job_info, &instance_info, task_id, status, result)
+            .context(error::SchedulerFailed)?;
+        match step {
+            // only the root failing or the reduce finishing decides the instance.
+            Step::Finished(status) => {
+                self.finish_instance(instance_info, status, result.to_string())
+                    .await?;
+            }
+            Step::Wait | Step::Reduce => {
+                let stats = self
+                    .mapreduce
+                    .stats(instance_id)
+                    .context(error::SchedulerFailed)?;
+                instance_info.status = Some(InstanceStatus::Running.into());
+                instance_info.result = Some(stats.to_string());
+                instance_info.last_report_time = Some(Local::now().timestamp_millis());
+                self.storage
+                    .update(&mut [instance_info])
+                    .context(error::WorkerStorageError)?;
+                self.dispatch_map_tasks(&job_info, instance_id, send)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the failed nodes of a failed or stopped workflow instance again.
+    pub fn retry_workflow_instance(&self, wf_instance_id: u64) -> Result<()> {
+        self.scheduler
+            .retry_workflow_instance(wf_instance_id)
+            .context(error::SchedulerFailed)
+    }
+
+    /// Mark a failed node of a workflow instance as succeeded to unblock its downstream nodes.
+    pub fn mark_workflow_node_success(&self, wf_instance_id: u64, node_id: u64) -> Result<()> {
+        self.scheduler
+            .mark_workflow_node_success(wf_instance_id, node_id)
+            .context(error::SchedulerFailed)
+    }
+
+    /// Stop a workflow instance, its unfinished node instances are stopped as well and the
+    /// reports of their workers are dropped from then on. Each of their task trackers is told
+    /// to stop with `stop`, a tracker that can't be reached only logs the failure.
+    pub fn stop_workflow_instance<F>(&self, wf_instance_id: u64, mut stop: F) -> Result<()>
+        where
+            F: FnMut(&str, &InstanceInfo) -> std::result::Result<(), String>,
+    {
+        let stopped = self
+            .scheduler
+            .stop_workflow_instance(wf_instance_id)
+            .context(error::SchedulerFailed)?;
+        info!("[WorkerManager] stop workflow instance {}, stopped node instances: {:?}.", wf_instance_id, stopped);
+        for instance_id in stopped {
+            let instance_info = match self
+                .storage
+                .find_instance_by_id(instance_id)
+                .context(error::WorkerStorageError)?
+            {
+                Some(instance_info) => instance_info,
+                None => continue,
+            };
+            // not dispatched yet, so no worker runs it.
+            let address = match instance_info.task_tracker_address.as_deref() {
+                Some(address) if !address.is_empty() => address,
+                _ => continue,
+            };
+            if let Err(e) = stop(address, &instance_info) {
+                warn!("[WorkerManager] stop instance {} on {} failed: {}", instance_id, address, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// How many map tasks of a MapReduce instance are waiting, running, succeeded or failed.
+    pub fn mapreduce_stats(&self, instance_id: u64) -> Result<MapReduceStats> {
+        self.mapreduce
+            .stats(instance_id)
+            .context(error::SchedulerFailed)
+    }
+
     /// Handle the deploy contain request.
     pub async fn handle_deploy_container(
         &self,
@@ -310,6 +635,11 @@ impl<S: Storage> WorkerManager<S> {
                     return Ok(());
                 }
 
+                // every worker of a broadcast reports its own sub-task.
+                if job_info.get_execute_type() == ExecuteType::Broadcast {
+                    return self.update_broadcast_status(req, &job_info, instance_info).await;
+                }
+
                 // drop the reported data of non-target worker (split brain issues).
                 if req.get_sourceAdrdress != instance_info.task_tracker_address.unwrap() {
                     warn!("[WorkerManager instance status] receive the other Worker report: {}, but current Worker is {}, this report will be dropped",
@@ -318,9 +648,23 @@ impl<S: Storage> WorkerManager<S> {
                     return Ok(());
                 }
 
+                // a stopped instance, e.g. a node of a stopped workflow, stays stopped.
+                if instance_info.status == Some(InstanceStatus::Stopped.into()) {
+                    warn!("[WorkerManager instance status] instance {} is stopped, this report will be dropped", instance_id);
+                    return Ok(());
+                }
+
                 let status = InstanceStatus::try_from(req.get_instanceStatus())?;
                 instance_info.last_report_time = Some(req.get_reportTime());
 
+                // the tasks decide how a MapReduce instance finishes, see `report_task_status`.
+                if job_info.get_execute_type() == ExecuteType::MapReduce
+                    && matches!(status, InstanceStatus::Success | InstanceStatus::Failed)
+                {
+                    debug!("[WorkerManager instance status] instance {} is MapReduce, its finished status is decided by its tasks.", instance_id);
+                    return Ok(());
+                }
+
                 // Frequent task don't have failure to retry, so keep running and sync the survival msg to db.
                 // Frequent task only has two cases:
                 // 1. Running
@@ -329,7 +673,7 @@ impl<S: Storage> WorkerManager<S> {
                     .is_frequent()
                 {
                     instance_info.status = Some(req.get_instanceStatus());
-                    instance_info.result = Some(req.get_result());
+                    instance_info.result = Some(req.get_result().to_string());
                     instance_info.running_times = Some(req.get_result());
                     self.storage.update(&mut instance_info);
                     return Ok(());
@@ -344,7 +688,7 @@ impl<S: Storage> WorkerManager<S> {
 
                 let finished = match status {
                     InstanceStatus::Success => {
-                        instance_info.result = Some(req.get_result());
+                        instance_info.result = Some(req.get_result().to_string());
                         instance_info.finished_time = Some(chrono::Local::now().timestamp_millis());
                         true
                     }
@@ -366,7 +710,7 @@ impl<S: Storage> WorkerManager<S> {
                             false
                         } else {
                             // exceed instance max retry num.
-                            instance_info.result = Some(req.get_result());
+                            instance_info.result = Some(req.get_result().to_string());
                             instance_info.finished_time =
                                 Some(chrono::Local::now().timestamp_millis());
                             warn!("[WorkerManager instance status] instance id: {} exceeded the maximum retry num that can't retry", req.get_instanceId());
@@ -379,12 +723,13 @@ impl<S: Storage> WorkerManager<S> {
                 self.storage.update(&mut instance_info);
 
                 if finished {
+                    self.notify_workflow(&instance_info, status, req.get_result());
                     self.sender
                         .send(Event::InstanceCompletedEvent(CompletedInstance {
                             instance_id: req.get_instanceId(),
                             wf_instance_id: req.get_wfInstanceId(),
                             status: req.get_instanceStatus(),
-                            result: req.get_result(),
+                            result: req.get_result().to_string(),
                         }));
                 }
             } else {
@@ -398,25 +743,174 @@ impl<S: Storage> WorkerManager<S> {
         Ok(())
     }
 
-    fn is_active(&self, target_server: &str, cache: &[&str]) -> bool {
-        if cache.contains(&target_server) {
-            return false;
+    /// Record a worker's sub-task status and finish the broadcast instance once its outcome
+    /// is decided.
+    async fn update_broadcast_status(
+        &self,
+        req: &ReportInstanceStatusRequest,
+        job_info: &JobInfo,
+        mut instance_info: InstanceInfo,
+    ) -> Result<()> {
+        let instance_id = req.get_instanceId();
+        let status = InstanceStatus::try_from(req.get_instanceStatus())?;
+        let now = Local::now().timestamp_millis();
+        if !self
+            .storage
+            .update_task_status(
+                instance_id,
+                req.get_sourceAddress(),
+                status.into(),
+                req.get_result(),
+                now,
+            )
+            .context(error::WorkerStorageError)?
+        {
+            warn!("[WorkerManager instance status] worker {} has no sub-task of broadcast instance {}, this report will be dropped",
+                  req.get_sourceAddress(),
+                  instance_id);
+            return Ok(());
         }
-        // send hello request to target server.
-        let client = init_grpc_client(target_server);
-        let mut req = Ping::default();
-        let reply = client.ping(&req).expect("Ping failed");
-        if reply.get_code() == 200 {
-            return true;
+        // late reports of a decided broadcast only update their sub-task.
+        let current = instance_info.status.and_then(|s| InstanceStatus::try_from(s).ok());
+        if !matches!(
+            current,
+            Some(InstanceStatus::WaitingWorkerReceive) | Some(InstanceStatus::Running)
+        ) {
+            return Ok(());
         }
-        false
+
+        let tasks = self
+            .storage
+            .find_tasks_by_instance_id(instance_id)
+            .context(error::WorkerStorageError)?;
+        let statuses: Vec<InstanceStatus> = tasks
+            .iter()
+            .filter_map(|task| task.status.and_then(|s| InstanceStatus::try_from(s).ok()))
+            .collect();
+        instance_info.last_report_time = Some(req.get_reportTime());
+        match broadcast_outcome(&statuses, job_info.get_success_ratio()) {
+            Some(outcome) => {
+                let succeeded = statuses
+                    .iter()
+                    .filter(|s| **s == InstanceStatus::Success)
+                    .count();
+                let result = format!("{}/{} sub-tasks succeeded", succeeded, statuses.len());
+                self.finish_instance(instance_info, outcome, result).await?;
+            }
+            None => {
+                instance_info.status = Some(InstanceStatus::Running.into());
+                self.storage
+                    .update(&mut [instance_info])
+                    .context(error::WorkerStorageError)?;
+            }
+        }
+        Ok(())
     }
 
-    fn lock(&self, lock: Lock) -> Result<()> {
-        self.storage.save(lock).context(error::WorkerStorageError)?;
+    /// Persist the final status of an instance and publish its completion.
+    async fn finish_instance(
+        &self,
+        mut instance_info: InstanceInfo,
+        status: InstanceStatus,
+        result: String,
+    ) -> Result<()> {
+        let instance_id = instance_info.instance_id.unwrap_or_default();
+        let wf_instance_id = instance_info.wf_instance_id.unwrap_or_default();
+        instance_info.status = Some(status.into());
+        instance_info.result = Some(result.clone());
+        instance_info.finished_time = Some(Local::now().timestamp_millis());
+        let mut rows = [instance_info];
+        self.storage
+            .update(&mut rows)
+            .context(error::WorkerStorageError)?;
+        self.notify_workflow(&rows[0], status, &result);
+        if let Err(e) = self
+            .sender
+            .send(Event::InstanceCompletedEvent(CompletedInstance {
+                instance_id,
+                wf_instance_id,
+                status: u32::from(status) as usize,
+                result,
+            }))
+            .await
+        {
+            warn!("[WorkerManager] send completed event of instance {} failed: {}", instance_id, e);
+        }
         Ok(())
     }
 
+    /// Append the key/values a node reported to its workflow context, before the status is
+    /// handled so the downstream nodes already receive them.
+    fn update_workflow_context(&self, req: &ReportInstanceStatusRequest) {
+        let wf_instance_id = req.get_wfInstanceId();
+        match self
+            .scheduler
+            .append_workflow_context(wf_instance_id, req.get_appendedWfContext())
+        {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!("[WorkerManager] the context reported by instance {} exceeds the limit of workflow instance {}.", req.get_instanceId(), wf_instance_id);
+            }
+            Err(e) => {
+                error!("[WorkerManager] update context of workflow instance {} failed: {}", wf_instance_id, e);
+            }
+        }
+    }
+
+    /// Advance the workflow of an instance that finished as a workflow node.
+    fn notify_workflow(&self, instance_info: &InstanceInfo, status: InstanceStatus, result: &str) {
+        if let Some(wf_instance_id) = instance_info.wf_instance_id {
+            let instance_id = instance_info.instance_id.unwrap_or_default();
+            if let Err(e) =
+                self.scheduler
+                    .workflow_node_finished(wf_instance_id, instance_id, status, result)
+            {
+                error!("[WorkerManager] advance workflow instance {} of instance {} failed: {}", wf_instance_id, instance_id, e);
+            }
+        }
+    }
+
+    fn find_job_and_instance(&self, instance_id: u64) -> Result<Option<(JobInfo, InstanceInfo)>> {
+        let job_info = self
+            .storage
+            .find_job_info_by_instance_id(instance_id)
+            .context(error::WorkerStorageError)?;
+        let instance_info = self
+            .storage
+            .find_instance_by_id(instance_id)
+            .context(error::WorkerStorageError)?;
+        match (job_info, instance_info) {
+            (Some(job_info), Some(instance_info)) => Ok(Some((job_info, instance_info))),
+            _ => {
+                warn!("[WorkerManager] can't find instance {} or its job.", instance_id);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Send the pending tasks of a MapReduce instance to the app's suitable workers with `send`.
+    fn dispatch_map_tasks<F>(&self, job_info: &JobInfo, instance_id: u64, send: F) -> Result<usize>
+        where
+            F: FnMut(&str, &TaskInfo, &[TaskInfo]) -> std::result::Result<(), String>,
+    {
+        let app_id = job_info.app_id.unwrap_or_default();
+        let suitable: Vec<Worker> = match self.workers.borrow().get_mut(&app_id) {
+            Some(mut holder) => holder.get_suitable_worker(job_info),
+            None => vec![],
+        };
+        let addresses: Vec<String> = suitable.iter().map(|w| w.address.to_string()).collect();
+        self.mapreduce
+            .dispatch_pending(job_info, instance_id, &addresses, send)
+            .context(error::SchedulerFailed)
+    }
+
+    fn is_active(&self, target_server: &str, cache: &[&str]) -> bool {
+        if cache.contains(&target_server) {
+            return false;
+        }
+        self.registry.is_alive(target_server)
+    }
+
     fn scheduler(&mut self) {
         match self.sched() {
             Ok(_) => {}
@@ -427,12 +921,16 @@ impl<S: Storage> WorkerManager<S> {
     }
 
     fn sched(&mut self) -> Result<()> {
+        if self.drain.is_draining() {
+            info!("[JobScheduler] server {} is draining, skip schedule.", self.address);
+            return Ok(());
+        }
         info!("Schedule task start.");
         let instant = Instant::now();
 
         let app_ids = self
             .storage
-            .find_all_app_id_by_current_server()
+            .find_all_app_id_by_current_server(&self.address)
             .context(error::WorkerStorageError)?;
 
         match app_ids {
@@ -440,24 +938,36 @@ impl<S: Storage> WorkerManager<S> {
                 info!("[JobScheduler] current server has no app's job to schedule.");
             }
             Some(ids) => {
-                self.clean_useless_worker(ids);
+                self.clean_useless_worker(&ids);
+
+                // only the apps whose lease this server holds are scheduled by it.
+                let ids = self
+                    .app_leases
+                    .refresh(&ids)
+                    .context(error::WorkerStorageError)?;
 
                 self.scheduler
-                    .schedule_cron_job(ids)
+                    .schedule_cron_job(&ids)
                     .context(error::SchedulerFailed)?;
                 let cron_cost = instant.elapsed();
 
                 self.scheduler
-                    .schedule_worker_flow(ids.clone())
+                    .schedule_worker_flow(&ids)
                     .context(error::SchedulerFailed)?;
                 let worker_flow_cost = instant.elapsed().sub(cron_cost);
 
                 self.scheduler
-                    .schedule_frequent_job(ids)
+                    .schedule_frequent_job(&ids)
                     .context(error::SchedulerFailed)?;
                 let frequent_cost = instant.elapsed().sub(worker_flow_cost + cron_cost);
 
-                info!("[JobScheduler] cron schedule cost: {}, workflow schedule cost: {}, frequent schedule: {}", cron_cost, worker_flow_cost, frequent_cost);
+                self.scheduler
+                    .schedule_one_shot_job(&ids)
+                    .context(error::SchedulerFailed)?;
+                let one_shot_cost =
+                    instant.elapsed().sub(frequent_cost + worker_flow_cost + cron_cost);
+
+                info!("[JobScheduler] cron schedule cost: {}, workflow schedule cost: {}, frequent schedule: {}, one-shot schedule cost: {}", cron_cost, worker_flow_cost, frequent_cost, one_shot_cost);
 
                 let total_cost = instant.elapsed().as_millis();
                 if total_cost > SCHEDULE_INTERVAL.as_millis() {
@@ -488,7 +998,7 @@ impl<S: Storage> WorkerManager<S> {
         let begin = Instant::now();
         let app_ids = self
             .storage
-            .find_all_app_id_by_current_server()
+            .find_all_app_id_by_current_server(&self.address)
             .context(error::WorkerStorageError)?;
         match app_ids {
             None => {
@@ -496,8 +1006,8 @@ impl<S: Storage> WorkerManager<S> {
                 return;
             }
             Some(ids) => {
-                self.check_instance(ids.clone()).await;
-                self.check_workflow(ids.clone()).await;
+                self.check_instance(&ids).await;
+                self.check_workflow(&ids).await;
             }
         }
         info!(
@@ -549,13 +1059,141 @@ impl<S: Storage> WorkerManager<S> {
 
     /// Check the status of workflow instance.
     async fn check_workflow(&self, ids: &[u64]) {
-        unreachable!()
-        // for chunk in ids.chunks(10) {}
+        if let Err(e) = self.scheduler.check_workflow(ids) {
+            error!("[InstanceStatusChecker] check workflow instances failed: {}", e);
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use fastjob_components_storage::model::workflow_instance_info::{
+        WorkflowInstanceInfo, WorkflowInstanceStatus,
+    };
+    use fastjob_components_storage::MemoryStorage;
+
+    fn worker_manager(storage: &MemoryStorage) -> WorkerManager<MemoryStorage> {
+        WorkerManagerBuilder::builder(WorkerManagerConfig::default(), storage.clone())
+            .id(1)
+            .address("127.0.0.1:3000")
+            .build()
+            .unwrap()
+    }
+
     #[test]
     fn t_sched() {}
+
+    #[test]
+    fn t_save_job_info_rejects_invalid_worker_filter() {
+        let storage = MemoryStorage::new();
+        let worker_manager = worker_manager(&storage);
+
+        let job_info = |extra: &str| JobInfo {
+            app_id: Some(1),
+            extra: Some(extra.to_string()),
+            ..Default::default()
+        };
+        let err = worker_manager
+            .save_job_info(job_info("cpuLoad <"))
+            .unwrap_err();
+        assert!(matches!(err, error::WorkerManagerError::InvalidWorkerFilter { .. }));
+        assert!(storage.find_job_info_by_id(1).unwrap().is_none());
+
+        worker_manager
+            .save_job_info(job_info("cpuLoad < 0.8"))
+            .unwrap();
+        assert!(storage.find_job_info_by_id(1).unwrap().is_some());
+    }
+
+    #[test]
+    fn t_save_job_info_rejects_invalid_cron() {
+        let storage = MemoryStorage::new();
+        let worker_manager = worker_manager(&storage);
+
+        let job_info = |expression: &str| JobInfo {
+            app_id: Some(1),
+            time_expression_type: Some(JobTimeExpressionType::CRON.into()),
+            time_expression: Some(expression.to_string()),
+            ..Default::default()
+        };
+        let err = worker_manager
+            .save_job_info(job_info("0 0 25 * * *"))
+            .unwrap_err();
+        assert!(matches!(err, error::WorkerManagerError::SchedulerFailed { .. }));
+        assert!(storage.find_job_info_by_id(1).unwrap().is_none());
+
+        worker_manager
+            .save_job_info(job_info("0 0 * * * *"))
+            .unwrap();
+        let saved = storage.find_job_info_by_id(1).unwrap().unwrap();
+        assert!(saved.next_trigger_time.is_some());
+    }
+
+    #[test]
+    fn t_lookup_then_hand_over() {
+        let storage = MemoryStorage::new();
+        let worker_manager = worker_manager(&storage);
+        storage
+            .save(AppInfo {
+                id: Some(1),
+                app_name: Some("app".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        let now = Local::now().timestamp_millis();
+        storage.upsert_server_heartbeat("127.0.0.1:3001", now).unwrap();
+
+        // elected with the app's lease, the hand over moves the app with that same lease.
+        assert_eq!(worker_manager.lookup("", 1).unwrap(), "127.0.0.1:3000");
+        worker_manager.registry.heartbeat().unwrap();
+        let moves = worker_manager.rebalancer.hand_over().unwrap();
+        assert_eq!(moves.len(), 1);
+        let app = storage.find_app_info_by_id(1).unwrap().unwrap();
+        assert_eq!(app.current_server.as_deref(), Some("127.0.0.1:3001"));
+    }
+
+    #[test]
+    fn t_stop_workflow_instance() {
+        let storage = MemoryStorage::new();
+        let worker_manager = worker_manager(&storage);
+        storage
+            .save(WorkflowInstanceInfo {
+                app_id: Some(1),
+                wf_instance_id: Some(1),
+                status: Some(WorkflowInstanceStatus::Running.into()),
+                dag: Some(
+                    r#"{"nodes":[{"nodeId":1,"instanceId":7,"status":3},
+                                 {"nodeId":2,"instanceId":8,"status":1}]}"#
+                        .to_string(),
+                ),
+                version: Some(0),
+                ..Default::default()
+            })
+            .unwrap();
+        // 7 runs on a worker, 8 waits for dispatch.
+        for (instance_id, address) in vec![(7, Some("127.0.0.1:4000")), (8, None)] {
+            storage
+                .save(InstanceInfo {
+                    job_id: Some(1),
+                    app_id: Some(1),
+                    instance_id: Some(instance_id),
+                    wf_instance_id: Some(1),
+                    task_tracker_address: address.map(str::to_string),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        let mut sent = vec![];
+        worker_manager
+            .stop_workflow_instance(1, |address, instance| {
+                sent.push((address.to_string(), instance.instance_id));
+                Err("unreachable".to_string())
+            })
+            .unwrap();
+        assert_eq!(sent, vec![("127.0.0.1:4000".to_string(), Some(7))]);
+        let instance = storage.find_instance_by_id(7).unwrap().unwrap();
+        assert_eq!(instance.status, Some(InstanceStatus::Stopped.into()));
+    }
 }
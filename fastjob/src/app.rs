@@ -1,8 +1,7 @@
 use super::Result;
 use crate::server;
 use crossbeam::channel::Sender;
-use fastjob_components_utils::id_generator::GeneratorTyp;
-use fastjob_components_utils::{id_generator, signal_handler};
+use fastjob_components_utils::signal_handler;
 use fastjob_components_worker::worker_manager;
 use fastjob_proto::fastjob::{
     WorkerManagerConfig, WorkerManagerScope, WorkerManagerScope::ServerSide,
@@ -22,10 +21,7 @@ pub struct App {
 impl Config {
     /// Only build all components equivalent to initialization, and will not start.
     pub fn build(self, shutdown_tx: Sender<()>) -> Result<App> {
-        let server = server::Server::build(
-            id_generator::generator_id(GeneratorTyp::Server),
-            &self.server,
-        );
+        let server = server::Server::build(&self.server)?;
 
         Ok(App { server })
     }
@@ -46,6 +42,10 @@ impl App {
 
         signal_handler::wait_for_signal();
 
+        server
+            .shutdown()
+            .unwrap_or_else(|e| tracing::error!("FastJob Server shutdown failure, cause: {}", e));
+
         // std::thread::Builder::new()
         //     .name("fastjob-server".into())
         //     .spawn(move || {
@@ -1,9 +1,12 @@
+use fastjob_components_storage::error::StorageError;
+use fastjob_components_worker::error::WorkerManagerError;
 use snafu::{ResultExt, Snafu};
 use std::fmt::{Display, Formatter};
 
 pub type Result<T, E = AppError> = std::result::Result<T, E>;
 
 #[derive(Snafu)]
+#[snafu(visibility = "pub(crate)")]
 enum AppError {
     #[snafu(display("Unable to read configuration from {}: {}", path.display(), source))]
     ReadConfiguration {
@@ -15,6 +18,10 @@ enum AppError {
         source: std::io::Error,
         path: std::path::PathBuf,
     },
+    #[snafu(display("Unable to initialize storage: {}", source))]
+    StorageInit { source: StorageError },
+    #[snafu(display("Unable to initialize worker manager: {}", source))]
+    WorkerManagerInit { source: WorkerManagerError },
 }
 
 impl Display for AppError {
@@ -1,7 +1,7 @@
 use fastjob::Result;
 use fastjob::{server, Config};
 use fastjob_components_log::{get_level_by_string, LogFormat};
-use fastjob_components_storage::StorageConfig;
+use fastjob_components_storage::{StorageConfig, StorageType};
 use fastjob_components_utils::signal;
 use fastjob_proto::fastjob::WorkerManagerConfig;
 use std::io::Error;
@@ -80,6 +80,7 @@ fn main() {
 
 pub fn overwrite_config_with_cmd_args(opt: Opt) -> Result<Config> {
     let config = StorageConfig {
+        storage_type: StorageType::Mysql,
         address: "localhost:3306".to_string(),
         username: "root".to_string(),
         password: "yaoyichen52".to_string(),
@@ -101,6 +102,7 @@ pub fn overwrite_config_with_cmd_args(opt: Opt) -> Result<Config> {
             log_rotation_timespan: Duration::from_secs(86400),
             storage_config: config,
             log_rotation_size: 300,
+            drain_timeout: Duration::from_secs(30),
         },
     })
 }
@@ -1,4 +1,5 @@
 use super::Result;
+use crate::error::{StorageInit, WorkerManagerInit};
 use crate::log::initial_logger;
 use crate::services::FastJobService;
 use crate::{cluster::Cluster, ListenAddr};
@@ -8,7 +9,10 @@ use fastjob_components_storage::{StorageBuilder, StorageConfig};
 use fastjob_components_utils::component::Component;
 use fastjob_components_utils::pair::PairCond;
 use fastjob_components_utils::{pair, Either};
+use fastjob_components_worker::drain::Drain;
 use fastjob_components_worker::worker_manager::WorkerManager;
+use fastjob_components_proto::admin_grpc::create_fast_job_admin;
+use fastjob_components_proto::task_grpc::create_fast_job_task;
 use fastjob_proto::fastjob_grpc::create_fast_job;
 use futures::prelude::*;
 use grpcio::{
@@ -17,6 +21,7 @@ use grpcio::{
 use grpcio_health::{create_health, HealthService, ServingStatus};
 use std::future::Future;
 use std::net::{IpAddr, SocketAddr, TcpListener};
+use snafu::ResultExt;
 use std::pin::Pin;
 use std::str::FromStr;
 use std::sync::{Arc, Condvar};
@@ -37,10 +42,11 @@ pub struct ServiceConfig {
     pub slow_log_threshold: Duration,
     pub log_rotation_timespan: Duration,
     pub log_rotation_size: u64,
+    /// How long a shutdown waits for in-flight dispatches before handing apps over.
+    pub drain_timeout: Duration,
 }
 
 pub struct Server {
-    id: u64,
     addr: SocketAddr,
     pub config: ServiceConfig,
     /// A GrpcServer build or a GrpcServer.
@@ -49,10 +55,12 @@ pub struct Server {
     builder_or_server: Option<Either<ServerBuilder, GrpcServer>>,
     health_service: HealthService,
     components: Vec<Box<dyn Component>>,
+    drains: Vec<Box<dyn Drain + Send>>,
 }
 
 impl Server {
-    pub fn build(id: u64, config: &ServiceConfig) -> Self {
+    /// Fails when the storage can't be initialized, e.g. its schema can't be migrated.
+    pub fn build(config: &ServiceConfig) -> Result<Self> {
         let addr = SocketAddr::from_str(&config.addr).unwrap();
 
         let health_service = HealthService::default();
@@ -65,12 +73,16 @@ impl Server {
         let pair = PairCond::new();
 
         // Constructor FastJob service.
-        let fastjob_service = FastJobService::new(tx, pair);
+        let fastjob_service =
+            FastJobService::new(config.addr.clone(), tx).context(WorkerManagerInit)?;
         fastjob_service.prepare();
+        let drains: Vec<Box<dyn Drain + Send>> = vec![Box::new(fastjob_service.drainer())];
 
         let builder = {
             let mut sb = ServerBuilder::new(Arc::clone(&env))
                 .channel_args(channel_args)
+                .register_service(create_fast_job_admin(fastjob_service.clone()))
+                .register_service(create_fast_job_task(fastjob_service.clone()))
                 .register_service(create_fast_job(fastjob_service))
                 .register_service(create_health(health_service.clone()));
             sb = sb.bind(format!("{}", &addr.ip()), addr.port());
@@ -80,7 +92,8 @@ impl Server {
         // Constructor Storage.
         let storage = StorageBuilder::builder()
             .config(config.storage_config.clone())
-            .build();
+            .build()
+            .context(StorageInit)?;
 
         // Constructor Cluster.
         let cluster = Cluster::new();
@@ -92,12 +105,12 @@ impl Server {
             vec![Box::new(storage), Box::new(cluster), Box::new(dispatcher)];
 
         let mut serve = Self {
-            id,
             addr,
             config: config.clone(),
             builder_or_server: Some(builder),
             health_service,
             components,
+            drains,
         };
 
         match serve.start() {
@@ -108,7 +121,7 @@ impl Server {
             }
         };
 
-        serve
+        Ok(serve)
     }
 
     /// start server, do as follows:
@@ -174,6 +187,11 @@ impl Server {
         Ok(())
     }
 
+    /// Gracefully stop the server, see `backend_shutdown`.
+    pub fn shutdown(&mut self) -> Result<()> {
+        self.backend_shutdown()
+    }
+
     /// Shutdown the serve when recv a shutdown api, it will do as follows:
     /// 1. update metadata that remove itself related information，pre-prevent client from registering task with this node again.
     /// 2. transfer task and related task metadata that belong itself, waiting for execute completed
@@ -181,6 +199,15 @@ impl Server {
     /// 3. transfer and storage the current node metadata, if this node just restart simply so directly loading it from disk
     ///    and try to stealing task from other nodes
     fn backend_shutdown(&self) -> Result<()> {
+        // 1. stop accepting registrations, clients move to the other servers.
+        self.health_service
+            .set_serving_status("", ServingStatus::NotServing);
+        // 2. wait for the in-flight dispatches then hand owned apps over to the live servers.
+        for drain in self.drains.iter() {
+            drain.drain(self.config.drain_timeout);
+        }
+        // 3. the metadata lives in storage, the handover above already persisted it.
+        info!("FastJob Server {} shutdown.", self.addr);
         Ok(())
     }
 }
@@ -1,15 +1,28 @@
 use crate::services::GRPC_RESPONSE_CODE;
 use crossbeam::channel::Sender;
+use fastjob_components_proto::admin::*;
+use fastjob_components_proto::admin_grpc::FastJobAdmin;
+use fastjob_components_proto::task::*;
+use fastjob_components_proto::task_grpc::FastJobTask;
+use fastjob_components_storage::model::calendar_info::CalendarInfo;
+use fastjob_components_storage::model::instance_info::InstanceStatus;
 use fastjob_components_storage::model::job_info::JobInfo;
 use fastjob_components_storage::model::task::Task;
 use fastjob_components_storage::Storage;
 use fastjob_components_utils::component::{Component, ComponentStatus};
+use fastjob_components_utils::grpc_returns::{FAIL, SUCCESS};
+use fastjob_components_worker::drain::Drainer;
+use fastjob_components_worker::error::WorkerManagerError;
+use fastjob_components_worker::{send_task, stop_instance};
 use fastjob_components_worker::worker_manager::{WorkerManager, WorkerManagerBuilder};
 use fastjob_proto::fastjob::*;
 use fastjob_proto::fastjob_grpc::FastJob;
+use futures::executor::block_on;
 use futures::prelude::*;
 use grpcio::{RpcContext, UnarySink};
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::Debug;
 use std::mem::MaybeUninit;
 use std::sync::Arc;
 
@@ -20,24 +33,44 @@ pub struct Service<S: Storage> {
     // workload  and server itself that are registered with the server,so the collection's key is server id
     work_mgr: WorkerManager<S>,
     storage: Arc<S>,
+    // The address this server is bound to, worker managers register themselves with it.
+    address: String,
 }
 
 impl<S: Storage> Service<S> {
-    pub fn new(sender: Sender<Vec<JobInfo>>) -> Self {
-        Self {
+    /// Fails when the worker manager can't be built, e.g. without an address.
+    pub fn new(
+        address: impl Into<String>,
+        sender: Sender<Vec<JobInfo>>,
+    ) -> Result<Self, WorkerManagerError> {
+        let address = address.into();
+        Ok(Self {
             work_mgr: WorkerManagerBuilder::builder(
                 req.get_workerManagerConfig().clone(),
                 sender,
             )
                 .id(req.get_workerManagerId())
+                .address(address.clone())
                 .scope(req.get_workerManagerScope())
-                .build(),
+                .build()?,
             storage: Arc::new(S),
-        }
+            address,
+        })
     }
 
     /// Prepare inner components.
     pub fn prepare(&self) {}
+
+    /// Create or update a job, the `SaveJob` RPC saves every job through here so an invalid
+    /// one is rejected before it is stored, see `WorkerManager::save_job_info`.
+    pub fn save_job(&self, job_info: JobInfo) -> Result<(), WorkerManagerError> {
+        self.work_mgr.save_job_info(job_info)
+    }
+
+    /// The shutdown hook of the worker manager.
+    pub fn drainer(&self) -> Drainer<S> {
+        self.work_mgr.drainer()
+    }
 }
 
 impl<S: Storage> FastJob for Service<S> {
@@ -63,18 +96,33 @@ impl<S: Storage> FastJob for Service<S> {
         let key = req.get_workerManagerId();
 
         if !self.work_mgrs.contains_key(&key) {
-            let mut worker_mgr = WorkerManagerBuilder::builder(
+            let worker_mgr = WorkerManagerBuilder::builder(
                 req.get_workerManagerConfig().clone(),
                 self.sender.clone(),
             )
                 .id(req.get_workerManagerId())
+                .address(self.address.clone())
                 .scope(req.get_workerManagerScope())
                 .build();
 
-            // Start worker manager.
-            // todo. `Result` needs to be added to determine whether the execution was successful.
-            worker_mgr.start();
-            self.work_mgrs.insert(key, worker_mgr);
+            match worker_mgr {
+                Ok(mut worker_mgr) => {
+                    // Start worker manager.
+                    // todo. `Result` needs to be added to determine whether the execution was successful.
+                    worker_mgr.start();
+                    self.work_mgrs.insert(key, worker_mgr);
+                }
+                Err(e) => {
+                    resp.set_message(e.to_string());
+                    resp.set_code(FAIL);
+                    let f = sink
+                        .success(resp)
+                        .map_err(move |e| format!("failed to reply {:?}: {:?}", req, e))
+                        .map(|_| ());
+                    ctx.spawn(f);
+                    return;
+                }
+            }
         }
 
         resp.set_message(msg);
@@ -225,6 +273,247 @@ impl<S: Storage> FastJob for Service<S> {
     }
 }
 
+impl<S: Storage> FastJobAdmin for Service<S> {
+    /// Create or update a job, see `Service::save_job`.
+    fn save_job(&mut self, ctx: RpcContext, req: SaveJobRequest, sink: UnarySink<AdminResponse>) {
+        debug!("receive save job request.");
+        let rs = serde_json::from_str::<JobInfo>(req.get_jobInfo())
+            .map_err(|e| format!("invalid job: {}", e))
+            .and_then(|job_info| Service::save_job(self, job_info).map_err(|e| e.to_string()))
+            .map(|_| AdminResponse::default());
+        reply(&ctx, sink, rs, req)
+    }
+
+    /// Run an API job, see `WorkerManager::run_job`.
+    fn run_job(&mut self, ctx: RpcContext, req: RunJobRequest, sink: UnarySink<RunJobResponse>) {
+        debug!("receive run job {} request.", req.get_jobId());
+        let instance_params = Some(req.get_instanceParams())
+            .filter(|params| !params.is_empty())
+            .map(str::to_string);
+        let rs = self
+            .work_mgr
+            .run_job(
+                req.get_jobId(),
+                instance_params,
+                req.get_delay(),
+                Some(req.get_requestKey()),
+            )
+            .map(|instance_id| {
+                let mut resp = RunJobResponse::default();
+                resp.set_instanceId(instance_id);
+                resp
+            })
+            .map_err(|e| e.to_string());
+        reply(&ctx, sink, rs, req)
+    }
+
+    /// The next fire times of a job's cron expression, see `WorkerManager::preview_cron`.
+    fn preview_cron(
+        &mut self,
+        ctx: RpcContext,
+        req: PreviewCronRequest,
+        sink: UnarySink<PreviewCronResponse>,
+    ) {
+        debug!("receive preview cron request.");
+        let rs = serde_json::from_str::<JobInfo>(req.get_jobInfo())
+            .map_err(|e| format!("invalid job: {}", e))
+            .and_then(|job_info| {
+                self.work_mgr
+                    .preview_cron(&job_info, req.get_count() as usize)
+                    .map_err(|e| e.to_string())
+            })
+            .map(|times| {
+                let mut resp = PreviewCronResponse::default();
+                resp.set_fireTimes(times);
+                resp
+            });
+        reply(&ctx, sink, rs, req)
+    }
+
+    /// Create or update a calendar, see `WorkerManager::save_calendar`.
+    fn save_calendar(
+        &mut self,
+        ctx: RpcContext,
+        req: SaveCalendarRequest,
+        sink: UnarySink<AdminResponse>,
+    ) {
+        debug!("receive save calendar request.");
+        let rs = serde_json::from_str::<CalendarInfo>(req.get_calendarInfo())
+            .map_err(|e| format!("invalid calendar: {}", e))
+            .and_then(|calendar| {
+                self.work_mgr
+                    .save_calendar(calendar)
+                    .map_err(|e| e.to_string())
+            })
+            .map(|_| AdminResponse::default());
+        reply(&ctx, sink, rs, req)
+    }
+
+    /// Import the holidays of an iCalendar file into a calendar of an app.
+    fn import_calendar(
+        &mut self,
+        ctx: RpcContext,
+        req: ImportCalendarRequest,
+        sink: UnarySink<AdminResponse>,
+    ) {
+        debug!(
+            "receive import calendar request, app id: {}, calendar: {}.",
+            req.get_appId(),
+            req.get_calendarName()
+        );
+        let rs = self
+            .work_mgr
+            .import_calendar(req.get_appId(), req.get_calendarName(), req.get_ics())
+            .map(|_| AdminResponse::default())
+            .map_err(|e| e.to_string());
+        reply(&ctx, sink, rs, req)
+    }
+
+    /// Run the failed nodes of a workflow instance again.
+    fn retry_workflow_instance(
+        &mut self,
+        ctx: RpcContext,
+        req: WorkflowInstanceRequest,
+        sink: UnarySink<AdminResponse>,
+    ) {
+        debug!("receive retry workflow instance {} request.", req.get_wfInstanceId());
+        let rs = self
+            .work_mgr
+            .retry_workflow_instance(req.get_wfInstanceId())
+            .map(|_| AdminResponse::default())
+            .map_err(|e| e.to_string());
+        reply(&ctx, sink, rs, req)
+    }
+
+    /// Mark a failed node of a workflow instance as succeeded.
+    fn mark_workflow_node_success(
+        &mut self,
+        ctx: RpcContext,
+        req: MarkWorkflowNodeSuccessRequest,
+        sink: UnarySink<AdminResponse>,
+    ) {
+        debug!(
+            "receive mark workflow node success request, workflow instance id: {}, node id: {}.",
+            req.get_wfInstanceId(),
+            req.get_nodeId()
+        );
+        let rs = self
+            .work_mgr
+            .mark_workflow_node_success(req.get_wfInstanceId(), req.get_nodeId())
+            .map(|_| AdminResponse::default())
+            .map_err(|e| e.to_string());
+        reply(&ctx, sink, rs, req)
+    }
+
+    /// Stop a workflow instance, the task trackers of its running nodes are told to stop.
+    fn stop_workflow_instance(
+        &mut self,
+        ctx: RpcContext,
+        req: WorkflowInstanceRequest,
+        sink: UnarySink<AdminResponse>,
+    ) {
+        debug!("receive stop workflow instance {} request.", req.get_wfInstanceId());
+        let rs = self
+            .work_mgr
+            .stop_workflow_instance(req.get_wfInstanceId(), stop_instance)
+            .map(|_| AdminResponse::default())
+            .map_err(|e| e.to_string());
+        reply(&ctx, sink, rs, req)
+    }
+}
+
+impl<S: Storage> FastJobTask for Service<S> {
+    /// Save the map tasks a task emitted and send the pending tasks to the workers.
+    fn map_task(&mut self, ctx: RpcContext, req: MapTaskRequest, sink: UnarySink<TaskResponse>) {
+        debug!(
+            "receive map task request, instance id: {}, task id: {}.",
+            req.get_instanceId(),
+            req.get_taskId()
+        );
+        let children = req
+            .get_children()
+            .iter()
+            .map(|child| (child.get_taskName().to_string(), child.get_taskParams().to_string()))
+            .collect();
+        let rs = self
+            .work_mgr
+            .map_task(req.get_instanceId(), req.get_taskId(), children, send_task)
+            .map(|_| TaskResponse::default())
+            .map_err(|e| e.to_string());
+        reply(&ctx, sink, rs, req)
+    }
+
+    /// Record the status of a task, the instance finishes with its root or reduce task.
+    fn report_task_status(
+        &mut self,
+        ctx: RpcContext,
+        req: ReportTaskStatusRequest,
+        sink: UnarySink<TaskResponse>,
+    ) {
+        debug!(
+            "receive report task status request, instance id: {}, task id: {}.",
+            req.get_instanceId(),
+            req.get_taskId()
+        );
+        let rs = match InstanceStatus::try_from(req.get_status()) {
+            Ok(status) => block_on(self.work_mgr.report_task_status(
+                req.get_instanceId(),
+                req.get_taskId(),
+                status,
+                req.get_result(),
+                send_task,
+            ))
+            .map(|_| TaskResponse::default())
+            .map_err(|e| e.to_string()),
+            Err(_) => Err(format!("invalid task status {}", req.get_status())),
+        };
+        reply(&ctx, sink, rs, req)
+    }
+}
+
+/// A response carrying the code and message of the outcome of a request.
+trait Outcome {
+    fn set_outcome(&mut self, code: u64, msg: String);
+}
+
+macro_rules! impl_outcome {
+    ($($resp:ty),*) => {$(
+        impl Outcome for $resp {
+            fn set_outcome(&mut self, code: u64, msg: String) {
+                self.set_code(code);
+                self.set_message(msg);
+            }
+        }
+    )*};
+}
+
+impl_outcome!(AdminResponse, PreviewCronResponse, RunJobResponse, TaskResponse);
+
+/// Reply the outcome of a request, a failure with the `FAIL` code and its error.
+fn reply<R, P>(ctx: &RpcContext, sink: UnarySink<P>, rs: Result<P, String>, req: R)
+    where
+        R: Debug + Send + 'static,
+        P: Outcome + Default,
+{
+    let resp = match rs {
+        Ok(mut resp) => {
+            resp.set_outcome(SUCCESS, "success.".to_string());
+            resp
+        }
+        Err(msg) => {
+            warn!("request {:?} failed: {}", req, msg);
+            let mut resp = P::default();
+            resp.set_outcome(FAIL, msg);
+            resp
+        }
+    };
+    let f = sink
+        .success(resp)
+        .map_err(move |e| format!("failed to reply {:?}: {:?}", req, e))
+        .map(|_| ());
+    ctx.spawn(f)
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
@@ -26,14 +26,33 @@ CREATE TABLE `app_info`
 (
     `id`             bigint(20) NOT NULL AUTO_INCREMENT,
     `app_name`       varchar(255) DEFAULT NULL,
+    `cron_spread`    bigint(20) DEFAULT NULL,
     `current_server` varchar(255) DEFAULT NULL,
     `gmt_create`     datetime(6) DEFAULT CURRENT_TIMESTAMP (6),
     `gmt_modified`   datetime(6) DEFAULT CURRENT_TIMESTAMP (6) ON UPDATE CURRENT_TIMESTAMP (6),
     `password`       varchar(255) DEFAULT NULL,
+    `fencing_token`  bigint(20) NOT NULL DEFAULT 0,
     PRIMARY KEY (`id`),
     UNIQUE KEY `appNameUK` (`app_name`)
 ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
 
+-- ----------------------------
+-- Table structure for calendar_info
+-- ----------------------------
+DROP TABLE IF EXISTS `calendar_info`;
+CREATE TABLE `calendar_info`
+(
+    `id`            bigint(20) NOT NULL AUTO_INCREMENT,
+    `app_id`        bigint(20) DEFAULT NULL,
+    `calendar_name` varchar(255) DEFAULT NULL,
+    `gmt_create`    bigint(20) DEFAULT NULL,
+    `gmt_modified`  bigint(20) DEFAULT NULL,
+    `holidays`      text,
+    `working_days`  varchar(32) DEFAULT NULL,
+    PRIMARY KEY (`id`),
+    UNIQUE KEY `UK_calendar_info_app_name` (`app_id`, `calendar_name`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+
 -- ----------------------------
 -- Table structure for container_info
 -- ----------------------------
@@ -72,16 +91,21 @@ CREATE TABLE `instance_info`
     `job_id`                bigint(20) DEFAULT NULL,
     `job_params`            longtext,
     `last_report_time`      bigint(20) DEFAULT NULL,
+    `misfire_action`        int(11) DEFAULT NULL,
+    `request_key`           varchar(255) DEFAULT NULL,
     `result`                text,
     `running_times`         bigint(20) DEFAULT NULL,
     `status`                int(11) DEFAULT NULL,
     `task_tracker_address`  varchar(255) DEFAULT NULL,
+    `trigger_slot`          bigint(20) DEFAULT NULL,
     `instance_type`         int(11) DEFAULT NULL,
     `wf_instance_id`        bigint(20) DEFAULT NULL,
     PRIMARY KEY (`id`),
     KEY                     `IDX5b1nhpe5je7gc5s1ur200njr7` (`job_id`),
     KEY                     `IDXjnji5lrr195kswk6f7mfhinrs` (`app_id`),
-    KEY                     `IDXa98hq3yu0l863wuotdjl7noum` (`instance_id`)
+    KEY                     `IDXa98hq3yu0l863wuotdjl7noum` (`instance_id`),
+    UNIQUE KEY              `UK_instance_info_request_key` (`job_id`, `request_key`),
+    UNIQUE KEY              `UK_instance_info_trigger_slot` (`job_id`, `trigger_slot`)
 ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
 
 -- ----------------------------
@@ -92,6 +116,8 @@ CREATE TABLE `job_info`
 (
     `id`                   bigint(20) NOT NULL AUTO_INCREMENT,
     `app_id`               bigint(20) DEFAULT NULL,
+    `calendar_id`          bigint(20) DEFAULT NULL,
+    `calendar_policy`      int(11) DEFAULT NULL,
     `concurrency`          int(11) DEFAULT NULL,
     `designated_workers`   varchar(255) DEFAULT NULL,
     `dispatch_strategy`    int(11) DEFAULT NULL,
@@ -104,20 +130,25 @@ CREATE TABLE `job_info`
     `job_description`      varchar(255) DEFAULT NULL,
     `job_name`             varchar(255) DEFAULT NULL,
     `job_params`           text,
+    `jitter`               bigint(20) DEFAULT NULL,
     `lifecycle`            varchar(255) DEFAULT NULL,
+    `max_catch_up`         int(11) DEFAULT NULL,
     `max_instance_num`     int(11) unsigned DEFAULT NULL,
     `max_worker_count`     int(11) DEFAULT NULL,
     `min_cpu_cores`        double NOT NULL,
     `min_disk_space`       double NOT NULL,
     `min_memory_space`     double NOT NULL,
+    `misfire_policy`       int(11) DEFAULT NULL,
     `next_trigger_time`    bigint(20) DEFAULT NULL,
     `notify_user_ids`      varchar(255) DEFAULT NULL,
     `processor_info`       varchar(255) DEFAULT NULL,
     `processor_type`       int(11) DEFAULT NULL,
     `status`               int(11) DEFAULT NULL,
+    `success_ratio`        double DEFAULT NULL,
     `task_retry_num`       int(11) DEFAULT NULL,
     `time_expression`      varchar(255) DEFAULT NULL,
     `time_expression_type` int(11) DEFAULT NULL,
+    `time_zone`            varchar(64) DEFAULT NULL,
     PRIMARY KEY (`id`),
     KEY                    `IDXk2xprmn3lldmlcb52i36udll1` (`app_id`)
 ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
@@ -129,11 +160,12 @@ DROP TABLE IF EXISTS `lock`;
 CREATE TABLE `lock`
 (
     `id`            bigint(20) NOT NULL AUTO_INCREMENT,
-    `gmt_create`    datetime(6) DEFAULT CURRENT_TIMESTAMP (6),
-    `gmt_modified`  datetime(6) DEFAULT CURRENT_TIMESTAMP (6) ON UPDATE CURRENT_TIMESTAMP (6),
+    `gmt_create`    bigint(20) DEFAULT NULL,
+    `gmt_modified`  bigint(20) DEFAULT NULL,
     `lock_name`     varchar(255)                                                  DEFAULT NULL,
     `max_lock_time` bigint(20) DEFAULT NULL,
     `owner_ip`      varchar(255) CHARACTER SET utf8mb4 COLLATE utf8mb4_0900_ai_ci DEFAULT NULL,
+    `fencing_token` bigint(20) NOT NULL DEFAULT 0,
     PRIMARY KEY (`id`),
     UNIQUE KEY `lockNameUK` (`lock_name`)
 ) ENGINE=InnoDB AUTO_INCREMENT=2 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
@@ -148,10 +180,37 @@ CREATE TABLE `server_info`
     `gmt_create`   datetime(6) DEFAULT CURRENT_TIMESTAMP (6),
     `gmt_modified` datetime(6) DEFAULT CURRENT_TIMESTAMP (6) ON UPDATE CURRENT_TIMESTAMP (6),
     `ip`           varchar(255) DEFAULT NULL,
+    `last_heartbeat` bigint(20) DEFAULT NULL,
     PRIMARY KEY (`id`),
-    UNIQUE KEY `UKtk8ytgpl7mpukhnvhbl82kgvy` (`ip`)
+    UNIQUE KEY `UKtk8ytgpl7mpukhnvhbl82kgvy` (`ip`),
+    KEY            `IDX_server_info_last_heartbeat` (`last_heartbeat`)
 ) ENGINE=InnoDB AUTO_INCREMENT=2 DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
 
+-- ----------------------------
+-- Table structure for task_info
+-- ----------------------------
+DROP TABLE IF EXISTS `task_info`;
+CREATE TABLE `task_info`
+(
+    `id`             bigint(20) NOT NULL AUTO_INCREMENT,
+    `app_id`         bigint(20) DEFAULT NULL,
+    `job_id`         bigint(20) DEFAULT NULL,
+    `instance_id`    bigint(20) DEFAULT NULL,
+    `task_id`        varchar(255) DEFAULT NULL,
+    `task_name`      varchar(255) DEFAULT NULL,
+    `task_type`      int(11) DEFAULT NULL,
+    `task_params`    longtext,
+    `worker_address` varchar(255) DEFAULT NULL,
+    `status`         int(11) DEFAULT NULL,
+    `result`         text,
+    `retry_times`    int(11) NOT NULL DEFAULT 0,
+    `gmt_create`     bigint(20) DEFAULT NULL,
+    `gmt_modified`   bigint(20) DEFAULT NULL,
+    PRIMARY KEY (`id`),
+    UNIQUE KEY `UK_task_info_instance_task` (`instance_id`, `task_id`),
+    KEY              `IDX_task_info_instance_status` (`instance_id`, `status`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
+
 -- ----------------------------
 -- Table structure for user_info
 -- ----------------------------
@@ -215,7 +274,9 @@ CREATE TABLE `workflow_instance_info`
     `wf_init_params`        text,
     `wf_instance_id`        bigint(20) DEFAULT NULL,
     `workflow_id`           bigint(20) DEFAULT NULL,
-    PRIMARY KEY (`id`)
+    PRIMARY KEY (`id`),
+    UNIQUE KEY              `UK_workflow_instance_info_wf_instance` (`wf_instance_id`),
+    KEY                     `IDX_workflow_instance_info_app_status` (`app_id`, `status`)
 ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_0900_ai_ci;
 
 -- ----------------------------